@@ -1,7 +1,76 @@
+// The instruction handlers, and the CPI helpers Anchor generates from them,
+// take the client facing arguments one to one.
+#![allow(clippy::too_many_arguments)]
+
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
 
 declare_id!("38P5X5bZni6nyT6yg329HyVeGKJr57U9cFPmYPoYdDo5");
 
+/// The maximum length of a merchant name in bytes (not chars).
+pub const MAX_MERCHANT_NAME_LEN: usize = 64;
+/// The maximum length of a note in bytes, bounds the size of the expense account.
+pub const MAX_NOTE_LEN: usize = 128;
+/// The maximum length of a deletion reason in bytes.
+pub const MAX_DELETE_REASON_LEN: usize = 64;
+/// The maximum number of expenses handled by a single batch instruction,
+/// keeps the transaction within the size and compute limits.
+pub const MAX_BATCH_SIZE: usize = 8;
+/// The maximum number of participants of a split expense.
+pub const MAX_SPLIT_PARTICIPANTS: usize = 16;
+/// The number of shares a single `SplitSharesRecorded` event carries. The shares of a
+/// split are emitted in chunks of this size, keeping every log message of a large split
+/// well within the log limit.
+pub const SPLIT_EVENT_CHUNK_SIZE: usize = 4;
+/// The current layout version of the expense account, older accounts are
+/// brought up to date by `migrate_expense`.
+pub const EXPENSE_ACCOUNT_VERSION: u8 = 22;
+/// The default maximum amount of a single expense, catches fat-finger entries.
+/// Users can pick their own cap with `Budget::max_amount`.
+pub const MAX_EXPENSE_AMOUNT: u64 = 1_000_000_000_000_000;
+/// The maximum number of expenses a user can have, bounds the on-chain footprint per user.
+pub const MAX_EXPENSES_PER_USER: u64 = 1000;
+/// The maximum number of members of a group, including its creator.
+pub const MAX_GROUP_MEMBERS: usize = 16;
+/// The maximum number of tags of an expense.
+pub const MAX_TAGS: usize = 8;
+/// The maximum length of a tag in bytes.
+pub const MAX_TAG_LEN: usize = 16;
+/// The maximum length of a receipt content identifier in bytes, fits a CIDv1 in base32.
+pub const MAX_RECEIPT_CID_LEN: usize = 64;
+/// The maximum number of approvals a large expense can require.
+pub const MAX_REQUIRED_APPROVALS: u8 = 8;
+/// The maximum number of keys the config designates as approvers.
+pub const MAX_APPROVERS: usize = 16;
+/// The maximum number of line items of an expense.
+pub const MAX_LINE_ITEMS: usize = 16;
+/// The maximum length of a line item description in bytes.
+pub const MAX_LINE_ITEM_DESCRIPTION_LEN: usize = 32;
+/// The maximum number of metadata entries of an expense.
+pub const MAX_METADATA_ENTRIES: usize = 8;
+/// The maximum length of a metadata key in bytes.
+pub const MAX_METADATA_KEY_LEN: usize = 16;
+/// The maximum length of a metadata value in bytes.
+pub const MAX_METADATA_VALUE_LEN: usize = 64;
+/// The maximum number of viewers of an expense.
+pub const MAX_VIEWERS: usize = 8;
+/// The maximum length of an encrypted note in bytes, including the authentication tag.
+pub const MAX_ENCRYPTED_NOTE_LEN: usize = 256;
+/// How far ahead of the cluster clock a client supplied timestamp may be, 10 years of
+/// 365 days. Keeps bogus far future dates out of the date keyed features.
+pub const MAX_FUTURE_SECONDS: i64 = 10 * 365 * 24 * 60 * 60;
+/// The fixed-point scale of `ExpenseAccount::fx_rate`, a rate of `FX_RATE_SCALE` is 1.0.
+pub const FX_RATE_SCALE: u64 = 1_000_000;
+/// The initial `Config::max_oracle_age`, in seconds.
+pub const DEFAULT_MAX_ORACLE_AGE: u32 = 60;
+/// The largest tax rate in basis points, 100%.
+pub const MAX_TAX_RATE_BPS: u16 = 10_000;
+/// A self-imposed cap on the size of the accounts the program grows, in bytes, below
+/// what every optional field of an expense at its limit adds up to.
+pub const MAX_ACCOUNT_SIZE: usize = 1536;
+
 #[program]
 pub mod expense_tracker {
     use super::*;
@@ -11,19 +80,330 @@ pub mod expense_tracker {
         id: u64,
         merchant_name: String,
         amount: u64,
+        category: ExpenseCategory,
+        currency: [u8; 3],
+        note: String,
+        receipt_hash: [u8; 32],
+        payment_method: PaymentMethod,
+        due_date: i64,
+        fx_rate: u64,
+        client_ref: Option<[u8; 16]>,
     ) -> Result<()> {
-        msg!(
-            "Initialize Expense id: {id} for merchant_name: {merchant_name} with amount: {amount} \
-            in program id: {:?} b",
-            ctx.program_id
+        validate_expense_input(
+            &merchant_name,
+            amount,
+            max_expense_amount(ctx.accounts.budget.as_deref()),
+        )?;
+        // ISO 4217 style code, e.g. `USD`
+        require!(
+            currency.iter().all(u8::is_ascii_uppercase),
+            ExpenseError::InvalidCurrency
+        );
+        require!(note.len() <= MAX_NOTE_LEN, ExpenseError::NoteTooLong);
+        require!(fx_rate > 0, ExpenseError::InvalidFxRate);
+        let now = Clock::get()?.unix_timestamp;
+        // Zero means the expense is not a bill
+        require!(
+            due_date == 0 || due_date >= now,
+            ExpenseError::DueDateInPast
+        );
+        require!(
+            due_date - now <= MAX_FUTURE_SECONDS,
+            ExpenseError::TimestampOutOfRange
         );
 
+        if ctx.accounts.config.log_level == LogLevel::Info {
+            msg!(
+                "Initialize Expense id: {id} for merchant_name: {merchant_name} with amount: {amount} \
+                in program id: {:?} b",
+                ctx.program_id
+            );
+        }
+
+        if ctx.accounts.config.duplicate_guard {
+            let duplicate_guard = ctx
+                .accounts
+                .duplicate_guard
+                .as_mut()
+                .ok_or(ExpenseError::DuplicateGuardMissing)?;
+            // A guard created by an earlier identical expense of the day is in use already
+            require_keys_eq!(
+                duplicate_guard.expense,
+                Pubkey::default(),
+                ExpenseError::DuplicateExpense
+            );
+            duplicate_guard.expense = ctx.accounts.expense_account.key();
+        }
+        if client_ref.is_some() {
+            let submission_guard = ctx
+                .accounts
+                .submission_guard
+                .as_mut()
+                .ok_or(ExpenseError::SubmissionGuardMissing)?;
+            // A guard taken by an earlier submission with the same reference, e.g. a retry
+            // of a transaction that did land
+            require_keys_eq!(
+                submission_guard.expense,
+                Pubkey::default(),
+                ExpenseError::DuplicateSubmission
+            );
+            submission_guard.expense = ctx.accounts.expense_account.key();
+        }
+
+        let expense_account = &mut ctx.accounts.expense_account;
+
+        expense_account.id = id;
+        expense_account.client_ref = client_ref.unwrap_or_default();
+        expense_account.merchant_name = merchant_name;
+        expense_account.fx_rate = fx_rate;
+        expense_account.set_amount(amount)?;
+        expense_account.category = category;
+        expense_account.currency = currency;
+        expense_account.note = note;
+        expense_account.receipt_hash = receipt_hash;
+        expense_account.payment_method = payment_method;
+        expense_account.due_date = due_date;
+        expense_account.merchant = ctx
+            .accounts
+            .merchant
+            .as_ref()
+            .map(|merchant| merchant.key());
+        expense_account.owner = *ctx.accounts.authority.key;
+        expense_account.creator = expense_account.owner;
+        expense_account.created_at = now;
+        expense_account.updated_at = expense_account.created_at;
+        expense_account.version = EXPENSE_ACCOUNT_VERSION;
+        expense_account.bump = ctx.bumps.expense_account;
+
+        record_new_expense(
+            expense_account,
+            ctx.accounts.budget.as_deref_mut(),
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.category_index,
+            &mut ctx.accounts.rollup,
+        )?;
+
+        emit!(ExpenseCreated {
+            id,
+            owner: expense_account.owner,
+            amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Runs the checks of `initialize_expense` for an expense in the base currency without
+    /// creating it, and emits the outcome as a `ValidationResult`, so clients can surface
+    /// errors before paying rent.
+    pub fn validate_expense(
+        ctx: Context<ValidateExpense>,
+        id: u64,
+        merchant_name: String,
+        amount: u64,
+        category: ExpenseCategory,
+    ) -> Result<()> {
+        let result = check_new_expense(ctx.accounts, &merchant_name, amount, category);
+
+        emit!(ValidationResult {
+            id,
+            ok: result.is_ok(),
+            error_code: result.err().map_or(0, |error| error_code(&error)),
+        });
+
+        Ok(())
+    }
+
+    /// Records a transfer between own accounts, an expense flagged `is_transfer` that the
+    /// totals of the user stats and the budget leave out.
+    pub fn initialize_transfer(
+        ctx: Context<InitializeTransfer>,
+        id: u64,
+        merchant_name: String,
+        amount: u64,
+    ) -> Result<()> {
+        validate_expense_input(&merchant_name, amount, MAX_EXPENSE_AMOUNT)?;
+
+        let expense_account = &mut ctx.accounts.expense_account;
+
+        expense_account.id = id;
+        expense_account.merchant_name = merchant_name;
+        expense_account.fx_rate = FX_RATE_SCALE;
+        expense_account.is_transfer = true;
+        expense_account.set_amount(amount)?;
+        expense_account.owner = *ctx.accounts.authority.key;
+        expense_account.creator = expense_account.owner;
+        expense_account.created_at = Clock::get()?.unix_timestamp;
+        expense_account.updated_at = expense_account.created_at;
+        expense_account.version = EXPENSE_ACCOUNT_VERSION;
+        expense_account.bump = ctx.bumps.expense_account;
+
+        // Transfers have no budget, their spending isn't limited by it
+        record_new_expense(
+            expense_account,
+            None,
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.category_index,
+            &mut ctx.accounts.rollup,
+        )?;
+
+        emit!(ExpenseCreated {
+            id,
+            owner: expense_account.owner,
+            amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Creates an expense with the next sequential ID of the user, see
+    /// `UserStats::next_id`, so clients don't have to pick IDs themselves. Without a
+    /// category the expense is filed under the default category of the user.
+    pub fn initialize_expense_auto(
+        ctx: Context<InitializeExpenseAuto>,
+        merchant_name: String,
+        amount: u64,
+        category: Option<ExpenseCategory>,
+    ) -> Result<()> {
+        validate_expense_input(
+            &merchant_name,
+            amount,
+            max_expense_amount(ctx.accounts.budget.as_deref()),
+        )?;
+
+        let user_stats = &mut ctx.accounts.user_stats;
+        let id = user_stats.next_id;
+        user_stats.next_id = checked_add(id, 1)?;
+
+        let expense_account = &mut ctx.accounts.expense_account;
+
+        expense_account.id = id;
+        expense_account.merchant_name = merchant_name;
+        expense_account.fx_rate = FX_RATE_SCALE;
+        expense_account.set_amount(amount)?;
+        expense_account.category = category.unwrap_or(user_stats.default_category);
+        expense_account.owner = *ctx.accounts.authority.key;
+        expense_account.creator = expense_account.owner;
+        expense_account.created_at = Clock::get()?.unix_timestamp;
+        expense_account.updated_at = expense_account.created_at;
+        expense_account.version = EXPENSE_ACCOUNT_VERSION;
+        expense_account.bump = ctx.bumps.expense_account;
+
+        record_new_expense(
+            expense_account,
+            ctx.accounts.budget.as_deref_mut(),
+            user_stats,
+            &mut ctx.accounts.category_index,
+            &mut ctx.accounts.rollup,
+        )?;
+
+        emit!(ExpenseCreated {
+            id,
+            owner: expense_account.owner,
+            amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Creates an expense in the namespace of `tenant`, so several organizations can share
+    /// the program without their IDs colliding. Its PDA includes the tenant, see
+    /// `InitializeExpenseTenant`, and the tenant is stored to derive it again, so the
+    /// instructions addressing an existing expense by its ID reach tenant expenses too.
+    pub fn initialize_expense_tenant(
+        ctx: Context<InitializeExpenseTenant>,
+        tenant: [u8; 8],
+        id: u64,
+        merchant_name: String,
+        amount: u64,
+    ) -> Result<()> {
+        // Zeros stand for no tenant
+        require!(tenant != [0; 8], ExpenseError::InvalidTenant);
+        validate_expense_input(
+            &merchant_name,
+            amount,
+            max_expense_amount(ctx.accounts.budget.as_deref()),
+        )?;
+
+        let expense_account = &mut ctx.accounts.expense_account;
+
+        expense_account.id = id;
+        expense_account.tenant = tenant;
+        expense_account.merchant_name = merchant_name;
+        expense_account.fx_rate = FX_RATE_SCALE;
+        expense_account.set_amount(amount)?;
+        expense_account.owner = *ctx.accounts.authority.key;
+        expense_account.creator = expense_account.owner;
+        expense_account.created_at = Clock::get()?.unix_timestamp;
+        expense_account.updated_at = expense_account.created_at;
+        expense_account.version = EXPENSE_ACCOUNT_VERSION;
+        expense_account.bump = ctx.bumps.expense_account;
+
+        record_new_expense(
+            expense_account,
+            ctx.accounts.budget.as_deref_mut(),
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.category_index,
+            &mut ctx.accounts.rollup,
+        )?;
+
+        emit!(ExpenseCreated {
+            id,
+            owner: expense_account.owner,
+            amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Creates an expense whose ID is scoped to its category, so e.g. Food #1 and
+    /// Transport #1 coexist. Its PDA includes the category, see `InitializeExpenseScoped`,
+    /// which is stored as the scope to derive it again, also once the category changed.
+    pub fn initialize_expense_scoped(
+        ctx: Context<InitializeExpenseScoped>,
+        id: u64,
+        merchant_name: String,
+        amount: u64,
+        category: ExpenseCategory,
+    ) -> Result<()> {
+        validate_expense_input(
+            &merchant_name,
+            amount,
+            max_expense_amount(ctx.accounts.budget.as_deref()),
+        )?;
+
         let expense_account = &mut ctx.accounts.expense_account;
 
         expense_account.id = id;
         expense_account.merchant_name = merchant_name;
-        expense_account.amount = amount;
+        expense_account.fx_rate = FX_RATE_SCALE;
+        expense_account.set_amount(amount)?;
+        expense_account.category = category;
+        expense_account.scope = Some(category as u8);
         expense_account.owner = *ctx.accounts.authority.key;
+        expense_account.creator = expense_account.owner;
+        expense_account.created_at = Clock::get()?.unix_timestamp;
+        expense_account.updated_at = expense_account.created_at;
+        expense_account.version = EXPENSE_ACCOUNT_VERSION;
+        expense_account.bump = ctx.bumps.expense_account;
+
+        record_new_expense(
+            expense_account,
+            ctx.accounts.budget.as_deref_mut(),
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.category_index,
+            &mut ctx.accounts.rollup,
+        )?;
+
+        emit!(ExpenseCreated {
+            id,
+            owner: expense_account.owner,
+            amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
 
         Ok(())
     }
@@ -33,101 +413,6065 @@ pub mod expense_tracker {
         id: u64,
         merchant_name: String,
         amount: u64,
+        category: ExpenseCategory,
+        payment_method: PaymentMethod,
     ) -> Result<()> {
-        // This is a "defense-in-depth" check.
-        // The primary validation that the correct expense account is being modified
-        // is handled by Anchor's `seeds` constraint on the `ModifyExpense` context.
-        // If a client were to pass a mismatched `id`, Anchor would fail the transaction
-        // with a `ConstraintSeeds` error before this instruction logic is ever executed.
-        // However, including this check provides an explicit, internal safeguard
-        // to ensure data integrity within the instruction itself.
-        require_eq!(
-            ctx.accounts.expense_account.id,
+        let accounts = ctx.accounts;
+        modify_expense_account(
+            &mut accounts.expense_account,
+            &mut accounts.user_stats,
+            accounts.category_index.as_deref_mut(),
+            &mut accounts.new_category_index,
+            accounts.budget.as_deref(),
+            &accounts.config,
+            &accounts.authority,
+            &accounts.system_program,
             id,
-            ExpenseError::IdMismatch
+            merchant_name,
+            amount,
+            category,
+            payment_method,
+        )
+    }
+
+    /// `modify_expense` with the canonical bump of the expense passed by the client, so
+    /// the seeds are checked with it instead of the one read from the account.
+    pub fn modify_expense_with_bump(
+        ctx: Context<ModifyExpenseWithBump>,
+        id: u64,
+        merchant_name: String,
+        amount: u64,
+        category: ExpenseCategory,
+        payment_method: PaymentMethod,
+        _bump: u8,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        modify_expense_account(
+            &mut accounts.expense_account,
+            &mut accounts.user_stats,
+            accounts.category_index.as_deref_mut(),
+            &mut accounts.new_category_index,
+            accounts.budget.as_deref(),
+            &accounts.config,
+            &accounts.authority,
+            &accounts.system_program,
+            id,
+            merchant_name,
+            amount,
+            category,
+            payment_method,
+        )
+    }
+
+    /// Changes only the amount of the expense, see `modify_expense`.
+    pub fn update_amount(ctx: Context<UpdateAmount>, id: u64, amount: u64) -> Result<()> {
+        validate_amount(amount, max_expense_amount(ctx.accounts.budget.as_deref()))?;
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        let old_amount_in_base = expense_account.amount_in_base;
+        expense_account.set_amount(amount)?;
+        if !expense_account.is_archived {
+            ctx.accounts
+                .user_stats
+                .replace_amount(old_amount_in_base, expense_account.amount_in_base)?;
+        }
+        update_category_index(
+            expense_account,
+            ctx.accounts.category_index.as_deref_mut(),
+            old_amount_in_base,
+        )?;
+
+        expense_account.record_modification(Clock::get()?.unix_timestamp)?;
+
+        emit!(ExpenseModified {
+            id,
+            amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Sets the tax rate of the expense in basis points, e.g. 825 for 8.25%, and the tax
+    /// amount derived from it.
+    pub fn set_tax_rate(ctx: Context<SetTaxRate>, id: u64, tax_rate_bps: u16) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        expense_account.tax_amount = tax_amount(expense_account.net_amount, tax_rate_bps)?;
+        expense_account.tax_rate_bps = tax_rate_bps;
+        expense_account.record_modification(Clock::get()?.unix_timestamp)?;
+
+        Ok(())
+    }
+
+    /// Sets the exchange rate of the expense from the price in the oracle account, see
+    /// `OraclePrice`, and converts the amount to the base currency again. The oracle must
+    /// be the feed the admin set for the currency of the expense, see `set_fx_feed`.
+    pub fn pin_fx_rate(ctx: Context<PinFxRate>, id: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(
+            config.oracle_program != Pubkey::default(),
+            ExpenseError::OracleNotConfigured
         );
+        let price = OraclePrice::deserialize(&mut &ctx.accounts.oracle.try_borrow_data()?[..])
+            .map_err(|_| ExpenseError::InvalidOraclePrice)?;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now.saturating_sub(price.publish_time) <= i64::from(config.max_oracle_age),
+            ExpenseError::OracleStale
+        );
+        let fx_rate = price.fx_rate()?;
+
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        let old_amount_in_base = expense_account.amount_in_base;
+        let amount = expense_account.amount;
+        expense_account.fx_rate = fx_rate;
+        expense_account.set_amount(amount)?;
+        if !expense_account.is_archived {
+            ctx.accounts
+                .user_stats
+                .replace_amount(old_amount_in_base, expense_account.amount_in_base)?;
+        }
+        update_category_index(
+            expense_account,
+            ctx.accounts.category_index.as_deref_mut(),
+            old_amount_in_base,
+        )?;
+
+        expense_account.record_modification(now)?;
+
+        Ok(())
+    }
 
+    /// Changes only the merchant name of the expense, see `modify_expense`.
+    pub fn rename_merchant(
+        ctx: Context<RenameMerchant>,
+        id: u64,
+        merchant_name: String,
+    ) -> Result<()> {
+        validate_merchant_name(&merchant_name)?;
         let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
         expense_account.merchant_name = merchant_name;
-        expense_account.amount = amount;
+        expense_account.record_modification(Clock::get()?.unix_timestamp)?;
+
+        emit!(ExpenseModified {
+            id,
+            amount: expense_account.amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
 
         Ok(())
     }
 
-    pub fn delete_expense(_ctx: Context<DeleteExpense>, _id: u64) -> Result<()> {
+    /// Changes only the category of the expense, moving it between the category indices,
+    /// see `modify_expense`.
+    pub fn recategorize(
+        ctx: Context<Recategorize>,
+        id: u64,
+        category: ExpenseCategory,
+    ) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        let same_category = expense_account.category == category;
+        let amount_in_base = expense_account.amount_in_base;
+        move_category_index(
+            expense_account,
+            ctx.accounts.category_index.as_deref_mut(),
+            &mut ctx.accounts.new_category_index,
+            same_category,
+            amount_in_base,
+        )?;
+        ctx.accounts.new_category_index.category = category;
+
+        expense_account.category = category;
+        expense_account.record_modification(Clock::get()?.unix_timestamp)?;
+
+        emit!(ExpenseModified {
+            id,
+            amount: expense_account.amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
+
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-#[instruction(id : u64)]
-pub struct InitializeExpense<'info> {
-    // represents the public key of the user who is calling this instruction
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    /// Moves the expense to the PDA of `new_id`, copying every field, and closes the old
+    /// account. Fails when there already is an expense with `new_id`.
+    pub fn rekey_expense(ctx: Context<RekeyExpense>, old_id: u64, new_id: u64) -> Result<()> {
+        require_eq!(
+            ctx.accounts.expense_account.id,
+            old_id,
+            ExpenseError::IdMismatch
+        );
 
-    // This is the new account that will be created and hold the expense data
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + 8 + 32+ (4 + 12)+ 8 + 1,
-        seeds = [b"expense", authority.key().as_ref(), id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub expense_account: Account<'info, ExpenseAccount>,
+        let mut expense_account = (*ctx.accounts.expense_account).clone();
+        expense_account.id = new_id;
+        expense_account.creator = ctx.accounts.authority.key();
+        expense_account.bump = ctx.bumps.new_expense_account;
+        ctx.accounts.new_expense_account.set_inner(expense_account);
 
-    pub system_program: Program<'info, System>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(id : u64)]
-pub struct ModifyExpense<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    pub fn delete_expense(ctx: Context<DeleteExpense>, id: u64, reason: String) -> Result<()> {
+        require!(!reason.is_empty(), ExpenseError::DeleteReasonEmpty);
+        require!(
+            reason.len() <= MAX_DELETE_REASON_LEN,
+            ExpenseError::DeleteReasonTooLong
+        );
+        ctx.accounts
+            .config
+            .check_deletable(&ctx.accounts.expense_account)?;
 
-    #[account(
-        mut,
-        seeds = [b"expense", authority.key().as_ref(), id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub expense_account: Account<'info, ExpenseAccount>,
+        if !ctx.accounts.expense_account.is_archived {
+            ctx.accounts
+                .user_stats
+                .remove_expense(ctx.accounts.expense_account.amount_in_base)?;
+        }
+        ctx.accounts.user_stats.remove_account()?;
+        remove_from_category_index(
+            &ctx.accounts.expense_account,
+            ctx.accounts.category_index.as_deref_mut(),
+        )?;
 
-    pub system_program: Program<'info, System>,
-}
+        // Emitted here, the account itself is closed by Anchor once the instruction returns.
+        emit!(ExpenseDeleted {
+            id,
+            owner: ctx.accounts.expense_account.owner,
+            reason,
+        });
 
-#[derive(Accounts)]
-#[instruction(id : u64)]
-pub struct DeleteExpense<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        close = authority,
-        seeds = [b"expense", authority.key().as_ref(), id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub expense_account: Account<'info, ExpenseAccount>,
+    /// Merges the expense `remove_id` into `keep_id`: the kept expense gets the sum of both
+    /// amounts and a note of the merge, the removed one is closed, returning its rent to
+    /// the authority. Both expenses must be owned by the authority.
+    pub fn merge_expenses(ctx: Context<MergeExpenses>, keep_id: u64, remove_id: u64) -> Result<()> {
+        let removed_expense = &ctx.accounts.removed_expense;
+        require_eq!(removed_expense.id, remove_id, ExpenseError::IdMismatch);
+        ctx.accounts.config.check_deletable(removed_expense)?;
+        let kept_expense = &mut ctx.accounts.kept_expense;
+        require_eq!(kept_expense.id, keep_id, ExpenseError::IdMismatch);
+        require!(
+            kept_expense.currency == removed_expense.currency,
+            ExpenseError::CurrencyMismatch
+        );
 
-    pub system_program: Program<'info, System>,
-}
+        let merge_note = format!("Merged expense {remove_id}");
+        let note = if kept_expense.note.is_empty() {
+            merge_note
+        } else {
+            format!("{}; {merge_note}", kept_expense.note)
+        };
+        require!(note.len() <= MAX_NOTE_LEN, ExpenseError::NoteTooLong);
+        let amount = checked_add(kept_expense.amount, removed_expense.amount)?;
+        validate_amount(amount, MAX_EXPENSE_AMOUNT)?;
 
-/// An on-chain account.
-#[account]
-#[derive(Default)]
-pub struct ExpenseAccount {
-    // Expense entries unique ID
-    pub id: u64,
-    // The owner value
-    pub owner: Pubkey,
-    // The merchant name
-    pub merchant_name: String,
-    // The spent amount
-    pub amount: u64,
-}
+        let old_amount_in_base = kept_expense.amount_in_base;
+        // The refunds of the merged expense are the sum of both as well, so its net amount is
+        kept_expense.total_refunded =
+            checked_add(kept_expense.total_refunded, removed_expense.total_refunded)?;
+        kept_expense.set_amount(amount)?;
+        kept_expense.note = note;
+        kept_expense.record_modification(Clock::get()?.unix_timestamp)?;
 
-#[error_code]
-pub enum ExpenseError {
-    #[msg("The provided ID does not match the one in the account.")]
-    IdMismatch,
+        let user_stats = &mut ctx.accounts.user_stats;
+        if !kept_expense.is_archived {
+            user_stats.replace_amount(old_amount_in_base, kept_expense.amount_in_base)?;
+        }
+        if !removed_expense.is_archived {
+            user_stats.remove_expense(removed_expense.amount_in_base)?;
+        }
+        user_stats.remove_account()?;
+        remove_from_category_index(
+            removed_expense,
+            ctx.accounts.removed_category_index.as_deref_mut(),
+        )?;
+        // With the same category both indices are the same account, which is written back
+        // once per field in declaration order, so both changes go to the later copy
+        let kept_category_index = if kept_expense.category == removed_expense.category {
+            ctx.accounts.removed_category_index.as_deref_mut()
+        } else {
+            ctx.accounts.kept_category_index.as_deref_mut()
+        };
+        update_category_index(kept_expense, kept_category_index, old_amount_in_base)?;
+
+        emit!(ExpenseModified {
+            id: keep_id,
+            amount,
+            merchant_name: kept_expense.merchant_name.clone(),
+        });
+        // The removed account is closed by Anchor once the instruction returns
+        emit!(ExpenseDeleted {
+            id: remove_id,
+            owner: removed_expense.owner,
+            reason: format!("Merged into expense {keep_id}"),
+        });
+
+        // Grown here like in `modify_expense`, for the longer note
+        let space = kept_expense.size();
+        resize_account(
+            &ctx.accounts.kept_expense.to_account_info(),
+            space,
+            &ctx.accounts.authority,
+            &ctx.accounts.system_program,
+        )?;
+
+        Ok(())
+    }
+
+    /// Deletes several expenses in one instruction, returning their rent to the authority.
+    /// The expense PDAs are passed as `remaining_accounts`, in the same order as `ids`,
+    /// followed by the category indices of the authority the expenses are counted in.
+    pub fn delete_expenses_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DeleteExpensesBatch<'info>>,
+        ids: Vec<u64>,
+    ) -> Result<()> {
+        require!(!ids.is_empty(), ExpenseError::BatchEmpty);
+        require!(ids.len() <= MAX_BATCH_SIZE, ExpenseError::BatchTooLarge);
+        require!(
+            ctx.remaining_accounts.len() >= ids.len(),
+            ExpenseError::BatchAccountsMismatch
+        );
+
+        let authority = ctx.accounts.authority.key();
+        let (expense_infos, category_index_infos) = ctx.remaining_accounts.split_at(ids.len());
+        let mut category_indices =
+            load_category_indices(category_index_infos, &authority, ctx.program_id)?;
+
+        for (id, account_info) in ids.into_iter().zip(expense_infos) {
+            // Checks the account is owned by the program, so it's closed only once
+            let expense_account = Account::<ExpenseAccount>::try_from(account_info)?;
+            // Derived like in `DeleteExpense`
+            let (pda, _) = expense_account.find_pda(id, ctx.program_id);
+            require_keys_eq!(account_info.key(), pda, ExpenseError::InvalidExpenseAccount);
+            require_keys_eq!(expense_account.owner, authority, ExpenseError::Unauthorized);
+            require!(!expense_account.is_locked, ExpenseError::ExpenseLocked);
+            ctx.accounts.config.check_deletable(&expense_account)?;
+
+            if !expense_account.is_archived {
+                ctx.accounts
+                    .user_stats
+                    .remove_expense(expense_account.amount_in_base)?;
+            }
+            ctx.accounts.user_stats.remove_account()?;
+            remove_from_category_index(
+                &expense_account,
+                batch_category_index(&mut category_indices, expense_account.category),
+            )?;
+
+            emit!(ExpenseDeleted {
+                id,
+                owner: authority,
+                reason: String::from("Batch deletion"),
+            });
+
+            expense_account.close(ctx.accounts.authority.to_account_info())?;
+        }
+
+        for category_index in &category_indices {
+            category_index.exit(ctx.program_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies several updates, with the expense PDAs passed as `remaining_accounts` in the
+    /// same order as `updates` and followed by the category indices the expenses are
+    /// counted in, like in `delete_expenses_batch`. A failing update is skipped instead of
+    /// reverting the others, every update is reported by a `ModifyResult`.
+    pub fn modify_expenses_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ModifyExpensesBatch<'info>>,
+        updates: Vec<ExpenseUpdate>,
+    ) -> Result<()> {
+        require!(!updates.is_empty(), ExpenseError::BatchEmpty);
+        require!(updates.len() <= MAX_BATCH_SIZE, ExpenseError::BatchTooLarge);
+        require!(
+            ctx.remaining_accounts.len() >= updates.len(),
+            ExpenseError::BatchAccountsMismatch
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let max_amount = max_expense_amount(ctx.accounts.budget.as_deref());
+        let (expense_infos, category_index_infos) = ctx.remaining_accounts.split_at(updates.len());
+        let mut category_indices = load_category_indices(
+            category_index_infos,
+            ctx.accounts.authority.key,
+            ctx.program_id,
+        )?;
+
+        for (update, account_info) in updates.into_iter().zip(expense_infos) {
+            let id = update.id;
+            let result = apply_expense_update(
+                ctx.program_id,
+                &ctx.accounts.authority,
+                &mut ctx.accounts.user_stats,
+                &mut category_indices,
+                &ctx.accounts.system_program,
+                account_info,
+                update,
+                max_amount,
+                now,
+            );
+
+            emit!(ModifyResult {
+                id,
+                success: result.is_ok(),
+                error_code: result.err().map_or(0, |error| error_code(&error)),
+            });
+        }
+
+        for category_index in &category_indices {
+            category_index.exit(ctx.program_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sums up the expenses passed as remaining accounts that were created within
+    /// `[start, end]` and emits the result as a `RangeTotal`. The amounts are in the base
+    /// currency and archived expenses are skipped, like in the user stats.
+    pub fn sum_range<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SumRange<'info>>,
+        start: i64,
+        end: i64,
+    ) -> Result<()> {
+        require!(start <= end, ExpenseError::InvalidDateRange);
+
+        let authority = ctx.accounts.authority.key();
+        let mut total = 0;
+        let mut count = 0;
+        for (index, account_info) in ctx.remaining_accounts.iter().enumerate() {
+            // An expense passed twice would be counted twice
+            require!(
+                ctx.remaining_accounts[..index]
+                    .iter()
+                    .all(|other| other.key() != account_info.key()),
+                ExpenseError::InvalidExpenseAccount
+            );
+            let expense_account = Account::<ExpenseAccount>::try_from(account_info)?;
+            require_keys_eq!(expense_account.owner, authority, ExpenseError::Unauthorized);
+
+            if !expense_account.is_archived && (start..=end).contains(&expense_account.created_at) {
+                total = checked_add(total, expense_account.amount_in_base)?;
+                count += 1;
+            }
+        }
+
+        emit!(RangeTotal {
+            start,
+            end,
+            total,
+            count,
+        });
+
+        Ok(())
+    }
+
+    /// Recomputes the count, the total and the largest amount of the user stats from the
+    /// expenses of the authority, passed as remaining accounts, e.g. after they drifted
+    /// from the actual expenses. Archived expenses are left out like everywhere else, but
+    /// have to be passed too: their number has to match `UserStats::account_count`, so
+    /// the aggregates can't be lowered by leaving some out.
+    pub fn reconcile_stats<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ReconcileStats<'info>>,
+    ) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        let mut total_spent = 0;
+        let mut expense_count = 0;
+        let mut largest_amount = 0;
+        let user_stats = &mut ctx.accounts.user_stats;
+        require_eq!(
+            ctx.remaining_accounts.len() as u64,
+            user_stats.account_count,
+            ExpenseError::IncompleteExpenseSet
+        );
+        for (index, account_info) in ctx.remaining_accounts.iter().enumerate() {
+            // An expense passed twice would be counted twice
+            require!(
+                ctx.remaining_accounts[..index]
+                    .iter()
+                    .all(|other| other.key() != account_info.key()),
+                ExpenseError::InvalidExpenseAccount
+            );
+            let expense_account = Account::<ExpenseAccount>::try_from(account_info)?;
+            require_keys_eq!(expense_account.owner, authority, ExpenseError::Unauthorized);
+
+            if !expense_account.is_archived {
+                total_spent = checked_add(total_spent, expense_account.amount_in_base)?;
+                expense_count += 1;
+                largest_amount = largest_amount.max(expense_account.amount_in_base);
+            }
+        }
+
+        user_stats.expense_count = expense_count;
+        user_stats.total_spent = total_spent;
+        user_stats.largest_amount = largest_amount;
+        user_stats.largest_amount_stale = false;
+
+        Ok(())
+    }
+
+    /// Archives or unarchives the expense without closing the account,
+    /// archived expenses are excluded from the user stats.
+    pub fn archive_expense(ctx: Context<ArchiveExpense>, id: u64, archived: bool) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        if expense_account.is_archived == archived {
+            return Ok(());
+        }
+        if archived {
+            ctx.accounts
+                .user_stats
+                .remove_expense(expense_account.amount_in_base)?;
+        } else {
+            ctx.accounts
+                .user_stats
+                .record_expense(expense_account.amount_in_base)?;
+        }
+
+        expense_account.is_archived = archived;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Restores an archived expense, the undo of archiving it. Unlike
+    /// `archive_expense`, restoring an active expense fails.
+    pub fn restore_expense(ctx: Context<ArchiveExpense>, id: u64) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            expense_account.is_archived,
+            ExpenseError::ExpenseNotArchived
+        );
+
+        ctx.accounts
+            .user_stats
+            .record_expense(expense_account.amount_in_base)?;
+
+        expense_account.is_archived = false;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Reduces the net amount of the expense by a (partial) refund from the merchant,
+    /// the amount as entered stays.
+    pub fn apply_refund(ctx: Context<ApplyRefund>, id: u64, refund_amount: u64) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(refund_amount > 0, ExpenseError::AmountZero);
+        require!(
+            refund_amount <= expense_account.net_amount,
+            ExpenseError::RefundExceedsAmount
+        );
+
+        let old_amount_in_base = expense_account.amount_in_base;
+        expense_account.total_refunded =
+            checked_add(expense_account.total_refunded, refund_amount)?;
+        let amount = expense_account.amount;
+        expense_account.set_amount(amount)?;
+        if !expense_account.is_archived {
+            ctx.accounts
+                .user_stats
+                .replace_amount(old_amount_in_base, expense_account.amount_in_base)?;
+        }
+        update_category_index(
+            expense_account,
+            ctx.accounts.category_index.as_deref_mut(),
+            old_amount_in_base,
+        )?;
+
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Records an expense and pays it in SPL tokens from the authority's
+    /// associated token account to the merchant in the same transaction. The expense is
+    /// filed under the default category of the user. The budget PDA of the user is always
+    /// passed, and the payment counts against the budget when there is one.
+    pub fn pay_expense(
+        ctx: Context<PayExpense>,
+        id: u64,
+        merchant_name: String,
+        amount: u64,
+    ) -> Result<()> {
+        let mut budget = load_budget(&ctx.accounts.budget)?;
+        validate_expense_input(&merchant_name, amount, max_expense_amount(budget.as_ref()))?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.source.to_account_info(),
+                    to: ctx.accounts.destination.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let expense_account = &mut ctx.accounts.expense_account;
+
+        expense_account.id = id;
+        expense_account.merchant_name = merchant_name;
+        // The amount is denominated in the base units of the mint.
+        expense_account.fx_rate = FX_RATE_SCALE;
+        expense_account.set_amount(amount)?;
+        expense_account.category = ctx.accounts.user_stats.default_category;
+        expense_account.mint = Some(ctx.accounts.mint.key());
+        expense_account.owner = *ctx.accounts.authority.key;
+        expense_account.creator = expense_account.owner;
+        expense_account.created_at = Clock::get()?.unix_timestamp;
+        expense_account.updated_at = expense_account.created_at;
+        // Paid by the transfer above
+        expense_account.is_paid = true;
+        expense_account.paid_at = expense_account.created_at;
+        expense_account.version = EXPENSE_ACCOUNT_VERSION;
+        expense_account.bump = ctx.bumps.expense_account;
+
+        if let Some(budget) = &budget {
+            // The limits of a budget in a specific token don't apply to another token
+            require!(
+                budget.mint == Pubkey::default() || budget.mint == ctx.accounts.mint.key(),
+                ExpenseError::MintMismatch
+            );
+        }
+        record_new_expense(
+            expense_account,
+            budget.as_mut(),
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.category_index,
+            &mut ctx.accounts.rollup,
+        )?;
+        if let Some(budget) = &budget {
+            budget.try_serialize(&mut &mut ctx.accounts.budget.try_borrow_mut_data()?[..])?;
+        }
+
+        emit!(ExpenseCreated {
+            id,
+            owner: expense_account.owner,
+            amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Creates several expenses in one instruction. The expense PDAs are passed
+    /// as `remaining_accounts`, in the same order as `expenses`. The expenses are in the
+    /// base currency and the default category of the user, and recorded like in
+    /// `initialize_expense`.
+    pub fn initialize_expenses_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, InitializeExpensesBatch<'info>>,
+        expenses: Vec<ExpenseInput>,
+    ) -> Result<()> {
+        require!(!expenses.is_empty(), ExpenseError::BatchEmpty);
+        require!(
+            expenses.len() <= MAX_BATCH_SIZE,
+            ExpenseError::BatchTooLarge
+        );
+        require_eq!(
+            ctx.remaining_accounts.len(),
+            expenses.len(),
+            ExpenseError::BatchAccountsMismatch
+        );
+
+        let authority = ctx.accounts.authority.key();
+        let now = Clock::get()?.unix_timestamp;
+        let rent = Rent::get()?;
+        let max_amount = max_expense_amount(ctx.accounts.budget.as_deref());
+
+        for (input, account_info) in expenses.into_iter().zip(ctx.remaining_accounts) {
+            validate_expense_input(&input.merchant_name, input.amount, max_amount)?;
+
+            let id_bytes = input.id.to_le_bytes();
+            let (pda, bump) = Pubkey::find_program_address(
+                &[b"expense", authority.as_ref(), id_bytes.as_ref()],
+                ctx.program_id,
+            );
+            require_keys_eq!(account_info.key(), pda, ExpenseError::InvalidExpenseAccount);
+
+            // Fails if the account already exists, like `init` does.
+            let space = ExpenseAccount::space(&input.merchant_name, "");
+            system_program::create_account(
+                CpiContext::new_with_signer(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::CreateAccount {
+                        from: ctx.accounts.authority.to_account_info(),
+                        to: account_info.clone(),
+                    },
+                    &[&[b"expense", authority.as_ref(), id_bytes.as_ref(), &[bump]]],
+                ),
+                rent.minimum_balance(space),
+                space as u64,
+                ctx.program_id,
+            )?;
+
+            let mut expense_account = ExpenseAccount {
+                id: input.id,
+                owner: authority,
+                creator: authority,
+                merchant_name: input.merchant_name,
+                amount: input.amount,
+                fx_rate: FX_RATE_SCALE,
+                amount_in_base: input.amount,
+                net_amount: input.amount,
+                category: ctx.accounts.user_stats.default_category,
+                currency: ctx.accounts.config.base_currency,
+                created_at: now,
+                updated_at: now,
+                version: EXPENSE_ACCOUNT_VERSION,
+                bump,
+                ..Default::default()
+            };
+            record_new_expense(
+                &mut expense_account,
+                ctx.accounts.budget.as_deref_mut(),
+                &mut ctx.accounts.user_stats,
+                &mut ctx.accounts.category_index,
+                &mut ctx.accounts.rollup,
+            )?;
+            expense_account.try_serialize(&mut &mut account_info.try_borrow_mut_data()?[..])?;
+
+            emit!(ExpenseCreated {
+                id: input.id,
+                owner: authority,
+                amount: input.amount,
+                merchant_name: expense_account.merchant_name,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn create_split_expense(
+        ctx: Context<CreateSplitExpense>,
+        id: u64,
+        amount: u64,
+        shares: Vec<Share>,
+    ) -> Result<()> {
+        record_split(
+            &mut ctx.accounts.split_expense,
+            id,
+            ctx.accounts.authority.key(),
+            amount,
+            shares,
+        )
+    }
+
+    /// Creates a split expense shared evenly among the participants, see `split_evenly`.
+    pub fn create_even_split_expense(
+        ctx: Context<CreateEvenSplitExpense>,
+        id: u64,
+        amount: u64,
+        participants: Vec<Pubkey>,
+    ) -> Result<()> {
+        let shares = participants
+            .iter()
+            .zip(split_evenly(amount, participants.len()))
+            .map(|(participant, amount)| Share {
+                participant: *participant,
+                amount,
+            })
+            .collect();
+
+        record_split(
+            &mut ctx.accounts.split_expense,
+            id,
+            ctx.accounts.authority.key(),
+            amount,
+            shares,
+        )
+    }
+
+    /// Creates a group, e.g. a household, with the creator as its first member.
+    pub fn create_group(ctx: Context<CreateGroup>, group_id: u64) -> Result<()> {
+        let group = &mut ctx.accounts.group;
+        group.id = group_id;
+        group.creator = ctx.accounts.authority.key();
+        group.members = vec![group.creator];
+        group.bump = ctx.bumps.group;
+
+        Ok(())
+    }
+
+    /// Adds a member to the group, creator only.
+    pub fn add_member(ctx: Context<AddMember>, _group_id: u64, member: Pubkey) -> Result<()> {
+        let group = &mut ctx.accounts.group;
+        require!(
+            group.members.len() < MAX_GROUP_MEMBERS,
+            ExpenseError::TooManyMembers
+        );
+        require!(
+            !group.members.contains(&member),
+            ExpenseError::AlreadyGroupMember
+        );
+
+        group.members.push(member);
+
+        Ok(())
+    }
+
+    /// Records an expense of the group, any member can create one.
+    /// The amount is attributed to the group, not to the stats of the member.
+    pub fn create_group_expense(
+        ctx: Context<CreateGroupExpense>,
+        id: u64,
+        merchant_name: String,
+        amount: u64,
+    ) -> Result<()> {
+        validate_expense_input(&merchant_name, amount, MAX_EXPENSE_AMOUNT)?;
+
+        let group = &mut ctx.accounts.group;
+        group.total_spent = checked_add(group.total_spent, amount)?;
+        group.expense_count = checked_add(group.expense_count, 1)?;
+
+        let group_expense = &mut ctx.accounts.group_expense;
+        group_expense.id = id;
+        group_expense.group = group.key();
+        group_expense.paid_by = ctx.accounts.member.key();
+        group_expense.merchant_name = merchant_name;
+        group_expense.amount = amount;
+        group_expense.created_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Deletes a group expense, only the member that paid it can, and returns the rent to
+    /// them. Expenses already factored into a settlement can't be deleted.
+    pub fn delete_group_expense(ctx: Context<DeleteGroupExpense>, id: u64) -> Result<()> {
+        require_eq!(ctx.accounts.group_expense.id, id, ExpenseError::IdMismatch);
+
+        let group = &mut ctx.accounts.group;
+        group.total_spent = checked_sub(group.total_spent, ctx.accounts.group_expense.amount)?;
+        group.expense_count = checked_sub(group.expense_count, 1)?;
+
+        Ok(())
+    }
+
+    /// Computes the net balance of every member relative to an equal share of the group
+    /// expenses, which must all be passed exactly once as writable remaining accounts,
+    /// and marks them settled. The remainder of the equal split is added to the share of
+    /// the creator.
+    pub fn settle_group<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SettleGroup<'info>>,
+        group_id: u64,
+    ) -> Result<()> {
+        let group_key = ctx.accounts.group.key();
+        let group = &mut ctx.accounts.group;
+        require_eq!(group.id, group_id, ExpenseError::IdMismatch);
+        require_eq!(
+            ctx.remaining_accounts.len() as u64,
+            group.expense_count,
+            ExpenseError::GroupExpensesMismatch
+        );
+
+        let mut paid = vec![0; group.members.len()];
+        let mut seen = Vec::with_capacity(ctx.remaining_accounts.len());
+        for account_info in ctx.remaining_accounts {
+            require!(
+                !seen.contains(account_info.key),
+                ExpenseError::GroupExpensesMismatch
+            );
+            seen.push(*account_info.key);
+
+            require!(
+                account_info.is_writable,
+                anchor_lang::error::ErrorCode::AccountNotMutable
+            );
+            let mut group_expense = Account::<GroupExpense>::try_from(account_info)?;
+            require_keys_eq!(
+                group_expense.group,
+                group_key,
+                ExpenseError::GroupExpensesMismatch
+            );
+            // Members can't leave, so whoever paid is still a member
+            let index = group
+                .members
+                .iter()
+                .position(|member| *member == group_expense.paid_by)
+                .ok_or(ExpenseError::NotGroupMember)?;
+            paid[index] = checked_add(paid[index], group_expense.amount)?;
+
+            group_expense.settled = true;
+            group_expense.exit(ctx.program_id)?;
+        }
+
+        let total = paid
+            .iter()
+            .try_fold(0, |total, amount| checked_add(total, *amount))?;
+        let members = group.members.len() as u64;
+        let share = total / members;
+        let remainder = total % members;
+        // The creator is the first member
+        group.balances = paid
+            .iter()
+            .enumerate()
+            .map(|(index, paid)| {
+                let owed = if index == 0 { share + remainder } else { share };
+                net_balance(*paid, owed)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(())
+    }
+
+    pub fn create_recurring(
+        ctx: Context<CreateRecurring>,
+        id: u64,
+        merchant_name: String,
+        amount: u64,
+        interval_seconds: i64,
+    ) -> Result<()> {
+        validate_expense_input(&merchant_name, amount, MAX_EXPENSE_AMOUNT)?;
+        require!(interval_seconds > 0, ExpenseError::InvalidInterval);
+
+        let template = &mut ctx.accounts.template;
+        template.id = id;
+        template.owner = ctx.accounts.authority.key();
+        template.merchant_name = merchant_name;
+        template.amount = amount;
+        template.interval_seconds = interval_seconds;
+
+        Ok(())
+    }
+
+    /// Creates a concrete expense from a recurring template, at most once per
+    /// `interval_seconds` of the template, in the default category of the user.
+    pub fn instantiate_from_template(
+        ctx: Context<InstantiateFromTemplate>,
+        template_id: u64,
+        new_expense_id: u64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let template = &mut ctx.accounts.template;
+        require_eq!(template.id, template_id, ExpenseError::IdMismatch);
+
+        // A never instantiated template has `last_instantiated_at == 0`, so the first call always passes.
+        let next_allowed_at = template
+            .last_instantiated_at
+            .checked_add(template.interval_seconds)
+            .ok_or(ExpenseError::ArithmeticOverflow)?;
+        require!(now >= next_allowed_at, ExpenseError::RecurringTooSoon);
+        template.last_instantiated_at = now;
+
+        let expense_account = &mut ctx.accounts.expense_account;
+
+        expense_account.id = new_expense_id;
+        expense_account.merchant_name = template.merchant_name.clone();
+        expense_account.fx_rate = FX_RATE_SCALE;
+        expense_account.set_amount(template.amount)?;
+        expense_account.category = ctx.accounts.user_stats.default_category;
+        expense_account.owner = *ctx.accounts.authority.key;
+        expense_account.creator = expense_account.owner;
+        expense_account.created_at = now;
+        expense_account.updated_at = now;
+        expense_account.version = EXPENSE_ACCOUNT_VERSION;
+        expense_account.bump = ctx.bumps.expense_account;
+
+        record_new_expense(
+            expense_account,
+            ctx.accounts.budget.as_deref_mut(),
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.category_index,
+            &mut ctx.accounts.rollup,
+        )?;
+
+        emit!(ExpenseCreated {
+            id: new_expense_id,
+            owner: expense_account.owner,
+            amount: expense_account.amount,
+            merchant_name: expense_account.merchant_name.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Creates the program config, whoever initializes it picks the admin and the base
+    /// currency. The base currency can't change later, the amounts in the base currency
+    /// are stored converted.
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        admin: Pubkey,
+        base_currency: [u8; 3],
+    ) -> Result<()> {
+        require!(
+            base_currency.iter().all(u8::is_ascii_uppercase),
+            ExpenseError::InvalidCurrency
+        );
+        ctx.accounts.config.admin = admin;
+        ctx.accounts.config.paused = false;
+        ctx.accounts.config.required_approvals = 1;
+        ctx.accounts.config.approval_threshold = 0;
+        ctx.accounts.config.reimbursement_payer = Pubkey::default();
+        ctx.accounts.config.duplicate_guard = false;
+        ctx.accounts.config.min_modify_interval = 0;
+        ctx.accounts.config.require_receipt_to_delete = false;
+        ctx.accounts.config.oracle_program = Pubkey::default();
+        ctx.accounts.config.max_oracle_age = DEFAULT_MAX_ORACLE_AGE;
+        ctx.accounts.config.log_level = LogLevel::Error;
+        ctx.accounts.config.approvers = Vec::new();
+        ctx.accounts.config.base_currency = base_currency;
+
+        Ok(())
+    }
+
+    /// Pauses or resumes every instruction changing state but the admin ones, admin only.
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        ctx.accounts.config.paused = paused;
+
+        Ok(())
+    }
+
+    /// Makes expenses with an amount in the base currency above `approval_threshold`
+    /// require `required_approvals` distinct approvers, admin only.
+    pub fn set_approval_policy(
+        ctx: Context<SetApprovalPolicy>,
+        required_approvals: u8,
+        approval_threshold: u64,
+    ) -> Result<()> {
+        require!(
+            (1..=MAX_REQUIRED_APPROVALS).contains(&required_approvals),
+            ExpenseError::InvalidRequiredApprovals
+        );
+
+        ctx.accounts.config.required_approvals = required_approvals;
+        ctx.accounts.config.approval_threshold = approval_threshold;
+
+        Ok(())
+    }
+
+    /// Replaces the keys allowed to approve and reject expenses, admin only.
+    pub fn set_approvers(ctx: Context<SetApprovers>, approvers: Vec<Pubkey>) -> Result<()> {
+        require!(
+            approvers.len() <= MAX_APPROVERS,
+            ExpenseError::TooManyApprovers
+        );
+
+        ctx.accounts.config.approvers = approvers;
+
+        Ok(())
+    }
+
+    /// Sets the company key that reimburses expenses, admin only.
+    pub fn set_reimbursement_payer(
+        ctx: Context<SetReimbursementPayer>,
+        reimbursement_payer: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.config.reimbursement_payer = reimbursement_payer;
+
+        Ok(())
+    }
+
+    /// Enables or disables rejecting a second expense with the same merchant and amount
+    /// on the same day, admin only.
+    pub fn set_duplicate_guard(ctx: Context<SetDuplicateGuard>, enabled: bool) -> Result<()> {
+        ctx.accounts.config.duplicate_guard = enabled;
+
+        Ok(())
+    }
+
+    /// Enables or disables rejecting the deletion of expenses without a receipt hash,
+    /// admin only.
+    pub fn set_require_receipt_to_delete(
+        ctx: Context<SetRequireReceiptToDelete>,
+        enabled: bool,
+    ) -> Result<()> {
+        ctx.accounts.config.require_receipt_to_delete = enabled;
+
+        Ok(())
+    }
+
+    /// Sets the minimum number of seconds between changes of an expense by
+    /// `modify_expense`, zero disables the check, admin only.
+    pub fn set_min_modify_interval(ctx: Context<SetMinModifyInterval>, seconds: u32) -> Result<()> {
+        ctx.accounts.config.min_modify_interval = seconds;
+
+        Ok(())
+    }
+
+    /// Sets which program logs the instructions write, admin only.
+    pub fn set_log_level(ctx: Context<SetLogLevel>, log_level: LogLevel) -> Result<()> {
+        ctx.accounts.config.log_level = log_level;
+
+        Ok(())
+    }
+
+    /// Sets the program owning the price accounts `pin_fx_rate` reads and how many
+    /// seconds old their prices may be, admin only.
+    pub fn set_oracle(
+        ctx: Context<SetOracle>,
+        oracle_program: Pubkey,
+        max_oracle_age: u32,
+    ) -> Result<()> {
+        ctx.accounts.config.oracle_program = oracle_program;
+        ctx.accounts.config.max_oracle_age = max_oracle_age;
+
+        Ok(())
+    }
+
+    /// Sets the oracle account `pin_fx_rate` reads the price of `currency` in the base
+    /// currency from, admin only.
+    pub fn set_fx_feed(ctx: Context<SetFxFeed>, currency: [u8; 3], oracle: Pubkey) -> Result<()> {
+        require!(
+            currency.iter().all(u8::is_ascii_uppercase),
+            ExpenseError::InvalidCurrency
+        );
+
+        let fx_feed = &mut ctx.accounts.fx_feed;
+        fx_feed.currency = currency;
+        fx_feed.base_currency = ctx.accounts.config.base_currency;
+        fx_feed.oracle = oracle;
+
+        Ok(())
+    }
+
+    /// Registers the canonical form of a merchant name, see `normalize_merchant_name`,
+    /// so expenses can reference the merchant regardless of how the name was typed.
+    pub fn register_merchant(ctx: Context<RegisterMerchant>, name: String) -> Result<()> {
+        let name = normalize_merchant_name(&name);
+        validate_merchant_name(&name)?;
+
+        let merchant = &mut ctx.accounts.merchant;
+        merchant.name_hash = merchant_name_hash(&name);
+        merchant.name = name;
+        merchant.registered_by = ctx.accounts.payer.key();
+
+        Ok(())
+    }
+
+    /// Sets the spending limits, a `daily_limit` of zero disables the daily cap
+    /// and a `max_amount` of zero keeps the default `MAX_EXPENSE_AMOUNT`.
+    pub fn set_budget(
+        ctx: Context<SetBudget>,
+        monthly_limit: u64,
+        daily_limit: u64,
+        max_amount: u64,
+        alert_threshold: u64,
+        mint: Pubkey,
+        period_start_day: u8,
+        period_length_days: u16,
+    ) -> Result<()> {
+        // Every month has the days up to the 28th
+        require!(
+            (1..=28).contains(&period_start_day),
+            ExpenseError::InvalidPeriodStartDay
+        );
+
+        ctx.accounts.budget.monthly_limit = monthly_limit;
+        ctx.accounts.budget.daily_limit = daily_limit;
+        ctx.accounts.budget.max_amount = max_amount;
+        ctx.accounts.budget.alert_threshold = alert_threshold;
+        ctx.accounts.budget.mint = mint;
+        ctx.accounts.budget.period_start_day = period_start_day;
+        ctx.accounts.budget.period_length_days = period_length_days;
+
+        Ok(())
+    }
+
+    /// Offboards a user, closing their stats and budget and returning the rent to them.
+    /// Only the expenses counted in the stats (so not the archived ones) have to be
+    /// deleted first.
+    pub fn close_user(ctx: Context<CloseUser>) -> Result<()> {
+        require_eq!(
+            ctx.accounts.user_stats.expense_count,
+            0,
+            ExpenseError::ExpensesOutstanding
+        );
+
+        Ok(())
+    }
+
+    /// Allows a third party to modify the expense on behalf of the owner, `None` revokes it.
+    pub fn set_delegate(
+        ctx: Context<SetDelegate>,
+        id: u64,
+        delegate: Option<Pubkey>,
+    ) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        expense_account.delegate = delegate;
+
+        Ok(())
+    }
+
+    /// Attaches a receipt to an expense, an all-zero hash detaches it.
+    pub fn update_receipt_hash(
+        ctx: Context<UpdateReceiptHash>,
+        id: u64,
+        receipt_hash: [u8; 32],
+    ) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        expense_account.receipt_hash = receipt_hash;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Attaches the IPFS content identifier of a receipt to an expense, resizing the
+    /// account to fit it. An empty CID detaches it.
+    pub fn attach_receipt(ctx: Context<AttachReceipt>, id: u64, cid: String) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            cid.len() <= MAX_RECEIPT_CID_LEN,
+            ExpenseError::ReceiptCidTooLong
+        );
+        require!(cid.is_ascii(), ExpenseError::InvalidReceiptCid);
+
+        expense_account.receipt_cid = cid;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Brings an expense account created by an older program version up to
+    /// the current layout. Migrating doesn't change the expense itself, so anyone
+    /// may pay for it. Expenses from before the layout was versioned are added to the
+    /// stats and the category index of their owner, which didn't count them yet.
+    pub fn migrate_expense(ctx: Context<MigrateExpense>, id: u64) -> Result<()> {
+        let account_info = ctx.accounts.expense_account.to_account_info();
+        let original_len = account_info.data_len();
+
+        // An older layout lacks the fields added since, which are appended at the end.
+        // Zero bytes decode as their defaults, so the data is padded with room for every
+        // field of an empty expense before decoding it.
+        account_info.resize(account_info.data_len() + ExpenseAccount::space("", ""))?;
+        let mut expense_account =
+            ExpenseAccount::try_deserialize(&mut &account_info.try_borrow_data()?[..])?;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        // Accounts older than the creator field are taken to be at the address of their
+        // owner, the PDA check below rejects one that was transferred before
+        if expense_account.version < 19 {
+            expense_account.creator = expense_account.owner;
+        }
+        // Scoped expenses couldn't be modified before the scope was stored, so their
+        // category is still the one in their seeds
+        if expense_account.version < 20
+            && expense_account.find_pda(id, ctx.program_id).0 != account_info.key()
+        {
+            expense_account.scope = Some(expense_account.category as u8);
+        }
+        let (pda, bump) = expense_account.find_pda(id, ctx.program_id);
+        require_keys_eq!(pda, account_info.key(), ExpenseError::InvalidExpenseAccount);
+        require_keys_eq!(
+            expense_account.owner,
+            ctx.accounts.owner.key(),
+            ExpenseError::Unauthorized
+        );
+
+        // Version 0 accounts predate the version field and come in every layout before
+        // it, down to the original one of just the ID, the owner, the merchant name and
+        // the amount. The fields they didn't store decode from the padding, where the
+        // enums would read as their first variant, so these get their defaults instead.
+        let is_unversioned = expense_account.version == 0;
+        if is_unversioned {
+            // After the ID, the owner, the merchant name, the amount and the timestamps
+            let category_offset =
+                8 + 8 + 32 + (4 + expense_account.merchant_name.len()) + 8 + 8 + 8;
+            if original_len <= category_offset {
+                expense_account.category = ctx.accounts.user_stats.default_category;
+            }
+            let payment_method_offset = category_offset
+                + 1
+                + 3
+                + (4 + expense_account.note.len())
+                + (1 + 32 * usize::from(expense_account.mint.is_some()))
+                + 1
+                + (1 + 32 * usize::from(expense_account.approver.is_some()))
+                + 32;
+            if original_len <= payment_method_offset {
+                expense_account.payment_method = PaymentMethod::default();
+            }
+        }
+        // Fields added by later versions, with a non-zero default, are set here.
+        if expense_account.version < 2 {
+            expense_account.bump = bump;
+        }
+        if expense_account.version < 9 {
+            expense_account.fx_rate = FX_RATE_SCALE;
+            expense_account.amount_in_base = expense_account.amount;
+        }
+        // Refunds were subtracted from the amount itself before the net amount was stored
+        if expense_account.version < 22 {
+            expense_account.net_amount = expense_account.amount;
+            expense_account.amount =
+                checked_add(expense_account.amount, expense_account.total_refunded)?;
+        }
+        // Older expenses decode as in no category index. Which of the versioned ones the
+        // index counted can't be told, so an index predating version 21 may need
+        // recreating. Unversioned expenses predate the stats and the indices, so they are
+        // added to both.
+        if is_unversioned {
+            let user_stats = &mut ctx.accounts.user_stats;
+            if !expense_account.is_archived {
+                user_stats.record_expense(expense_account.amount_in_base)?;
+            }
+            user_stats.add_account()?;
+            let category_index_info = ctx
+                .accounts
+                .category_index
+                .as_ref()
+                .ok_or(ExpenseError::CategoryIndexMissing)?;
+            let owner = expense_account.owner;
+            let category_seed = [expense_account.category as u8];
+            let (category_index_pda, category_index_bump) = Pubkey::find_program_address(
+                &[b"cat_index", owner.as_ref(), &category_seed],
+                ctx.program_id,
+            );
+            require_keys_eq!(
+                category_index_info.key(),
+                category_index_pda,
+                ExpenseError::InvalidCategoryIndex
+            );
+            // Created here like `init_if_needed` would, its seeds depend on the category
+            // decoded above
+            let mut category_index: CategoryIndex = load_or_default(category_index_info)?;
+            if category_index_info.data_is_empty() {
+                system_program::create_account(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.system_program.to_account_info(),
+                        system_program::CreateAccount {
+                            from: ctx.accounts.payer.to_account_info(),
+                            to: category_index_info.to_account_info(),
+                        },
+                        &[&[
+                            b"cat_index",
+                            owner.as_ref(),
+                            &category_seed,
+                            &[category_index_bump],
+                        ]],
+                    ),
+                    Rent::get()?.minimum_balance(CategoryIndex::SPACE),
+                    CategoryIndex::SPACE as u64,
+                    ctx.program_id,
+                )?;
+            }
+            add_to_category_index(&mut expense_account, &mut category_index)?;
+            category_index
+                .try_serialize(&mut &mut category_index_info.try_borrow_mut_data()?[..])?;
+        }
+        expense_account.version = EXPENSE_ACCOUNT_VERSION;
+
+        resize_account(
+            &account_info,
+            expense_account.size(),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        expense_account.try_serialize(&mut &mut account_info.try_borrow_mut_data()?[..])?;
+
+        Ok(())
+    }
+
+    /// Locks the expense, e.g. once filed with a tax return. Locked expenses can neither
+    /// be modified nor deleted, and there is no way to unlock them.
+    pub fn lock_expense(ctx: Context<LockExpense>, id: u64) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(!expense_account.is_locked, ExpenseError::ExpenseLocked);
+
+        expense_account.is_locked = true;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Marks the expense (bill) as paid, recording when it was paid.
+    pub fn mark_paid(ctx: Context<MarkPaid>, id: u64) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(!expense_account.is_paid, ExpenseError::ExpenseAlreadyPaid);
+
+        expense_account.is_paid = true;
+        expense_account.paid_at = Clock::get()?.unix_timestamp;
+        expense_account.updated_at = expense_account.paid_at;
+
+        Ok(())
+    }
+
+    /// Flags the expense for a follow-up review, or clears the flag.
+    pub fn set_flag(ctx: Context<SetFlag>, id: u64, flagged: bool) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        expense_account.flagged = flagged;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(ExpenseFlagged {
+            id,
+            owner: expense_account.owner,
+            flagged,
+        });
+
+        Ok(())
+    }
+
+    /// Adds a tag to the expense, growing the account by the size of the tag.
+    pub fn add_tag(ctx: Context<AddTag>, id: u64, tag: String) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(tag.len() <= MAX_TAG_LEN, ExpenseError::TagTooLong);
+        require!(
+            expense_account.tags.len() < MAX_TAGS,
+            ExpenseError::TooManyTags
+        );
+        require!(
+            !expense_account.tags.contains(&tag),
+            ExpenseError::DuplicateTag
+        );
+
+        expense_account.tags.push(tag);
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Removes a tag from the expense, shrinking the account and refunding the rent.
+    pub fn remove_tag(ctx: Context<RemoveTag>, id: u64, tag: String) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        let position = expense_account
+            .tags
+            .iter()
+            .position(|t| *t == tag)
+            .ok_or(ExpenseError::TagNotFound)?;
+
+        expense_account.tags.remove(position);
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Shares the expense read-only with a viewer, see `ExpenseAccount::viewers`.
+    pub fn add_viewer(ctx: Context<AddViewer>, id: u64, viewer: Pubkey) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            expense_account.viewers.len() < MAX_VIEWERS,
+            ExpenseError::TooManyViewers
+        );
+        require!(
+            !expense_account.viewers.contains(&viewer),
+            ExpenseError::DuplicateViewer
+        );
+
+        expense_account.viewers.push(viewer);
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Stops sharing the expense with a viewer, shrinking the account and refunding the rent.
+    pub fn remove_viewer(ctx: Context<RemoveViewer>, id: u64, viewer: Pubkey) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        let position = expense_account
+            .viewers
+            .iter()
+            .position(|v| *v == viewer)
+            .ok_or(ExpenseError::ViewerNotFound)?;
+
+        expense_account.viewers.remove(position);
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Stores the ciphertext of a private note and its nonce, an empty ciphertext clears
+    /// the note. The account is resized to the ciphertext.
+    pub fn set_encrypted_note(
+        ctx: Context<SetEncryptedNote>,
+        id: u64,
+        ciphertext: Vec<u8>,
+        nonce: [u8; 24],
+    ) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            ciphertext.len() <= MAX_ENCRYPTED_NOTE_LEN,
+            ExpenseError::EncryptedNoteTooLong
+        );
+        require!(
+            ciphertext.is_empty() || nonce != [0; 24],
+            ExpenseError::NonceMissing
+        );
+
+        expense_account.nonce = if ciphertext.is_empty() {
+            [0; 24]
+        } else {
+            nonce
+        };
+        expense_account.encrypted_note = ciphertext;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Sets a metadata entry of the expense, overwriting the value of an existing key.
+    pub fn set_metadata(
+        ctx: Context<SetMetadata>,
+        id: u64,
+        key: String,
+        value: String,
+    ) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            key.len() <= MAX_METADATA_KEY_LEN,
+            ExpenseError::MetadataKeyTooLong
+        );
+        require!(
+            value.len() <= MAX_METADATA_VALUE_LEN,
+            ExpenseError::MetadataValueTooLong
+        );
+
+        match expense_account
+            .metadata
+            .iter_mut()
+            .find(|entry| entry.key == key)
+        {
+            Some(entry) => entry.value = value,
+            None => {
+                require!(
+                    expense_account.metadata.len() < MAX_METADATA_ENTRIES,
+                    ExpenseError::TooManyMetadataEntries
+                );
+                expense_account.metadata.push(MetadataEntry { key, value });
+            }
+        }
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Removes a metadata entry from the expense, shrinking the account and refunding the rent.
+    pub fn remove_metadata(ctx: Context<RemoveMetadata>, id: u64, key: String) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        let position = expense_account
+            .metadata
+            .iter()
+            .position(|entry| entry.key == key)
+            .ok_or(ExpenseError::MetadataKeyNotFound)?;
+
+        expense_account.metadata.remove(position);
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Adds a line item to the expense, the line items must not add up to more than
+    /// the amount of the expense.
+    pub fn add_line_item(
+        ctx: Context<AddLineItem>,
+        id: u64,
+        description: String,
+        amount: u64,
+    ) -> Result<()> {
+        let expense_account = &ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(amount > 0, ExpenseError::AmountZero);
+        require!(
+            description.len() <= MAX_LINE_ITEM_DESCRIPTION_LEN,
+            ExpenseError::LineItemDescriptionTooLong
+        );
+
+        let line_items = &mut ctx.accounts.line_items;
+        require!(
+            line_items.items.len() < MAX_LINE_ITEMS,
+            ExpenseError::TooManyLineItems
+        );
+        require!(
+            checked_add(line_items.total()?, amount)? <= expense_account.amount,
+            ExpenseError::LineItemsExceedAmount
+        );
+
+        line_items.expense = expense_account.key();
+        line_items.items.push(LineItem {
+            description,
+            amount,
+        });
+
+        // Created empty by `init_if_needed`, grown here as `realloc` can't be combined with it
+        let space = line_items.size();
+        resize_account(
+            &line_items.to_account_info(),
+            space,
+            &ctx.accounts.authority,
+            &ctx.accounts.system_program,
+        )
+    }
+
+    /// Removes the line item at `index`, shrinking the account and refunding the rent.
+    pub fn remove_line_item(ctx: Context<RemoveLineItem>, id: u64, index: u32) -> Result<()> {
+        require_eq!(
+            ctx.accounts.expense_account.id,
+            id,
+            ExpenseError::IdMismatch
+        );
+        let line_items = &mut ctx.accounts.line_items;
+        require!(
+            (index as usize) < line_items.items.len(),
+            ExpenseError::LineItemNotFound
+        );
+
+        line_items.items.remove(index as usize);
+
+        Ok(())
+    }
+
+    /// Checks that the line items of the expense add up to at most its amount, anything
+    /// short of it being an uncategorized remainder. The amount itself can still be
+    /// lowered below the items after they were added.
+    pub fn verify_expense(ctx: Context<VerifyExpense>, id: u64) -> Result<()> {
+        let expense_account = &ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            ctx.accounts.line_items.total()? <= expense_account.amount,
+            ExpenseError::LineItemMismatch
+        );
+
+        Ok(())
+    }
+
+    /// Emits the full state of the expense as an `ExpenseSnapshot`, for off-chain archival.
+    pub fn export_expense(ctx: Context<ExportExpense>, id: u64) -> Result<()> {
+        let expense_account = &ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        emit!(ExpenseSnapshot {
+            expense: expense_account.key(),
+            account: (**expense_account).clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Approves or rejects a pending expense, the approver must be one of the approvers
+    /// of the config and not the owner.
+    /// Expenses above the approval threshold of the config stay pending until enough
+    /// distinct approvers signed off, a single rejection rejects them.
+    pub fn approve_expense(ctx: Context<ApproveExpense>, id: u64, approved: bool) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        let approver = ctx.accounts.approver.key();
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require_keys_neq!(expense_account.owner, approver, ExpenseError::SelfApproval);
+        require!(
+            expense_account.status == ExpenseStatus::Pending,
+            ExpenseError::ExpenseAlreadyReviewed
+        );
+
+        if !approved {
+            expense_account.status = ExpenseStatus::Rejected;
+            expense_account.approver = Some(approver);
+            return Ok(());
+        }
+
+        let multi_approval = &mut ctx.accounts.multi_approval;
+        require!(
+            !multi_approval.approvers.contains(&approver),
+            ExpenseError::DuplicateApproval
+        );
+        multi_approval.expense = expense_account.key();
+        multi_approval.approvers.push(approver);
+
+        if multi_approval.approvers.len()
+            >= ctx
+                .accounts
+                .config
+                .required_approvals_for(expense_account.amount_in_base)
+        {
+            expense_account.status = ExpenseStatus::Approved;
+            expense_account.approver = Some(approver);
+        }
+
+        Ok(())
+    }
+
+    /// Asks the company to reimburse an expense the owner fronted.
+    pub fn request_reimbursement(ctx: Context<RequestReimbursement>, id: u64) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            expense_account.reimbursement_status == ReimbursementStatus::NotRequested,
+            ExpenseError::ReimbursementAlreadyRequested
+        );
+
+        expense_account.reimbursement_status = ReimbursementStatus::Requested;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Reimburses a requested expense, transferring its net amount in lamports from the
+    /// reimbursement payer of the config to the owner.
+    pub fn mark_reimbursed(ctx: Context<MarkReimbursed>, id: u64) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            expense_account.reimbursement_status == ReimbursementStatus::Requested,
+            ExpenseError::ReimbursementNotRequested
+        );
+
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            expense_account.net_amount,
+        )?;
+
+        expense_account.reimbursement_status = ReimbursementStatus::Reimbursed;
+        expense_account.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    pub fn transfer_ownership(
+        ctx: Context<TransferOwnership>,
+        id: u64,
+        new_owner: Pubkey,
+    ) -> Result<()> {
+        require_eq!(
+            ctx.accounts.expense_account.id,
+            id,
+            ExpenseError::IdMismatch
+        );
+
+        // Only the logical ownership changes. The PDA address stays derived from the key
+        // of the original creator, so the account keeps living at the same address.
+        change_owner(
+            &mut ctx.accounts.expense_account,
+            new_owner,
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.new_owner_stats,
+            ctx.accounts.category_index.as_deref_mut(),
+            &mut ctx.accounts.new_owner_category_index,
+        )
+    }
+
+    /// Sets or, with `None`, clears the recovery key of the user, see `recover_expense`.
+    pub fn set_recovery_key(
+        ctx: Context<SetRecoveryKey>,
+        recovery_key: Option<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.user_stats.recovery_key = recovery_key;
+
+        Ok(())
+    }
+
+    /// Sets the minimum amount in the base currency of the expenses the user creates,
+    /// zero disables it.
+    pub fn set_min_amount(ctx: Context<SetMinAmount>, min_amount: u64) -> Result<()> {
+        ctx.accounts.user_stats.min_amount = min_amount;
+
+        Ok(())
+    }
+
+    /// Sets the category `initialize_expense_auto` files expenses of the user under when
+    /// they come without one.
+    pub fn set_default_category(
+        ctx: Context<SetDefaultCategory>,
+        category: ExpenseCategory,
+    ) -> Result<()> {
+        ctx.accounts.user_stats.default_category = category;
+
+        Ok(())
+    }
+
+    /// Reassigns an expense to `new_owner`, signed by the recovery key of its owner.
+    /// An escape hatch for a lost wallet, only available once the owner set a recovery key.
+    pub fn recover_expense(ctx: Context<RecoverExpense>, id: u64, new_owner: Pubkey) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        let recovery_key = ctx
+            .accounts
+            .user_stats
+            .recovery_key
+            .ok_or(ExpenseError::RecoveryKeyNotSet)?;
+        require_keys_eq!(
+            recovery_key,
+            ctx.accounts.recovery_key.key(),
+            ExpenseError::Unauthorized
+        );
+
+        // Like in `transfer_ownership` the account stays at its address
+        change_owner(
+            expense_account,
+            new_owner,
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.new_owner_stats,
+            ctx.accounts.category_index.as_deref_mut(),
+            &mut ctx.accounts.new_owner_category_index,
+        )
+    }
+
+    /// Offers the expense to `new_owner`, the first step of a transfer that only takes
+    /// effect once `new_owner` accepts it, so a mistyped key can't receive the expense.
+    pub fn propose_transfer(
+        ctx: Context<ProposeTransfer>,
+        id: u64,
+        new_owner: Pubkey,
+    ) -> Result<()> {
+        require_eq!(
+            ctx.accounts.expense_account.id,
+            id,
+            ExpenseError::IdMismatch
+        );
+
+        ctx.accounts.expense_account.pending_owner = Some(new_owner);
+
+        Ok(())
+    }
+
+    /// Completes a transfer proposed by `propose_transfer`, signed by the proposed owner.
+    pub fn accept_transfer(ctx: Context<AcceptTransfer>, id: u64) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+        // Like in `transfer_ownership` the account stays at its address
+        change_owner(
+            expense_account,
+            ctx.accounts.authority.key(),
+            &mut ctx.accounts.user_stats,
+            &mut ctx.accounts.new_owner_stats,
+            ctx.accounts.category_index.as_deref_mut(),
+            &mut ctx.accounts.new_owner_category_index,
+        )
+    }
+
+    /// Withdraws a transfer proposed by `propose_transfer`, owner only.
+    pub fn cancel_transfer(ctx: Context<ProposeTransfer>, id: u64) -> Result<()> {
+        let expense_account = &mut ctx.accounts.expense_account;
+        require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+        require!(
+            expense_account.pending_owner.is_some(),
+            ExpenseError::NoPendingTransfer
+        );
+
+        expense_account.pending_owner = None;
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(
+    id: u64,
+    merchant_name: String,
+    amount: u64,
+    category: ExpenseCategory,
+    currency: [u8; 3],
+    note: String,
+    receipt_hash: [u8; 32],
+    payment_method: PaymentMethod,
+    due_date: i64,
+    fx_rate: u64,
+    client_ref: Option<[u8; 16]>
+)]
+pub struct InitializeExpense<'info> {
+    // represents the public key of the user who is calling this instruction
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // This is the new account that will be created and hold the expense data
+    #[account(
+        init,
+        payer = authority,
+        space = ExpenseAccount::space(&merchant_name, &note),
+        seeds = [b"expense", authority.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // Per-user aggregates, created along with the first expense of the user
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // The rollup of the month the expense is created in. The month is taken from the
+    // cluster clock, the same `created_at` is set from, so an expense created around
+    // midnight at the end of a month is never filed under the other month: a client
+    // that derived the PDA for the wrong month gets a seeds error and can retry.
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = MonthlyRollup::SPACE,
+        seeds = [
+            b"rollup",
+            authority.key().as_ref(),
+            year_month(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub rollup: Account<'info, MonthlyRollup>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[category as u8]],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    // Optional, the spending is checked against the budget only when it's provided
+    #[account(
+        mut,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    // Optional, links the expense to a registered merchant
+    pub merchant: Option<Account<'info, Merchant>>,
+
+    // Required while the duplicate guard of the config is enabled, one per merchant,
+    // amount and (UTC) day
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = DuplicateGuard::SPACE,
+        seeds = [
+            b"dup",
+            authority.key().as_ref(),
+            merchant_name_hash(&normalize_merchant_name(&merchant_name)).as_ref(),
+            amount.to_le_bytes().as_ref(),
+            day_start(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub duplicate_guard: Option<Account<'info, DuplicateGuard>>,
+
+    // Required with a `client_ref`, one per user and reference
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = SubmissionGuard::SPACE,
+        seeds = [b"submission", authority.key().as_ref(), client_ref.unwrap_or_default().as_ref()],
+        bump
+    )]
+    pub submission_guard: Option<Account<'info, SubmissionGuard>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merchant_name: String, amount: u64, category: ExpenseCategory)]
+pub struct ValidateExpense<'info> {
+    pub authority: Signer<'info>,
+
+    // The accounts `initialize_expense` would create or update, at the same addresses.
+    // Not deserialized by Anchor, as they may not exist yet.
+    /// CHECK: Only checked to be empty, the ID is in use otherwise.
+    #[account(seeds = [b"expense", authority.key().as_ref(), id.to_le_bytes().as_ref()], bump)]
+    pub expense_account: UncheckedAccount<'info>,
+
+    /// CHECK: Decoded by `load_or_default`, the PDA can only be created by the program.
+    #[account(seeds = [b"stats", authority.key().as_ref()], bump)]
+    pub user_stats: UncheckedAccount<'info>,
+
+    /// CHECK: Decoded by `load_or_default`, the PDA can only be created by the program.
+    #[account(
+        seeds = [b"cat_index", authority.key().as_ref(), &[category as u8]],
+        bump
+    )]
+    pub category_index: UncheckedAccount<'info>,
+
+    // Optional like in `InitializeExpense`
+    #[account(
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    /// CHECK: Decoded by `load_or_default`, the PDA can only be created by the program.
+    #[account(
+        seeds = [
+            b"dup",
+            authority.key().as_ref(),
+            merchant_name_hash(&normalize_merchant_name(&merchant_name)).as_ref(),
+            amount.to_le_bytes().as_ref(),
+            day_start(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub duplicate_guard: UncheckedAccount<'info>,
+
+    // Without the pause constraint, a paused program is reported like the other failures
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merchant_name: String)]
+pub struct InitializeTransfer<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // At the address of `initialize_expense`, the other instructions treat it like any
+    // expense
+    #[account(
+        init,
+        payer = authority,
+        space = ExpenseAccount::space(&merchant_name, ""),
+        seeds = [b"expense", authority.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Like in `InitializeExpense`
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = MonthlyRollup::SPACE,
+        seeds = [
+            b"rollup",
+            authority.key().as_ref(),
+            year_month(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub rollup: Account<'info, MonthlyRollup>,
+
+    // Transfers have no category of their own, they stay in the default one
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[ExpenseCategory::default() as u8]],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(merchant_name: String, amount: u64, category: Option<ExpenseCategory>)]
+pub struct InitializeExpenseAuto<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Comes before the expense account, whose seeds take the ID from it
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = ExpenseAccount::space(&merchant_name, ""),
+        seeds = [b"expense", authority.key().as_ref(), user_stats.next_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    // Like in `InitializeExpense`
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = MonthlyRollup::SPACE,
+        seeds = [
+            b"rollup",
+            authority.key().as_ref(),
+            year_month(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub rollup: Account<'info, MonthlyRollup>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[category.unwrap_or(user_stats.default_category) as u8]],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merchant_name: String, amount: u64, category: ExpenseCategory)]
+pub struct InitializeExpenseScoped<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The category byte between the owner and the ID keeps the addresses apart from the
+    // flat ones of `initialize_expense`
+    #[account(
+        init,
+        payer = authority,
+        space = ExpenseAccount::space(&merchant_name, ""),
+        seeds = [b"expense", authority.key().as_ref(), &[category as u8], id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        mut,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    // Like in `InitializeExpense`
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = MonthlyRollup::SPACE,
+        seeds = [
+            b"rollup",
+            authority.key().as_ref(),
+            year_month(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub rollup: Account<'info, MonthlyRollup>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[category as u8]],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(tenant: [u8; 8], id: u64, merchant_name: String)]
+pub struct InitializeExpenseTenant<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The tenant before the owner makes the seeds longer than the flat and the scoped
+    // ones, so the addresses can't collide with theirs
+    #[account(
+        init,
+        payer = authority,
+        space = ExpenseAccount::space(&merchant_name, ""),
+        seeds = [b"expense", tenant.as_ref(), authority.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // The stats and the budget of the user are shared by the tenants
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        mut,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    // Like in `InitializeExpense`
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = MonthlyRollup::SPACE,
+        seeds = [
+            b"rollup",
+            authority.key().as_ref(),
+            year_month(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub rollup: Account<'info, MonthlyRollup>,
+
+    // Tenant expenses are created without a category, they stay in the default one
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[ExpenseCategory::default() as u8]],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merchant_name: String, amount: u64, category: ExpenseCategory)]
+pub struct ModifyExpense<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The signer is either the owner or the delegate, so the seeds are derived from the
+    // stored creator, which unlike the owner survives transfers, and the authorization is
+    // enforced explicitly by the constraint.
+    // The handler resizes the account to the new merchant name, the authority tops up
+    // the rent when it grows and gets the excess back when it shrinks. Locked expenses
+    // and expenses signed off by an approval can't be changed.
+    // The seeds are checked with the canonical bump stored at creation, a single hash
+    // rather than a `find_program_address` search. `ModifyExpenseWithBump` takes it
+    // from the client instead.
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.is_owner_or_delegate(&authority.key()) @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = expense_account.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // The stats of the owner, not of the (possibly delegated) signer
+    #[account(
+        mut,
+        seeds = [b"stats", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // The index of the current category, optional like in `UpdateAmount`. An expense in
+    // no index joins the one of the new category.
+    #[account(
+        mut,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    // The index of the new category, the same account when the category doesn't change
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[category as u8]],
+        bump
+    )]
+    pub new_category_index: Account<'info, CategoryIndex>,
+
+    // Optional, only read for the maximum amount of the owner
+    #[account(
+        seeds = [b"budget", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    id: u64,
+    merchant_name: String,
+    amount: u64,
+    category: ExpenseCategory,
+    payment_method: PaymentMethod,
+    bump: u8
+)]
+pub struct ModifyExpenseWithBump<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Like in `ModifyExpense`, but the seeds are checked with the bump passed by the
+    // client. Any bump but the stored canonical one derives another address and fails
+    // the seeds, and the constraint compares it against the stored one as well, so a
+    // non-canonical bump is never accepted.
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = bump,
+        constraint = bump == expense_account.bump @ ExpenseError::InvalidBump,
+        constraint = expense_account.is_owner_or_delegate(&authority.key()) @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = expense_account.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // The accounts below are the ones of `ModifyExpense`
+    #[account(
+        mut,
+        seeds = [b"stats", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        mut,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[category as u8]],
+        bump
+    )]
+    pub new_category_index: Account<'info, CategoryIndex>,
+
+    #[account(
+        seeds = [b"budget", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct SetTaxRate<'info> {
+    pub authority: Signer<'info>,
+
+    // Authorized like `ModifyExpense`
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.is_owner_or_delegate(&authority.key()) @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = expense_account.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct PinFxRate<'info> {
+    pub authority: Signer<'info>,
+
+    // Authorized like `ModifyExpense`
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.is_owner_or_delegate(&authority.key()) @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = expense_account.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // The index the expense is counted in, optional for an expense in none, see
+    // `ExpenseAccount::in_category_index`
+    #[account(
+        mut,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    /// CHECK: Decoded as an `OraclePrice`, trusted because the configured oracle
+    /// program owns it.
+    #[account(owner = config.oracle_program @ ExpenseError::InvalidOracleAccount)]
+    pub oracle: UncheckedAccount<'info>,
+
+    // The oracle has to be the feed of the currency pair, a price of another pair would
+    // be taken as the rate otherwise
+    #[account(
+        seeds = [b"fx_feed", expense_account.currency.as_ref(), config.base_currency.as_ref()],
+        bump,
+        constraint = fx_feed.oracle == oracle.key() @ ExpenseError::OracleFeedMismatch
+    )]
+    pub fx_feed: Account<'info, FxFeed>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct UpdateAmount<'info> {
+    pub authority: Signer<'info>,
+
+    // Authorized like `ModifyExpense`
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.is_owner_or_delegate(&authority.key()) @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = expense_account.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // The index the expense is counted in, optional for an expense in none, see
+    // `ExpenseAccount::in_category_index`
+    #[account(
+        mut,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(
+        seeds = [b"budget", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merchant_name: String)]
+pub struct RenameMerchant<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Authorized and resized like `ModifyExpense`
+    #[account(
+        mut,
+        realloc = capped_space(expense_account.size() - expense_account.merchant_name.len() + merchant_name.len())?,
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.is_owner_or_delegate(&authority.key()) @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = expense_account.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, category: ExpenseCategory)]
+pub struct Recategorize<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Authorized like `ModifyExpense`
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.is_owner_or_delegate(&authority.key()) @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = expense_account.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // The indices of the current and the new category, like in `ModifyExpense`
+    #[account(
+        mut,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[category as u8]],
+        bump
+    )]
+    pub new_category_index: Account<'info, CategoryIndex>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(old_id: u64, new_id: u64)]
+pub struct RekeyExpense<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Authorized like `DeleteExpense`, expenses signed off by an approval keep their ID
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), old_id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = expense_account.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // `init` fails when the new ID is taken. The expense stays in its tenant and scope.
+    #[account(
+        init,
+        payer = authority,
+        space = expense_account.size(),
+        seeds = [b"expense", expense_account.tenant_seed(), authority.key().as_ref(), expense_account.scope_seed(), new_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub new_expense_account: Account<'info, ExpenseAccount>,
+
+    /// CHECK: Only checked to be empty. The line items and approvals are derived from the
+    /// address of the expense, so they would be left behind by the move.
+    #[account(
+        seeds = [b"items", expense_account.key().as_ref()],
+        bump,
+        constraint = line_items.data_is_empty() @ ExpenseError::ExpenseHasDependents
+    )]
+    pub line_items: UncheckedAccount<'info>,
+
+    /// CHECK: Only checked to be empty, see `line_items`.
+    #[account(
+        seeds = [b"approval", expense_account.key().as_ref()],
+        bump,
+        constraint = approval.data_is_empty() @ ExpenseError::ExpenseHasDependents
+    )]
+    pub approval: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id : u64)]
+pub struct DeleteExpense<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: Only credited with the reclaimed rent. It's passed explicitly and the owner
+    /// signs the transaction naming it, pass the authority itself to get the rent back.
+    #[account(mut)]
+    pub rent_destination: UncheckedAccount<'info>,
+
+    // Derived from the creator like in `ModifyExpense`, so the owner of a transferred
+    // expense can delete it. The owner check keeps a creator that transferred the expense
+    // away from deleting it.
+    #[account(
+        mut,
+        close = rent_destination,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized,
+        constraint = !expense_account.is_locked @ ExpenseError::ExpenseLocked
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Optional like in `ModifyExpense`
+    #[account(
+        mut,
+        seeds = [b"cat_index", authority.key().as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(keep_id: u64, remove_id: u64)]
+pub struct MergeExpenses<'info> {
+    #[account(
+        mut,
+        constraint = keep_id != remove_id @ ExpenseError::MergeIntoItself
+    )]
+    pub authority: Signer<'info>,
+
+    // Authorized like `DeleteExpense`, for both expenses
+    #[account(
+        mut,
+        seeds = [b"expense", kept_expense.tenant_seed(), kept_expense.creator.as_ref(), kept_expense.scope_seed(), keep_id.to_le_bytes().as_ref()],
+        bump = kept_expense.bump,
+        constraint = kept_expense.owner == authority.key() @ ExpenseError::Unauthorized,
+        constraint = !kept_expense.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = kept_expense.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub kept_expense: Account<'info, ExpenseAccount>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"expense", removed_expense.tenant_seed(), removed_expense.creator.as_ref(), removed_expense.scope_seed(), remove_id.to_le_bytes().as_ref()],
+        bump = removed_expense.bump,
+        constraint = removed_expense.owner == authority.key() @ ExpenseError::Unauthorized,
+        constraint = !removed_expense.is_locked @ ExpenseError::ExpenseLocked,
+        constraint = removed_expense.status != ExpenseStatus::Approved @ ExpenseError::ExpenseApproved
+    )]
+    pub removed_expense: Account<'info, ExpenseAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // The indices the expenses are counted in, optional like in `UpdateAmount`. Both are
+    // the same account when the expenses share their category.
+    #[account(
+        mut,
+        seeds = [b"cat_index", authority.key().as_ref(), &[kept_expense.category as u8]],
+        bump
+    )]
+    pub kept_category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(
+        mut,
+        seeds = [b"cat_index", authority.key().as_ref(), &[removed_expense.category as u8]],
+        bump
+    )]
+    pub removed_category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merchant_name: String)]
+pub struct PayExpense<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = ExpenseAccount::space(&merchant_name, ""),
+        seeds = [b"expense", authority.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Like in `InitializeExpense`
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = MonthlyRollup::SPACE,
+        seeds = [
+            b"rollup",
+            authority.key().as_ref(),
+            year_month(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub rollup: Account<'info, MonthlyRollup>,
+
+    // The expense is created in the default category of the user
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[user_stats.default_category as u8]],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    /// CHECK: The budget PDA of the authority, required rather than optional so a payment
+    /// can't leave out the mint check and the spending of an existing budget. It is only
+    /// read and written back by the handler when the budget exists.
+    #[account(
+        mut,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: UncheckedAccount<'info>,
+
+    // The token the expense is paid in
+    pub mint: Account<'info, Mint>,
+
+    // The authority's associated token account the payment is taken from
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = authority
+    )]
+    pub source: Account<'info, TokenAccount>,
+
+    // The merchant's token account receiving the payment
+    #[account(mut, token::mint = mint)]
+    pub destination: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeExpensesBatch<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Like in `InitializeExpense`, the expenses are all created in the current month
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = MonthlyRollup::SPACE,
+        seeds = [
+            b"rollup",
+            authority.key().as_ref(),
+            year_month(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub rollup: Account<'info, MonthlyRollup>,
+
+    // The index of the default category of the user, which every expense is filed under
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[user_stats.default_category as u8]],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    #[account(
+        mut,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DeleteExpensesBatch<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, amount: u64, participants: Vec<Pubkey>)]
+pub struct CreateEvenSplitExpense<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The same account as in `CreateSplitExpense`, one share per participant
+    #[account(
+        init,
+        payer = authority,
+        space = SplitExpense::space(participants.len()),
+        seeds = [b"split", authority.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub split_expense: Account<'info, SplitExpense>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ModifyExpensesBatch<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Optional, only read for the maximum amount
+    #[account(
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SumRange<'info> {
+    // The expenses to sum up are passed as remaining accounts, all owned by the authority
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReconcileStats<'info> {
+    // The expenses to recompute the stats from are passed as remaining accounts, all
+    // owned by the authority
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, amount: u64, shares: Vec<Share>)]
+pub struct CreateSplitExpense<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = SplitExpense::space(shares.len()),
+        seeds = [b"split", authority.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub split_expense: Account<'info, SplitExpense>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(group_id: u64)]
+pub struct CreateGroup<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = Group::space(1),
+        seeds = [b"group", authority.key().as_ref(), group_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub group: Account<'info, Group>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(group_id: u64)]
+pub struct AddMember<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Grown by one member, the creator pays the rent
+    #[account(
+        mut,
+        realloc = capped_space(Group::space(group.members.len() + 1))?,
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"group", authority.key().as_ref(), group_id.to_le_bytes().as_ref()],
+        bump = group.bump,
+        constraint = group.creator == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub group: Account<'info, Group>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct DeleteGroupExpense<'info> {
+    #[account(mut)]
+    pub member: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"group", group.creator.as_ref(), group.id.to_le_bytes().as_ref()],
+        bump = group.bump
+    )]
+    pub group: Account<'info, Group>,
+
+    #[account(
+        mut,
+        close = member,
+        seeds = [b"group_expense", group.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump,
+        constraint = group_expense.paid_by == member.key() @ ExpenseError::Unauthorized,
+        constraint = !group_expense.settled @ ExpenseError::ExpenseSettled
+    )]
+    pub group_expense: Account<'info, GroupExpense>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SettleGroup<'info> {
+    pub member: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"group", group.creator.as_ref(), group.id.to_le_bytes().as_ref()],
+        bump = group.bump,
+        constraint = group.members.contains(&member.key()) @ ExpenseError::NotGroupMember
+    )]
+    pub group: Account<'info, Group>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merchant_name: String)]
+pub struct CreateGroupExpense<'info> {
+    #[account(mut)]
+    pub member: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"group", group.creator.as_ref(), group.id.to_le_bytes().as_ref()],
+        bump = group.bump,
+        constraint = group.members.contains(&member.key()) @ ExpenseError::NotGroupMember
+    )]
+    pub group: Account<'info, Group>,
+
+    #[account(
+        init,
+        payer = member,
+        space = GroupExpense::space(&merchant_name),
+        seeds = [b"group_expense", group.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub group_expense: Account<'info, GroupExpense>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merchant_name: String)]
+pub struct CreateRecurring<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = RecurringTemplate::space(&merchant_name),
+        seeds = [b"recurring", authority.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub template: Account<'info, RecurringTemplate>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: u64, new_expense_id: u64)]
+pub struct InstantiateFromTemplate<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"recurring", authority.key().as_ref(), template_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub template: Account<'info, RecurringTemplate>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = ExpenseAccount::space(&template.merchant_name, ""),
+        seeds = [b"expense", authority.key().as_ref(), new_expense_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Like in `InitializeExpense`
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = MonthlyRollup::SPACE,
+        seeds = [
+            b"rollup",
+            authority.key().as_ref(),
+            year_month(Clock::get()?.unix_timestamp).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub rollup: Account<'info, MonthlyRollup>,
+
+    // The expense is created in the default category of the user
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[user_stats.default_category as u8]],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    #[account(
+        mut,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = Config::SPACE,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetApprovalPolicy<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetApprovers<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetReimbursementPayer<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetDuplicateGuard<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetRequireReceiptToDelete<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetLogLevel<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetOracle<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinModifyInterval<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(currency: [u8; 3])]
+pub struct SetFxFeed<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump,
+        has_one = admin @ ExpenseError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = FxFeed::SPACE,
+        seeds = [b"fx_feed", currency.as_ref(), config.base_currency.as_ref()],
+        bump
+    )]
+    pub fx_feed: Account<'info, FxFeed>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(name: String)]
+pub struct RegisterMerchant<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Registering a name that normalizes to an already registered one fails,
+    // the account exists already
+    #[account(
+        init,
+        payer = payer,
+        space = Merchant::space(&normalize_merchant_name(&name)),
+        seeds = [b"merchant", merchant_name_hash(&normalize_merchant_name(&name)).as_ref()],
+        bump
+    )]
+    pub merchant: Account<'info, Merchant>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetBudget<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = Budget::SPACE,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Account<'info, Budget>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUser<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Optional, a user that never set a budget has none
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"budget", authority.key().as_ref()],
+        bump
+    )]
+    pub budget: Option<Account<'info, Budget>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id : u64)]
+pub struct SetDelegate<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id : u64)]
+pub struct UpdateReceiptHash<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, cid: String)]
+pub struct AttachReceipt<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        realloc = capped_space(expense_account.size() - expense_account.receipt_cid.len() + cid.len())?,
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateExpense<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Possibly in an older layout that doesn't decode as an `ExpenseAccount`,
+    /// the handler pads and decodes it and verifies it is the expense PDA.
+    #[account(mut, owner = crate::ID)]
+    pub expense_account: UncheckedAccount<'info>,
+
+    /// CHECK: The owner of the expense, only used for the seeds below, the handler
+    /// checks it against the decoded expense.
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = UserStats::SPACE,
+        seeds = [b"stats", owner.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    /// CHECK: The index of the category of an unversioned expense, which is added to it.
+    /// Its seeds depend on the decoded category, so the handler derives, creates and
+    /// writes it.
+    #[account(mut)]
+    pub category_index: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id : u64)]
+pub struct LockExpense<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id : u64)]
+pub struct MarkPaid<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id : u64)]
+pub struct ApplyRefund<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Optional like in `UpdateAmount`
+    #[account(
+        mut,
+        seeds = [b"cat_index", authority.key().as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id : u64)]
+pub struct ArchiveExpense<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct SetFlag<'info> {
+    pub authority: Signer<'info>,
+
+    // Authorized like `ModifyExpense`. The flag is review metadata rather than part
+    // of the expense, so locked expenses can be flagged too.
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.is_owner_or_delegate(&authority.key()) @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, tag: String)]
+pub struct AddTag<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        realloc = capped_space(expense_account.size() + 4 + tag.len())?,
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, tag: String)]
+pub struct RemoveTag<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Saturating, so an unknown tag reaches the handler and fails with `TagNotFound`
+    #[account(
+        mut,
+        realloc = expense_account.size().saturating_sub(4 + tag.len()),
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct AddViewer<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        realloc = capped_space(expense_account.size() + 32)?,
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct RemoveViewer<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Saturating, so an unknown viewer reaches the handler and fails with `ViewerNotFound`
+    #[account(
+        mut,
+        realloc = expense_account.size().saturating_sub(32),
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, ciphertext: Vec<u8>)]
+pub struct SetEncryptedNote<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        realloc = capped_space(expense_account.size() - expense_account.encrypted_note.len() + ciphertext.len())?,
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, key: String, value: String)]
+pub struct SetMetadata<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Sized for the new entry, less the one it overwrites if the key is already set
+    #[account(
+        mut,
+        realloc = capped_space(expense_account.size() - expense_account.metadata_space(&key) + (4 + key.len()) + (4 + value.len()))?,
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, key: String)]
+pub struct RemoveMetadata<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // An unknown key keeps the size, so it reaches the handler and fails with
+    // `MetadataKeyNotFound`
+    #[account(
+        mut,
+        realloc = expense_account.size() - expense_account.metadata_space(&key),
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct AddLineItem<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = ExpenseLineItems::space(&[]),
+        seeds = [b"items", expense_account.key().as_ref()],
+        bump
+    )]
+    pub line_items: Account<'info, ExpenseLineItems>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, index: u32)]
+pub struct RemoveLineItem<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // An unknown index keeps the size, so it reaches the handler and fails with
+    // `LineItemNotFound`
+    #[account(
+        mut,
+        realloc = line_items.size() - line_items.items.get(index as usize).map_or(0, LineItem::space),
+        realloc::payer = authority,
+        realloc::zero = false,
+        seeds = [b"items", expense_account.key().as_ref()],
+        bump
+    )]
+    pub line_items: Account<'info, ExpenseLineItems>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct VerifyExpense<'info> {
+    // Read-only, anyone can verify an expense
+    #[account(
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"items", expense_account.key().as_ref()], bump)]
+    pub line_items: Account<'info, ExpenseLineItems>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct ExportExpense<'info> {
+    // Read-only like in `VerifyExpense`
+    #[account(
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveExpense<'info> {
+    #[account(mut)]
+    pub approver: Signer<'info>,
+
+    #[account(mut)]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = approver,
+        space = MultiApproval::SPACE,
+        seeds = [b"approval", expense_account.key().as_ref()],
+        bump
+    )]
+    pub multi_approval: Account<'info, MultiApproval>,
+
+    // Only the approvers the admin designated can review expenses
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.approvers.contains(&approver.key()) @ ExpenseError::Unauthorized,
+        constraint = !config.paused @ ExpenseError::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct RequestReimbursement<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct MarkReimbursed<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.reimbursement_payer == payer.key() @ ExpenseError::Unauthorized,
+        constraint = !config.paused @ ExpenseError::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    /// CHECK: Only credited with the reimbursement, must be the owner of the expense.
+    #[account(mut, address = expense_account.owner @ ExpenseError::Unauthorized)]
+    pub owner: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, new_owner: Pubkey)]
+pub struct TransferOwnership<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Derived from the creator like in `ModifyExpense`, after a transfer the signer is no
+    // longer the key the PDA was derived from
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // The expense moves from the stats of the current owner to the ones of the new owner,
+    // created for a new owner without expenses
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", new_owner.as_ref()],
+        bump
+    )]
+    pub new_owner_stats: Account<'info, UserStats>,
+
+    // The expense moves to the index of the new owner like between the stats, the current
+    // index is optional like in `UpdateAmount`
+    #[account(
+        mut,
+        seeds = [b"cat_index", authority.key().as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", new_owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub new_owner_category_index: Account<'info, CategoryIndex>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct ProposeTransfer<'info> {
+    pub authority: Signer<'info>,
+
+    // Authorized like `TransferOwnership`, the ownership only changes once accepted
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.owner == authority.key() @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetRecoveryKey<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinAmount<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetDefaultCategory<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, new_owner: Pubkey)]
+pub struct RecoverExpense<'info> {
+    #[account(mut)]
+    pub recovery_key: Signer<'info>,
+
+    // Derived from the creator like in `TransferOwnership`
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // The stats of the current owner, holding its recovery key
+    #[account(
+        mut,
+        seeds = [b"stats", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Like in `TransferOwnership`, the recovery key pays for new stats
+    #[account(
+        init_if_needed,
+        payer = recovery_key,
+        space = UserStats::SPACE,
+        seeds = [b"stats", new_owner.as_ref()],
+        bump
+    )]
+    pub new_owner_stats: Account<'info, UserStats>,
+
+    // Like in `TransferOwnership`
+    #[account(
+        mut,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(
+        init_if_needed,
+        payer = recovery_key,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", new_owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub new_owner_category_index: Account<'info, CategoryIndex>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct AcceptTransfer<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // Derived from the creator like in `TransferOwnership`
+    #[account(
+        mut,
+        seeds = [b"expense", expense_account.tenant_seed(), expense_account.creator.as_ref(), expense_account.scope_seed(), id.to_le_bytes().as_ref()],
+        bump = expense_account.bump,
+        constraint = expense_account.pending_owner == Some(authority.key()) @ ExpenseError::Unauthorized
+    )]
+    pub expense_account: Account<'info, ExpenseAccount>,
+
+    // The stats of the current owner and of the signer, the new owner
+    #[account(
+        mut,
+        seeds = [b"stats", expense_account.owner.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::SPACE,
+        seeds = [b"stats", authority.key().as_ref()],
+        bump
+    )]
+    pub new_owner_stats: Account<'info, UserStats>,
+
+    // Like in `TransferOwnership`
+    #[account(
+        mut,
+        seeds = [b"cat_index", expense_account.owner.as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub category_index: Option<Account<'info, CategoryIndex>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = CategoryIndex::SPACE,
+        seeds = [b"cat_index", authority.key().as_ref(), &[expense_account.category as u8]],
+        bump
+    )]
+    pub new_owner_category_index: Account<'info, CategoryIndex>,
+
+    #[account(seeds = [b"config"], bump, constraint = !config.paused @ ExpenseError::ProgramPaused)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// An on-chain account.
+#[account]
+#[derive(Default)]
+pub struct ExpenseAccount {
+    // Expense entries unique ID
+    pub id: u64,
+    // The owner value
+    pub owner: Pubkey,
+    // The merchant name
+    pub merchant_name: String,
+    // The amount as entered, before refunds, see `net_amount`
+    pub amount: u64,
+    // Unix timestamp of the creation, never changes afterwards
+    pub created_at: i64,
+    // Unix timestamp of the last modification
+    pub updated_at: i64,
+    // The expense category
+    pub category: ExpenseCategory,
+    // ISO 4217 style currency code of the amount, e.g. `USD`
+    pub currency: [u8; 3],
+    // Optional free-text note, empty when not provided
+    pub note: String,
+    // The SPL token mint the expense was paid with, if paid via `pay_expense`
+    pub mint: Option<Pubkey>,
+    // The review status, `Pending` until approved or rejected
+    pub status: ExpenseStatus,
+    // The key that reviewed the expense, if reviewed
+    pub approver: Option<Pubkey>,
+    // Hash of the receipt backing the expense, all zeros when there is no receipt
+    pub receipt_hash: [u8; 32],
+    // How the expense was paid
+    pub payment_method: PaymentMethod,
+    // A third party allowed to modify (but not delete) the expense
+    pub delegate: Option<Pubkey>,
+    // Free-form labels, unique within the expense
+    pub tags: Vec<String>,
+    // Archived expenses are kept but excluded from the stats
+    pub is_archived: bool,
+    // Unix timestamp the bill is due, zero when the expense is not a bill
+    pub due_date: i64,
+    // Whether the bill has been paid
+    pub is_paid: bool,
+    // Unix timestamp of the payment, zero until paid
+    pub paid_at: i64,
+    // The layout version, see `EXPENSE_ACCOUNT_VERSION`, zero for accounts created
+    // before the field existed
+    pub version: u8,
+    // The canonical bump of the PDA, so later instructions don't search for it again
+    pub bump: u8,
+    // Sum of the refunds applied by `apply_refund`. Kept when the amount is modified.
+    pub total_refunded: u64,
+    // The registered merchant of the expense, if linked
+    pub merchant: Option<Pubkey>,
+    // Locked expenses are immutable, locking can't be undone
+    pub is_locked: bool,
+    // Number of successful `modify_expense` calls, for auditing
+    pub modification_count: u32,
+    // IPFS content identifier of the receipt, empty when not attached
+    pub receipt_cid: String,
+    // Flagged for a follow-up review by a bookkeeper
+    pub flagged: bool,
+    // Rate converting the amount to the base currency, scaled by `FX_RATE_SCALE`,
+    // captured at creation
+    pub fx_rate: u64,
+    // The amount converted with `fx_rate`, what the user stats add up. Zero for
+    // transfers, see `is_transfer`.
+    pub amount_in_base: u64,
+    // Whether the company was asked to, or did, reimburse the expense
+    pub reimbursement_status: ReimbursementStatus,
+    // Free-form key-value pairs, keys are unique within the expense
+    pub metadata: Vec<MetadataEntry>,
+    // Keys the owner shared the expense with. Account data is public anyway, this is an
+    // access list for front-ends and indexers to honor, not enforced on-chain.
+    pub viewers: Vec<Pubkey>,
+    // The key `propose_transfer` offered the expense to, until it accepts or the owner
+    // cancels
+    pub pending_owner: Option<Pubkey>,
+    // Ciphertext of a private note, encrypted client-side (e.g. XChaCha20-Poly1305) and
+    // never decrypted by the program, empty when there is none
+    pub encrypted_note: Vec<u8>,
+    // The nonce `encrypted_note` was encrypted with, all zeros when there is no note
+    pub nonce: [u8; 24],
+    // The tax rate applicable to the amount in basis points, at most `MAX_TAX_RATE_BPS`
+    pub tax_rate_bps: u16,
+    // The tax part of the net amount at `tax_rate_bps`, kept in sync with the amount
+    pub tax_amount: u64,
+    // A transfer between own accounts rather than spending, set by `initialize_transfer`.
+    // Counted in the user stats but not in the totals or budgets.
+    pub is_transfer: bool,
+    // The namespace of a multi-tenant deployment the expense was created in by
+    // `initialize_expense_tenant`, part of its seeds. Zeros for the other expenses.
+    pub tenant: [u8; 8],
+    // The idempotency key the client created the expense with, zeros without one, see
+    // `SubmissionGuard`
+    pub client_ref: [u8; 16],
+    // The key that created the expense, which its PDA is derived from. Unlike `owner` it
+    // doesn't change when the expense is transferred.
+    pub creator: Pubkey,
+    // The category an expense created by `initialize_expense_scoped` is scoped to, part
+    // of its seeds. Unlike `category` it doesn't change when the expense is modified.
+    pub scope: Option<u8>,
+    // Whether the expense is counted in the `CategoryIndex` of its owner and category.
+    // The instructions changing its amount, category or owner then require that index.
+    pub in_category_index: bool,
+    // The amount net of refunds, `amount - total_refunded`, and zero when the amount is
+    // modified below the refunds. `amount_in_base` is converted from it.
+    pub net_amount: u64,
+}
+
+impl ExpenseAccount {
+    /// The account space needed to store a new expense with the given strings,
+    /// so accounts are sized to their content instead of a fixed reservation.
+    pub fn space(merchant_name: &str, note: &str) -> usize {
+        8 // discriminator
+            + 8 // id
+            + 32 // owner
+            + (4 + merchant_name.len()) // merchant_name
+            + 8 // amount
+            + 8 // created_at
+            + 8 // updated_at
+            + 1 // category
+            + 3 // currency
+            + (4 + note.len()) // note
+            + (1 + 32) // mint
+            + 1 // status
+            + (1 + 32) // approver
+            + 32 // receipt_hash
+            + 1 // payment_method
+            + (1 + 32) // delegate
+            + 4 // tags, none yet
+            + 1 // is_archived
+            + 8 // due_date
+            + 1 // is_paid
+            + 8 // paid_at
+            + 1 // version
+            + 1 // bump
+            + 8 // total_refunded
+            + (1 + 32) // merchant
+            + 1 // is_locked
+            + 4 // modification_count
+            + 4 // receipt_cid, none yet
+            + 1 // flagged
+            + 8 // fx_rate
+            + 8 // amount_in_base
+            + 1 // reimbursement_status
+            + 4 // metadata, none yet
+            + 4 // viewers, none yet
+            + (1 + 32) // pending_owner
+            + 4 // encrypted_note, none yet
+            + 24 // nonce
+            + 2 // tax_rate_bps
+            + 8 // tax_amount
+            + 1 // is_transfer
+            + 8 // tenant
+            + 16 // client_ref
+            + 32 // creator
+            + (1 + 1) // scope
+            + 1 // in_category_index
+            + 8 // net_amount
+    }
+
+    /// The account space needed to store the expense with its current content.
+    pub fn size(&self) -> usize {
+        Self::space(&self.merchant_name, &self.note)
+            + self.tags.iter().map(|tag| 4 + tag.len()).sum::<usize>()
+            + self.receipt_cid.len()
+            + self
+                .metadata
+                .iter()
+                .map(MetadataEntry::space)
+                .sum::<usize>()
+            + 32 * self.viewers.len()
+            + self.encrypted_note.len()
+    }
+
+    /// The space the metadata entry with the given key takes, zero if there is none.
+    pub fn metadata_space(&self, key: &str) -> usize {
+        self.metadata
+            .iter()
+            .find(|entry| entry.key == key)
+            .map_or(0, MetadataEntry::space)
+    }
+
+    /// Sets the amount before refunds, keeping `net_amount`, `amount_in_base` and
+    /// `tax_amount` in sync.
+    pub fn set_amount(&mut self, amount: u64) -> Result<()> {
+        let net_amount = amount.saturating_sub(self.total_refunded);
+        self.amount_in_base = if self.is_transfer {
+            0
+        } else {
+            to_base_amount(net_amount, self.fx_rate)?
+        };
+        self.tax_amount = tax_amount(net_amount, self.tax_rate_bps)?;
+        self.net_amount = net_amount;
+        self.amount = amount;
+
+        Ok(())
+    }
+
+    /// Records a successful modification at `now`.
+    pub fn record_modification(&mut self, now: i64) -> Result<()> {
+        self.updated_at = now;
+        self.modification_count = self
+            .modification_count
+            .checked_add(1)
+            .ok_or(ExpenseError::ArithmeticOverflow)?;
+
+        Ok(())
+    }
+
+    /// Whether `key` may modify the expense.
+    pub fn is_owner_or_delegate(&self, key: &Pubkey) -> bool {
+        self.owner == *key || self.delegate.as_ref() == Some(key)
+    }
+
+    /// The tenant part of the seeds, empty for an expense outside a tenant.
+    pub fn tenant_seed(&self) -> &[u8] {
+        if self.tenant == [0; 8] {
+            &[]
+        } else {
+            &self.tenant
+        }
+    }
+
+    /// The scope part of the seeds, empty for an expense that isn't scoped.
+    pub fn scope_seed(&self) -> &[u8] {
+        self.scope.as_slice()
+    }
+
+    /// Derives the PDA of the expense with `id` from its stored seeds, the empty ones
+    /// adding nothing, so it is the address of whichever instruction created it.
+    pub fn find_pda(&self, id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                b"expense",
+                self.tenant_seed(),
+                self.creator.as_ref(),
+                self.scope_seed(),
+                id.to_le_bytes().as_ref(),
+            ],
+            program_id,
+        )
+    }
+}
+
+/// How an expense was paid.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PaymentMethod {
+    Cash,
+    Card,
+    Crypto,
+    BankTransfer,
+    #[default]
+    Other,
+}
+
+/// The review status of an expense.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExpenseStatus {
+    #[default]
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// The program logs the instructions write, see `Config::log_level`. Errors are logged
+/// by Anchor regardless.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogLevel {
+    // Only the errors
+    #[default]
+    Error,
+    // Also informational messages, e.g. for debugging
+    Info,
+}
+
+/// Where an expense fronted by an employee is in the reimbursement workflow.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReimbursementStatus {
+    #[default]
+    NotRequested,
+    Requested,
+    Reimbursed,
+}
+
+/// Per-user aggregates, so clients don't have to read every expense to compute totals.
+#[account]
+#[derive(Default)]
+pub struct UserStats {
+    // Sum of the amounts in the base currency, net of refunds, of the expenses of the
+    // user. Archived expenses and transfers are left out.
+    pub total_spent: u64,
+    // Number of expenses of the user, transfers included but not the archived ones
+    pub expense_count: u64,
+    // The ID `initialize_expense_auto` assigns next, the auto-assigned IDs are `0..next_id`.
+    // Expenses created with an explicit ID don't advance it.
+    pub next_id: u64,
+    // Opt-in key allowed to reassign the expenses of the user with `recover_expense`,
+    // e.g. after the user lost their wallet
+    pub recovery_key: Option<Pubkey>,
+    // The amount of the largest expense of the user, the average is
+    // `total_spent / expense_count`
+    pub largest_amount: u64,
+    // Set once the largest expense was deleted or reduced, `largest_amount` is then only
+    // an upper bound until a larger expense replaces it. The actual maximum needs a rescan.
+    pub largest_amount_stale: bool,
+    // Expenses created with a smaller amount in the base currency are rejected, so
+    // trivial purchases aren't tracked. Zero means no minimum.
+    pub min_amount: u64,
+    // The category `initialize_expense_auto` files expenses under when none is given
+    pub default_category: ExpenseCategory,
+    // Number of expense accounts of the user, archived ones included. Only creating,
+    // deleting and transferring expenses change it, so `reconcile_stats` can tell
+    // whether it was passed all of them.
+    pub account_count: u64,
+}
+
+impl UserStats {
+    pub const SPACE: usize = 8 + 8 + 8 + 8 + (1 + 32) + 8 + 1 + 8 + 1 + 8;
+
+    /// Fails with `ExpenseError::AmountBelowMinimum` when a new expense of `amount` is
+    /// below the minimum amount of the user.
+    pub fn check_min_amount(&self, amount: u64) -> Result<()> {
+        require!(amount >= self.min_amount, ExpenseError::AmountBelowMinimum);
+
+        Ok(())
+    }
+
+    /// Adds a newly created expense to the aggregates,
+    /// failing when the user already has the maximum number of expenses.
+    pub fn record_expense(&mut self, amount: u64) -> Result<()> {
+        require!(
+            self.expense_count < MAX_EXPENSES_PER_USER,
+            ExpenseError::TooManyExpenses
+        );
+        self.expense_count = checked_add(self.expense_count, 1)?;
+        self.total_spent = checked_add(self.total_spent, amount)?;
+        self.raise_largest_amount(amount);
+
+        Ok(())
+    }
+
+    /// Counts a newly created expense account, see `account_count`.
+    pub fn add_account(&mut self) -> Result<()> {
+        self.account_count = checked_add(self.account_count, 1)?;
+
+        Ok(())
+    }
+
+    /// Uncounts a closed expense account, see `account_count`.
+    pub fn remove_account(&mut self) -> Result<()> {
+        self.account_count = checked_sub(self.account_count, 1)?;
+
+        Ok(())
+    }
+
+    /// Replaces the old amount of a modified expense with the new one.
+    pub fn replace_amount(&mut self, old_amount: u64, new_amount: u64) -> Result<()> {
+        self.total_spent = checked_add(checked_sub(self.total_spent, old_amount)?, new_amount)?;
+        if new_amount < old_amount && old_amount == self.largest_amount {
+            self.largest_amount_stale = true;
+        }
+        self.raise_largest_amount(new_amount);
+
+        Ok(())
+    }
+
+    /// Removes a deleted expense from the aggregates.
+    pub fn remove_expense(&mut self, amount: u64) -> Result<()> {
+        self.expense_count = checked_sub(self.expense_count, 1)?;
+        self.total_spent = checked_sub(self.total_spent, amount)?;
+        if self.expense_count == 0 {
+            self.largest_amount = 0;
+            self.largest_amount_stale = false;
+        } else if amount == self.largest_amount {
+            self.largest_amount_stale = true;
+        }
+
+        Ok(())
+    }
+
+    /// Takes `amount` as the largest amount if it is at least the current one, which is
+    /// exact again even when it was stale.
+    fn raise_largest_amount(&mut self, amount: u64) {
+        if amount >= self.largest_amount {
+            self.largest_amount = amount;
+            self.largest_amount_stale = false;
+        }
+    }
+}
+
+/// A single expense of `initialize_expenses_batch`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct ExpenseInput {
+    pub id: u64,
+    pub merchant_name: String,
+    pub amount: u64,
+}
+
+/// A single update of `modify_expenses_batch`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct ExpenseUpdate {
+    pub id: u64,
+    pub merchant_name: String,
+    pub amount: u64,
+}
+
+/// A key-value pair of the metadata of an expense.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct MetadataEntry {
+    pub key: String,
+    pub value: String,
+}
+
+impl MetadataEntry {
+    /// The space the entry takes in `ExpenseAccount`.
+    pub fn space(&self) -> usize {
+        (4 + self.key.len()) + (4 + self.value.len())
+    }
+}
+
+/// A component of an expense, e.g. one item of a receipt.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct LineItem {
+    pub description: String,
+    pub amount: u64,
+}
+
+impl LineItem {
+    /// The space the line item takes in `ExpenseLineItems`.
+    pub fn space(&self) -> usize {
+        (4 + self.description.len()) + 8
+    }
+}
+
+/// The line items of an expense, adding up to at most its amount.
+#[account]
+#[derive(Default)]
+pub struct ExpenseLineItems {
+    // The expense account the items belong to
+    pub expense: Pubkey,
+    pub items: Vec<LineItem>,
+}
+
+impl ExpenseLineItems {
+    /// The account space needed to store the given line items.
+    pub fn space(items: &[LineItem]) -> usize {
+        8 + 32 + 4 + items.iter().map(LineItem::space).sum::<usize>()
+    }
+
+    /// The account space needed to store the current line items.
+    pub fn size(&self) -> usize {
+        Self::space(&self.items)
+    }
+
+    /// The sum of the amounts of the line items.
+    pub fn total(&self) -> Result<u64> {
+        self.items
+            .iter()
+            .try_fold(0, |total, item| checked_add(total, item.amount))
+    }
+}
+
+/// An expense shared among several participants.
+#[account]
+#[derive(Default)]
+pub struct SplitExpense {
+    // Split expense unique ID
+    pub id: u64,
+    // The owner value
+    pub owner: Pubkey,
+    // The total amount, equal to the sum of the shares
+    pub amount: u64,
+    // The part of the amount each participant owes
+    pub shares: Vec<Share>,
+}
+
+impl SplitExpense {
+    /// The account space needed to store a split among `participants` participants.
+    pub fn space(participants: usize) -> usize {
+        8 + 8 + 32 + 8 + 4 + participants * Share::SPACE
+    }
+}
+
+/// The part of a split expense a single participant owes.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Share {
+    pub participant: Pubkey,
+    pub amount: u64,
+}
+
+impl Share {
+    pub const SPACE: usize = 32 + 8;
+}
+
+/// A group of users sharing expenses, e.g. roommates.
+#[account]
+#[derive(Default)]
+pub struct Group {
+    // Unique ID of the group among the groups of the creator
+    pub id: u64,
+    // The user that created the group and manages its members
+    pub creator: Pubkey,
+    // The members, the creator first
+    pub members: Vec<Pubkey>,
+    // Sum of the amounts of all expenses of the group
+    pub total_spent: u64,
+    // The canonical bump of the PDA
+    pub bump: u8,
+    // Number of expenses of the group
+    pub expense_count: u64,
+    // Net balance of each member as of the last settlement, in the order of `members`,
+    // positive when the member is owed, negative when the member owes
+    pub balances: Vec<i64>,
+}
+
+impl Group {
+    /// The account space needed to store a group of `members` members,
+    /// including their balances.
+    pub fn space(members: usize) -> usize {
+        8 + 8 + 32 + (4 + members * 32) + 8 + 1 + 8 + (4 + members * 8)
+    }
+}
+
+/// An expense of a group, attributed to the group rather than to the member that paid it.
+#[account]
+#[derive(Default)]
+pub struct GroupExpense {
+    // Unique ID of the expense within the group
+    pub id: u64,
+    // The group the expense belongs to
+    pub group: Pubkey,
+    // The member that paid and recorded the expense
+    pub paid_by: Pubkey,
+    // The merchant name
+    pub merchant_name: String,
+    // The spent amount
+    pub amount: u64,
+    // Unix timestamp of the creation
+    pub created_at: i64,
+    // Set once the expense was factored into `settle_group`, it can't be deleted then
+    pub settled: bool,
+}
+
+impl GroupExpense {
+    /// The account space needed to store a group expense with the given merchant name.
+    pub fn space(merchant_name: &str) -> usize {
+        8 + 8 + 32 + 32 + (4 + merchant_name.len()) + 8 + 8 + 1
+    }
+}
+
+/// A template to re-create a recurring expense, e.g. a subscription.
+#[account]
+#[derive(Default)]
+pub struct RecurringTemplate {
+    // Template unique ID
+    pub id: u64,
+    // The owner value
+    pub owner: Pubkey,
+    // The merchant name of the created expenses
+    pub merchant_name: String,
+    // The amount of the created expenses
+    pub amount: u64,
+    // The minimum number of seconds between two instantiations
+    pub interval_seconds: i64,
+    // Unix timestamp of the last instantiation, 0 if never instantiated
+    pub last_instantiated_at: i64,
+}
+
+impl RecurringTemplate {
+    /// The account space needed to store a template with the given merchant name.
+    pub fn space(merchant_name: &str) -> usize {
+        8 + 8 + 32 + (4 + merchant_name.len()) + 8 + 8 + 8
+    }
+}
+
+/// A registered merchant, its address is the stable merchant id.
+#[account]
+#[derive(Default)]
+pub struct Merchant {
+    // The canonical name, see `normalize_merchant_name`
+    pub name: String,
+    // Hash of the canonical name, the seed of the merchant PDA
+    pub name_hash: [u8; 32],
+    // The key that registered the merchant
+    pub registered_by: Pubkey,
+}
+
+impl Merchant {
+    /// The account space needed to store a merchant with the given canonical name.
+    pub fn space(name: &str) -> usize {
+        8 + (4 + name.len()) + 32 + 32
+    }
+}
+
+/// The oracle account the price of a currency in the base currency is read from.
+#[account]
+#[derive(Default)]
+pub struct FxFeed {
+    pub currency: [u8; 3],
+    pub base_currency: [u8; 3],
+    // The oracle account `pin_fx_rate` accepts for the pair
+    pub oracle: Pubkey,
+}
+
+impl FxFeed {
+    pub const SPACE: usize = 8 + 3 + 3 + 32;
+}
+
+/// The global program settings.
+#[account]
+#[derive(Default)]
+pub struct Config {
+    // The key allowed to change the config
+    pub admin: Pubkey,
+    // Kill switch, rejects every instruction changing state but the admin ones while set
+    pub paused: bool,
+    // Number of distinct approvers an expense above `approval_threshold` needs
+    pub required_approvals: u8,
+    // Expenses with a larger amount in the base currency need `required_approvals`
+    // approvals, others one
+    pub approval_threshold: u64,
+    // The company key that reimburses expenses, the default key while there is none
+    pub reimbursement_payer: Pubkey,
+    // Opt-in, rejects a second expense with the same merchant and amount on the same day
+    pub duplicate_guard: bool,
+    // Seconds `modify_expense` has to wait after the last change of an expense, a
+    // debounce for clients that repeat modifications. Zero disables it.
+    pub min_modify_interval: u32,
+    // Compliance mode, only expenses with a receipt hash can be deleted
+    pub require_receipt_to_delete: bool,
+    // The program owning the price accounts `pin_fx_rate` reads, the default key while
+    // there is none
+    pub oracle_program: Pubkey,
+    // Seconds an oracle price may be old for `pin_fx_rate`
+    pub max_oracle_age: u32,
+    // Which program logs the instructions write, informational ones cost compute
+    pub log_level: LogLevel,
+    // The keys allowed to approve and reject expenses, none until the admin sets them
+    pub approvers: Vec<Pubkey>,
+    // The currency the amounts in the base currency are in, set once at initialization
+    pub base_currency: [u8; 3],
+}
+
+impl Config {
+    pub const SPACE: usize =
+        8 + 32 + 1 + 1 + 8 + 32 + 1 + 4 + 1 + 32 + 4 + 1 + (4 + 32 * MAX_APPROVERS) + 3;
+
+    /// Fails with `ExpenseError::ReceiptRequired` when the config requires a receipt to
+    /// delete an expense and the expense has none.
+    pub fn check_deletable(&self, expense_account: &ExpenseAccount) -> Result<()> {
+        require!(
+            !self.require_receipt_to_delete || expense_account.receipt_hash != [0; 32],
+            ExpenseError::ReceiptRequired
+        );
+
+        Ok(())
+    }
+
+    /// The number of approvals an expense of `amount`, in the base currency, needs to be
+    /// approved.
+    pub fn required_approvals_for(&self, amount: u64) -> usize {
+        if amount > self.approval_threshold {
+            self.required_approvals.max(1) as usize
+        } else {
+            1
+        }
+    }
+}
+
+/// Marks that a user created an expense with a merchant and amount on a day, see
+/// `Config::duplicate_guard`.
+#[account]
+#[derive(Default)]
+pub struct DuplicateGuard {
+    // The expense that took the guard
+    pub expense: Pubkey,
+}
+
+impl DuplicateGuard {
+    pub const SPACE: usize = 8 + 32;
+}
+
+/// Marks that a user created an expense with a client reference, so a retried submission
+/// with the same reference fails instead of creating the expense twice.
+#[account]
+#[derive(Default)]
+pub struct SubmissionGuard {
+    // The expense that took the guard
+    pub expense: Pubkey,
+}
+
+impl SubmissionGuard {
+    pub const SPACE: usize = 8 + 32;
+}
+
+/// The approvals collected by an expense so far.
+#[account]
+#[derive(Default)]
+pub struct MultiApproval {
+    // The approved expense account
+    pub expense: Pubkey,
+    // The distinct keys that approved the expense, in order
+    pub approvers: Vec<Pubkey>,
+}
+
+impl MultiApproval {
+    pub const SPACE: usize = 8 + 32 + (4 + 32 * MAX_REQUIRED_APPROVALS as usize);
+}
+
+/// The spending of a user in a calendar month, for charts over time without reading
+/// every expense. Expenses are added when created by `initialize_expense`, with their
+/// amount in the base currency at that time.
+#[account]
+#[derive(Default)]
+pub struct MonthlyRollup {
+    // The calendar month, see `year_month`
+    pub period: u32,
+    // Sum of the amounts in the base currency of the expenses created in the month, see
+    // `ExpenseAccount::amount_in_base`
+    pub total: u64,
+    // Number of expenses created in the month
+    pub count: u64,
+}
+
+impl MonthlyRollup {
+    pub const SPACE: usize = 8 + 4 + 8 + 8;
+}
+
+/// The expenses of a user in one category, so category aggregates are read without
+/// scanning the expenses. Every instruction changing the amount, category or owner of an
+/// indexed expense updates it, see `ExpenseAccount::in_category_index`. Expenses created
+/// by the other instructions join an index once modified.
+#[account]
+#[derive(Default)]
+pub struct CategoryIndex {
+    // The category of the indexed expenses
+    pub category: ExpenseCategory,
+    // Sum of the amounts in the base currency, see `ExpenseAccount::amount_in_base`
+    pub total: u64,
+    // Number of indexed expenses
+    pub count: u64,
+}
+
+impl CategoryIndex {
+    pub const SPACE: usize = 8 + 1 + 8 + 8;
+
+    /// Adds an expense to the index.
+    pub fn record_expense(&mut self, amount: u64) -> Result<()> {
+        self.count = checked_add(self.count, 1)?;
+        self.total = checked_add(self.total, amount)?;
+
+        Ok(())
+    }
+
+    /// Replaces the old amount of an expense in the index with the new one.
+    pub fn replace_amount(&mut self, old_amount: u64, new_amount: u64) -> Result<()> {
+        self.total = checked_add(checked_sub(self.total, old_amount)?, new_amount)?;
+
+        Ok(())
+    }
+
+    /// Removes an expense from the index.
+    pub fn remove_expense(&mut self, amount: u64) -> Result<()> {
+        let total = checked_sub(self.total, amount)?;
+        self.count = checked_sub(self.count, 1)?;
+        self.total = total;
+
+        Ok(())
+    }
+}
+
+/// The spending caps of a user.
+#[account]
+#[derive(Default)]
+pub struct Budget {
+    // The maximum amount that can be spent in a period, a calendar month by default
+    pub monthly_limit: u64,
+    // The amount spent in the current period
+    pub spent_this_period: u64,
+    // The period `spent_this_period` belongs to, see `Budget::period_of`
+    pub period: u32,
+    // The maximum amount that can be spent in a (UTC) day, zero for no daily limit
+    pub daily_limit: u64,
+    // Unix timestamp of the start of the day `spent_today` belongs to
+    pub day_start: i64,
+    // The amount spent in the current day
+    pub spent_today: u64,
+    // The maximum amount of a single expense, zero for `MAX_EXPENSE_AMOUNT`
+    pub max_amount: u64,
+    // The spending of a period that raises a `BudgetAlert`, zero for no alert
+    pub alert_threshold: u64,
+    // Whether the alert of the current period was raised already
+    pub alert_fired: bool,
+    // The token the limits are denominated in, `pay_expense` only accepts payments in it.
+    // The default key when the budget is in no specific token.
+    pub mint: Pubkey,
+    // The day of the month (1-28) the periods start on, e.g. 15 for a pay-cycle
+    pub period_start_day: u8,
+    // The length of a period in days, zero for periods of a month
+    pub period_length_days: u16,
+}
+
+impl Budget {
+    pub const SPACE: usize = 8 + 8 + 8 + 4 + 8 + 8 + 8 + 8 + 8 + 1 + 32 + 1 + 2;
+
+    /// The period `now` falls in. For monthly periods the `year_month` of the month the
+    /// period started in, otherwise the number of the period counted in
+    /// `period_length_days` from `period_start_day` of January 1970.
+    pub fn period_of(&self, now: i64) -> u32 {
+        let offset = i64::from(self.period_start_day.saturating_sub(1)) * 86_400;
+        if self.period_length_days == 0 {
+            year_month(now - offset)
+        } else {
+            (now - offset)
+                .div_euclid(86_400)
+                .div_euclid(i64::from(self.period_length_days)) as u32
+        }
+    }
+
+    /// Adds `amount` to the spending of the period and the day `now` falls in,
+    /// failing when it would exceed the period or the daily limit.
+    pub fn record_spending(&mut self, amount: u64, now: i64) -> Result<()> {
+        let period = self.period_of(now);
+        if self.period != period {
+            self.period = period;
+            self.spent_this_period = 0;
+            self.alert_fired = false;
+        }
+        let day_start = day_start(now);
+        if self.day_start != day_start {
+            self.day_start = day_start;
+            self.spent_today = 0;
+        }
+
+        let spent = checked_add(self.spent_this_period, amount)?;
+        require!(spent <= self.monthly_limit, ExpenseError::BudgetExceeded);
+        let spent_today = checked_add(self.spent_today, amount)?;
+        require!(
+            self.daily_limit == 0 || spent_today <= self.daily_limit,
+            ExpenseError::DailyLimitExceeded
+        );
+        self.spent_this_period = spent;
+        self.spent_today = spent_today;
+
+        Ok(())
+    }
+
+    /// Whether the spending of the period has reached the alert threshold, true only
+    /// the first time in a period.
+    pub fn take_alert(&mut self) -> bool {
+        if self.alert_fired
+            || self.alert_threshold == 0
+            || self.spent_this_period < self.alert_threshold
+        {
+            return false;
+        }
+        self.alert_fired = true;
+
+        true
+    }
+}
+
+/// The category an expense is filed under.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExpenseCategory {
+    Food,
+    Transport,
+    Utilities,
+    Entertainment,
+    Healthcare,
+    #[default]
+    Other,
+}
+
+/// Emitted when a new expense is created.
+#[event]
+pub struct ExpenseCreated {
+    pub id: u64,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub merchant_name: String,
+}
+
+/// Emitted when an expense is modified.
+#[event]
+pub struct ExpenseModified {
+    pub id: u64,
+    pub amount: u64,
+    pub merchant_name: String,
+}
+
+/// Emitted when an expense is deleted.
+#[event]
+pub struct ExpenseDeleted {
+    pub id: u64,
+    pub owner: Pubkey,
+    // Why the expense was deleted, for the audit trail
+    pub reason: String,
+}
+
+/// Emitted when the spending of a period reaches the alert threshold of the budget.
+#[event]
+pub struct BudgetAlert {
+    pub owner: Pubkey,
+    pub spent: u64,
+    pub limit: u64,
+}
+
+/// Emitted for every chunk of `SPLIT_EVENT_CHUNK_SIZE` shares of a new split expense,
+/// the chunks in `sequence` order reassemble the shares.
+#[event]
+pub struct SplitSharesRecorded {
+    pub id: u64,
+    pub owner: Pubkey,
+    // Zero-based index of the chunk, out of `chunk_count`
+    pub sequence: u8,
+    pub chunk_count: u8,
+    pub shares: Vec<Share>,
+}
+
+/// Emitted by `export_expense` with every field of the expense account.
+#[event]
+pub struct ExpenseSnapshot {
+    // The address of the expense account
+    pub expense: Pubkey,
+    pub account: ExpenseAccount,
+}
+
+/// Emitted by `sum_range` with the total of the expenses created within `[start, end]`.
+#[event]
+pub struct RangeTotal {
+    pub start: i64,
+    pub end: i64,
+    pub total: u64,
+    pub count: u64,
+}
+
+/// Emitted by `validate_expense` with the outcome of the checks.
+#[event]
+pub struct ValidationResult {
+    pub id: u64,
+    pub ok: bool,
+    // The code of the error `initialize_expense` would fail with, see `error_code`, zero
+    // when it would pass
+    pub error_code: u32,
+}
+
+/// Emitted by `modify_expenses_batch` for every update.
+#[event]
+pub struct ModifyResult {
+    pub id: u64,
+    pub success: bool,
+    // The code of the error the update failed with, see `error_code`, zero on success
+    pub error_code: u32,
+}
+
+/// Emitted when an expense is flagged or its flag is cleared.
+#[event]
+pub struct ExpenseFlagged {
+    pub id: u64,
+    pub owner: Pubkey,
+    pub flagged: bool,
+}
+
+/// `a + b` that fails with `ExpenseError::ArithmeticOverflow` instead of wrapping or panicking.
+fn checked_add(a: u64, b: u64) -> Result<u64> {
+    a.checked_add(b)
+        .ok_or_else(|| ExpenseError::ArithmeticOverflow.into())
+}
+
+/// The body of `modify_expense` and `modify_expense_with_bump`, once the expense is
+/// checked by either context.
+fn modify_expense_account<'info>(
+    expense_account: &mut Account<'info, ExpenseAccount>,
+    user_stats: &mut UserStats,
+    category_index: Option<&mut CategoryIndex>,
+    new_category_index: &mut CategoryIndex,
+    budget: Option<&Budget>,
+    config: &Config,
+    authority: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    id: u64,
+    merchant_name: String,
+    amount: u64,
+    category: ExpenseCategory,
+    payment_method: PaymentMethod,
+) -> Result<()> {
+    validate_expense_input(&merchant_name, amount, max_expense_amount(budget))?;
+
+    // This is a "defense-in-depth" check.
+    // The primary validation that the correct expense account is being modified
+    // is handled by Anchor's `seeds` constraint on the `ModifyExpense` and `ModifyExpenseWithBump` contexts.
+    // If a client were to pass a mismatched `id`, Anchor would fail the transaction
+    // with a `ConstraintSeeds` error before this instruction logic is ever executed.
+    // However, including this check provides an explicit, internal safeguard
+    // to ensure data integrity within the instruction itself.
+    require_eq!(expense_account.id, id, ExpenseError::IdMismatch);
+
+    let now = Clock::get()?.unix_timestamp;
+    let min_modify_interval = config.min_modify_interval;
+    require!(
+        min_modify_interval == 0
+            || now.saturating_sub(expense_account.updated_at) >= i64::from(min_modify_interval),
+        ExpenseError::ModifyTooSoon
+    );
+
+    let old_amount_in_base = expense_account.amount_in_base;
+    expense_account.set_amount(amount)?;
+    // Archived expenses are not part of the stats until unarchived
+    if !expense_account.is_archived {
+        user_stats.replace_amount(old_amount_in_base, expense_account.amount_in_base)?;
+    }
+
+    let same_category = expense_account.category == category;
+    move_category_index(
+        expense_account,
+        category_index,
+        new_category_index,
+        same_category,
+        old_amount_in_base,
+    )?;
+    new_category_index.category = category;
+
+    expense_account.merchant_name = merchant_name;
+    expense_account.category = category;
+    expense_account.payment_method = payment_method;
+    expense_account.record_modification(now)?;
+
+    emit!(ExpenseModified {
+        id,
+        amount,
+        merchant_name: expense_account.merchant_name.clone(),
+    });
+
+    // Resized here rather than by `realloc`, so an authority that can't cover the
+    // rent of a longer name fails with `InsufficientRent`
+    let space = expense_account.size();
+    resize_account(
+        &expense_account.to_account_info(),
+        space,
+        authority,
+        system_program,
+    )?;
+
+    Ok(())
+}
+
+/// Applies a single update of `modify_expenses_batch` to the expense at `account_info`,
+/// checked like in `ModifyExpense` but for the owner only.
+fn apply_expense_update<'info>(
+    program_id: &Pubkey,
+    authority: &Signer<'info>,
+    user_stats: &mut UserStats,
+    category_indices: &mut [Account<'info, CategoryIndex>],
+    system_program: &Program<'info, System>,
+    account_info: &'info AccountInfo<'info>,
+    update: ExpenseUpdate,
+    max_amount: u64,
+    now: i64,
+) -> Result<()> {
+    let mut expense_account = Account::<ExpenseAccount>::try_from(account_info)?;
+    let (pda, _) = expense_account.find_pda(update.id, program_id);
+    require_keys_eq!(account_info.key(), pda, ExpenseError::InvalidExpenseAccount);
+    require_keys_eq!(
+        expense_account.owner,
+        authority.key(),
+        ExpenseError::Unauthorized
+    );
+    require!(!expense_account.is_locked, ExpenseError::ExpenseLocked);
+    require!(
+        expense_account.status != ExpenseStatus::Approved,
+        ExpenseError::ExpenseApproved
+    );
+    validate_expense_input(&update.merchant_name, update.amount, max_amount)?;
+
+    let old_amount_in_base = expense_account.amount_in_base;
+    expense_account.set_amount(update.amount)?;
+    expense_account.merchant_name = update.merchant_name;
+    expense_account.record_modification(now)?;
+    // The index and the stats are updated on copies, stored once the account is resized as
+    // well, so a failing update leaves them as they were
+    let category_index = batch_category_index(category_indices, expense_account.category);
+    let mut updated_category_index = category_index.as_deref().cloned();
+    update_category_index(
+        &expense_account,
+        updated_category_index.as_mut(),
+        old_amount_in_base,
+    )?;
+    let mut updated_user_stats = user_stats.clone();
+    if !expense_account.is_archived {
+        updated_user_stats.replace_amount(old_amount_in_base, expense_account.amount_in_base)?;
+    }
+    // Resized last, the account keeps its size unless the update is stored
+    resize_account(
+        account_info,
+        expense_account.size(),
+        authority,
+        system_program,
+    )?;
+    *user_stats = updated_user_stats;
+    if let (Some(category_index), Some(updated_category_index)) =
+        (category_index, updated_category_index)
+    {
+        *category_index = updated_category_index;
+    }
+
+    emit!(ExpenseModified {
+        id: update.id,
+        amount: update.amount,
+        merchant_name: expense_account.merchant_name.clone(),
+    });
+
+    expense_account.exit(program_id)
+}
+
+/// The code a failed instruction would have returned with `error`, the custom error code
+/// for program errors and `u32::MAX` for the builtin ones.
+fn error_code(error: &Error) -> u32 {
+    match error {
+        Error::AnchorError(error) => error.error_code_number,
+        Error::ProgramError(error) => match error.program_error {
+            ProgramError::Custom(code) => code,
+            _ => u32::MAX,
+        },
+    }
+}
+
+/// The checks of `initialize_expense` run by `validate_expense`, in the same order, on
+/// copies of the accounts the real instruction would update.
+fn check_new_expense(
+    accounts: &ValidateExpense,
+    merchant_name: &str,
+    amount: u64,
+    category: ExpenseCategory,
+) -> Result<()> {
+    require!(!accounts.config.paused, ExpenseError::ProgramPaused);
+    validate_expense_input(
+        merchant_name,
+        amount,
+        max_expense_amount(accounts.budget.as_deref()),
+    )?;
+    require!(
+        accounts.expense_account.data_is_empty(),
+        ExpenseError::IdInUse
+    );
+    if accounts.config.duplicate_guard {
+        let duplicate_guard: DuplicateGuard = load_or_default(&accounts.duplicate_guard)?;
+        require_keys_eq!(
+            duplicate_guard.expense,
+            Pubkey::default(),
+            ExpenseError::DuplicateExpense
+        );
+    }
+
+    if let Some(budget) = accounts.budget.as_deref() {
+        budget
+            .clone()
+            .record_spending(amount, Clock::get()?.unix_timestamp)?;
+    }
+    let mut user_stats: UserStats = load_or_default(&accounts.user_stats)?;
+    user_stats.check_min_amount(amount)?;
+    user_stats.record_expense(amount)?;
+    let mut category_index: CategoryIndex = load_or_default(&accounts.category_index)?;
+    category_index.category = category;
+    category_index.record_expense(amount)
+}
+
+/// The budget at `account_info`, or `None` while the user has none.
+fn load_budget(account_info: &AccountInfo) -> Result<Option<Budget>> {
+    if account_info.data_is_empty() {
+        return Ok(None);
+    }
+
+    Budget::try_deserialize(&mut &account_info.try_borrow_data()?[..]).map(Some)
+}
+
+/// The account at `account_info`, or the default `init_if_needed` would start from while
+/// it doesn't exist yet.
+fn load_or_default<T: AccountDeserialize + Default>(account_info: &AccountInfo) -> Result<T> {
+    if account_info.data_is_empty() {
+        return Ok(T::default());
+    }
+
+    T::try_deserialize(&mut &account_info.try_borrow_data()?[..])
+}
+
+/// Validates the shares of a new split expense, stores them and emits them in chunks.
+fn record_split(
+    split_expense: &mut SplitExpense,
+    id: u64,
+    owner: Pubkey,
+    amount: u64,
+    shares: Vec<Share>,
+) -> Result<()> {
+    require!(amount > 0, ExpenseError::AmountZero);
+    require!(!shares.is_empty(), ExpenseError::SplitEmpty);
+    require!(
+        shares.len() <= MAX_SPLIT_PARTICIPANTS,
+        ExpenseError::TooManyParticipants
+    );
+
+    let total = shares
+        .iter()
+        .try_fold(0, |total, share| checked_add(total, share.amount))?;
+    require_eq!(total, amount, ExpenseError::SplitMismatch);
+
+    split_expense.id = id;
+    split_expense.owner = owner;
+    split_expense.amount = amount;
+    split_expense.shares = shares;
+
+    let chunk_count = split_expense.shares.len().div_ceil(SPLIT_EVENT_CHUNK_SIZE) as u8;
+    for (sequence, chunk) in split_expense
+        .shares
+        .chunks(SPLIT_EVENT_CHUNK_SIZE)
+        .enumerate()
+    {
+        emit!(SplitSharesRecorded {
+            id,
+            owner: split_expense.owner,
+            sequence: sequence as u8,
+            chunk_count,
+            shares: chunk.to_vec(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Splits `amount` into `participants` shares that sum up to exactly `amount`. Everyone
+/// gets the same base share and the first `amount % participants` get one unit more, so
+/// the rounding is deterministic and clients can preview the split.
+pub fn split_evenly(amount: u64, participants: usize) -> Vec<u64> {
+    if participants == 0 {
+        return Vec::new();
+    }
+    let participants = participants as u64;
+    let (base, remainder) = (amount / participants, amount % participants);
+    (0..participants)
+        .map(|index| base + u64::from(index < remainder))
+        .collect()
+}
+
+/// The index the expense is counted in, `None` for an expense in no index. Fails with
+/// `ExpenseError::CategoryIndexMissing` when the expense is in one that wasn't passed.
+fn indexed_in<'a>(
+    expense_account: &ExpenseAccount,
+    category_index: Option<&'a mut CategoryIndex>,
+) -> Result<Option<&'a mut CategoryIndex>> {
+    if !expense_account.in_category_index {
+        return Ok(None);
+    }
+
+    category_index
+        .map(Some)
+        .ok_or_else(|| ExpenseError::CategoryIndexMissing.into())
+}
+
+/// Adds a new expense to the index of its category.
+fn add_to_category_index(
+    expense_account: &mut ExpenseAccount,
+    category_index: &mut CategoryIndex,
+) -> Result<()> {
+    category_index.category = expense_account.category;
+    category_index.record_expense(expense_account.amount_in_base)?;
+    expense_account.in_category_index = true;
+
+    Ok(())
+}
+
+/// Updates the index the expense is counted in, if any, to its current amount in the
+/// base currency, where `old_amount` is the one before the change.
+fn update_category_index(
+    expense_account: &ExpenseAccount,
+    category_index: Option<&mut CategoryIndex>,
+    old_amount: u64,
+) -> Result<()> {
+    match indexed_in(expense_account, category_index)? {
+        Some(category_index) => {
+            category_index.replace_amount(old_amount, expense_account.amount_in_base)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Removes the expense from the index it is counted in, if any.
+fn remove_from_category_index(
+    expense_account: &ExpenseAccount,
+    category_index: Option<&mut CategoryIndex>,
+) -> Result<()> {
+    match indexed_in(expense_account, category_index)? {
+        Some(category_index) => category_index.remove_expense(expense_account.amount_in_base),
+        None => Ok(()),
+    }
+}
+
+/// Moves the expense from the index it is counted in, if any, to `new_category_index`,
+/// the index of its new category or owner, where `old_amount` is its amount in the base
+/// currency before the change. `same_index` tells both indices are the same account.
+fn move_category_index(
+    expense_account: &mut ExpenseAccount,
+    category_index: Option<&mut CategoryIndex>,
+    new_category_index: &mut CategoryIndex,
+    same_index: bool,
+    old_amount: u64,
+) -> Result<()> {
+    if expense_account.in_category_index && same_index {
+        // The same account is written back once per field in declaration order, so only
+        // the later copy is updated
+        new_category_index.replace_amount(old_amount, expense_account.amount_in_base)?;
+    } else {
+        if let Some(category_index) = indexed_in(expense_account, category_index)? {
+            category_index.remove_expense(old_amount)?;
+        }
+        new_category_index.record_expense(expense_account.amount_in_base)?;
+    }
+    expense_account.in_category_index = true;
+
+    Ok(())
+}
+
+/// The category indices of `authority` passed after the expenses of a batch, in any
+/// order. Loaded once, so the expenses of a category update the same copy, and written
+/// back by the caller.
+fn load_category_indices<'info>(
+    account_infos: &'info [AccountInfo<'info>],
+    authority: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<Vec<Account<'info, CategoryIndex>>> {
+    let mut category_indices: Vec<Account<CategoryIndex>> = Vec::new();
+    for account_info in account_infos {
+        let category_index = Account::<CategoryIndex>::try_from(account_info)?;
+        let (pda, _) = Pubkey::find_program_address(
+            &[
+                b"cat_index",
+                authority.as_ref(),
+                &[category_index.category as u8],
+            ],
+            program_id,
+        );
+        require_keys_eq!(account_info.key(), pda, ExpenseError::InvalidCategoryIndex);
+        // A second copy would overwrite the changes made to the first one
+        require!(
+            category_indices
+                .iter()
+                .all(|other| other.key() != account_info.key()),
+            ExpenseError::InvalidCategoryIndex
+        );
+        category_indices.push(category_index);
+    }
+
+    Ok(category_indices)
+}
+
+/// The index of `category` among the ones loaded by `load_category_indices`.
+fn batch_category_index<'a>(
+    category_indices: &'a mut [Account<'_, CategoryIndex>],
+    category: ExpenseCategory,
+) -> Option<&'a mut CategoryIndex> {
+    category_indices
+        .iter_mut()
+        .find(|category_index| category_index.category == category)
+        .map(|category_index| &mut **category_index)
+}
+
+/// Makes `new_owner` the owner of the expense, moving it from the stats and the category
+/// index of the current owner to the ones of the new owner. Archived expenses are in
+/// neither stats, but stay in the index and the account count.
+fn change_owner(
+    expense_account: &mut ExpenseAccount,
+    new_owner: Pubkey,
+    user_stats: &mut UserStats,
+    new_owner_stats: &mut UserStats,
+    category_index: Option<&mut CategoryIndex>,
+    new_owner_category_index: &mut CategoryIndex,
+) -> Result<()> {
+    let same_owner = new_owner == expense_account.owner;
+    // For the same key both stats are the same account, which is written back once per
+    // field like in `move_category_index`, so there is nothing to move
+    if !same_owner {
+        if !expense_account.is_archived {
+            user_stats.remove_expense(expense_account.amount_in_base)?;
+            new_owner_stats.record_expense(expense_account.amount_in_base)?;
+        }
+        user_stats.remove_account()?;
+        new_owner_stats.add_account()?;
+    }
+    let amount_in_base = expense_account.amount_in_base;
+    move_category_index(
+        expense_account,
+        category_index,
+        new_owner_category_index,
+        same_owner,
+        amount_in_base,
+    )?;
+    new_owner_category_index.category = expense_account.category;
+    expense_account.owner = new_owner;
+    expense_account.pending_owner = None;
+
+    Ok(())
+}
+
+/// Records a new expense like `initialize_expense` does: checks the minimum amount of the
+/// owner and adds the expense to the budget, the user stats, the index of its category
+/// and the rollup of the month it was created in.
+fn record_new_expense(
+    expense_account: &mut ExpenseAccount,
+    budget: Option<&mut Budget>,
+    user_stats: &mut UserStats,
+    category_index: &mut CategoryIndex,
+    rollup: &mut MonthlyRollup,
+) -> Result<()> {
+    if let Some(budget) = budget {
+        budget.record_spending(expense_account.amount, expense_account.created_at)?;
+        if budget.take_alert() {
+            emit!(BudgetAlert {
+                owner: expense_account.owner,
+                spent: budget.spent_this_period,
+                limit: budget.monthly_limit,
+            });
+        }
+    }
+    // Transfers count as zero in the totals, but are in the base currency, so their
+    // amount is held to the minimum as it is
+    user_stats.check_min_amount(if expense_account.is_transfer {
+        expense_account.net_amount
+    } else {
+        expense_account.amount_in_base
+    })?;
+    user_stats.record_expense(expense_account.amount_in_base)?;
+    user_stats.add_account()?;
+    add_to_category_index(expense_account, category_index)?;
+    rollup.period = year_month(expense_account.created_at);
+    rollup.total = checked_add(rollup.total, expense_account.amount_in_base)?;
+    rollup.count = checked_add(rollup.count, 1)?;
+
+    Ok(())
+}
+
+/// Resizes an account owned by the program to `space`. The payer tops up the rent when
+/// the account grows, failing with `ExpenseError::InsufficientRent` when it can't, and
+/// gets the excess back when it shrinks. Both failures are checked before the account is
+/// touched, so a caller that recovers from the error, like `modify_expenses_batch`,
+/// keeps the account as it was.
+fn resize_account<'info>(
+    account_info: &AccountInfo<'info>,
+    space: usize,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let old_space = account_info.data_len();
+    if space > old_space {
+        capped_space(space)?;
+    }
+    let rent = Rent::get()?.minimum_balance(space);
+    require!(
+        payer.lamports() >= rent.saturating_sub(account_info.lamports()),
+        ExpenseError::InsufficientRent
+    );
+    account_info.resize(space)?;
+
+    if space < old_space && account_info.lamports() > rent {
+        let excess = account_info.lamports() - rent;
+        **account_info.try_borrow_mut_lamports()? -= excess;
+        **payer.try_borrow_mut_lamports()? += excess;
+    } else if rent > account_info.lamports() {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: account_info.clone(),
+                },
+            ),
+            rent - account_info.lamports(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns `space`, failing with `ExpenseError::AccountTooLarge` when it is above
+/// `MAX_ACCOUNT_SIZE`. Checked before every realloc that can grow an account.
+fn capped_space(space: usize) -> Result<usize> {
+    require!(space <= MAX_ACCOUNT_SIZE, ExpenseError::AccountTooLarge);
+
+    Ok(space)
+}
+
+/// `a - b` that fails with `ExpenseError::ArithmeticOverflow` instead of wrapping or panicking.
+fn checked_sub(a: u64, b: u64) -> Result<u64> {
+    a.checked_sub(b)
+        .ok_or_else(|| ExpenseError::ArithmeticOverflow.into())
+}
+
+/// `paid - owed` as a signed balance, failing with `ExpenseError::ArithmeticOverflow`
+/// when it doesn't fit.
+fn net_balance(paid: u64, owed: u64) -> Result<i64> {
+    let paid = i64::try_from(paid).map_err(|_| ExpenseError::ArithmeticOverflow)?;
+    let owed = i64::try_from(owed).map_err(|_| ExpenseError::ArithmeticOverflow)?;
+    paid.checked_sub(owed)
+        .ok_or_else(|| ExpenseError::ArithmeticOverflow.into())
+}
+
+/// The price read from an oracle account by `pin_fx_rate`, the leading fields of the
+/// account data in Borsh: the price of one unit of the expense currency in the base
+/// currency is `price * 10^expo`, published at the unix timestamp `publish_time`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OraclePrice {
+    pub price: i64,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+impl OraclePrice {
+    /// The price as an `ExpenseAccount::fx_rate`, rounded down. Fails with
+    /// `ExpenseError::InvalidOraclePrice` for prices that aren't positive and with
+    /// `ExpenseError::InvalidFxRate` for prices that round down to zero.
+    pub fn fx_rate(&self) -> Result<u64> {
+        require!(self.price > 0, ExpenseError::InvalidOraclePrice);
+        let scale = 10u128
+            .checked_pow(self.expo.unsigned_abs())
+            .ok_or(ExpenseError::ArithmeticOverflow)?;
+        let scaled = self.price as u128 * FX_RATE_SCALE as u128;
+        let fx_rate = if self.expo < 0 {
+            scaled / scale
+        } else {
+            scaled
+                .checked_mul(scale)
+                .ok_or(ExpenseError::ArithmeticOverflow)?
+        };
+        require!(fx_rate > 0, ExpenseError::InvalidFxRate);
+        u64::try_from(fx_rate).map_err(|_| ExpenseError::ArithmeticOverflow.into())
+    }
+}
+
+/// Converts `amount` to the base currency at `fx_rate`, see `FX_RATE_SCALE`. Fails
+/// with `ExpenseError::ArithmeticOverflow` when the converted amount doesn't fit a `u64`.
+pub fn to_base_amount(amount: u64, fx_rate: u64) -> Result<u64> {
+    let amount_in_base = amount as u128 * fx_rate as u128 / FX_RATE_SCALE as u128;
+    u64::try_from(amount_in_base).map_err(|_| ExpenseError::ArithmeticOverflow.into())
+}
+
+/// The tax part of `amount` at `tax_rate_bps` basis points, rounded down. Fails with
+/// `ExpenseError::InvalidTaxRate` for rates above `MAX_TAX_RATE_BPS`.
+pub fn tax_amount(amount: u64, tax_rate_bps: u16) -> Result<u64> {
+    require!(
+        tax_rate_bps <= MAX_TAX_RATE_BPS,
+        ExpenseError::InvalidTaxRate
+    );
+    let tax_amount = amount as u128 * tax_rate_bps as u128 / 10_000;
+    u64::try_from(tax_amount).map_err(|_| ExpenseError::ArithmeticOverflow.into())
+}
+
+/// Returns the calendar month of a unix timestamp in UTC as `yyyymm`, e.g. `202510`.
+pub fn year_month(unix_timestamp: i64) -> u32 {
+    // Converts days since the epoch to a civil date,
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = unix_timestamp.div_euclid(86_400) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year * 100 + month) as u32
+}
+
+/// Returns the unix timestamp of the start of the UTC day `unix_timestamp` falls in.
+pub fn day_start(unix_timestamp: i64) -> i64 {
+    unix_timestamp - unix_timestamp.rem_euclid(86_400)
+}
+
+/// Returns the canonical form of a merchant name: trimmed, lowercase,
+/// with runs of whitespace collapsed to a single space.
+pub fn normalize_merchant_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Returns the hash of a canonical merchant name, used as the seed of its PDA
+/// since names can be longer than the maximum seed length.
+pub fn merchant_name_hash(name: &str) -> [u8; 32] {
+    hash(name.as_bytes()).to_bytes()
+}
+
+/// Returns the maximum amount of a single expense, the one of the budget if provided.
+fn max_expense_amount(budget: Option<&Budget>) -> u64 {
+    match budget {
+        Some(budget) if budget.max_amount > 0 => budget.max_amount,
+        _ => MAX_EXPENSE_AMOUNT,
+    }
+}
+
+/// Validates the user supplied expense data shared by the create and modify instructions.
+fn validate_expense_input(merchant_name: &str, amount: u64, max_amount: u64) -> Result<()> {
+    validate_merchant_name(merchant_name)?;
+    validate_amount(amount, max_amount)
+}
+
+fn validate_merchant_name(merchant_name: &str) -> Result<()> {
+    require!(!merchant_name.is_empty(), ExpenseError::MerchantNameEmpty);
+    // Control characters, e.g. line breaks, would break the display of the name
+    require!(
+        merchant_name.bytes().all(|byte| byte >= 0x20),
+        ExpenseError::MerchantNameInvalid
+    );
+    // `str::len` counts bytes, so multibyte UTF-8 names are measured by their encoded size.
+    require!(
+        merchant_name.len() <= MAX_MERCHANT_NAME_LEN,
+        ExpenseError::MerchantNameTooLong
+    );
+
+    Ok(())
+}
+
+fn validate_amount(amount: u64, max_amount: u64) -> Result<()> {
+    require!(amount > 0, ExpenseError::AmountZero);
+    require!(amount <= max_amount, ExpenseError::AmountTooLarge);
+
+    Ok(())
+}
+
+#[error_code]
+pub enum ExpenseError {
+    #[msg("The provided ID does not match the one in the account.")]
+    IdMismatch,
+    #[msg("The merchant name is too long.")]
+    MerchantNameTooLong,
+    #[msg("The amount must be greater than zero.")]
+    AmountZero,
+    #[msg("The signer is not authorized to perform this action.")]
+    Unauthorized,
+    #[msg("The merchant name must not be empty.")]
+    MerchantNameEmpty,
+    #[msg("The currency code must be three uppercase ASCII letters.")]
+    InvalidCurrency,
+    #[msg("The note is too long.")]
+    NoteTooLong,
+    #[msg("Arithmetic overflow.")]
+    ArithmeticOverflow,
+    #[msg("The expense exceeds the monthly budget.")]
+    BudgetExceeded,
+    #[msg("The shares do not sum up to the amount.")]
+    SplitMismatch,
+    #[msg("A split expense needs at least one participant.")]
+    SplitEmpty,
+    #[msg("Too many participants.")]
+    TooManyParticipants,
+    #[msg("The interval must be greater than zero.")]
+    InvalidInterval,
+    #[msg("The recurring interval has not elapsed yet.")]
+    RecurringTooSoon,
+    #[msg("The owner cannot approve their own expense.")]
+    SelfApproval,
+    #[msg("The expense has already been reviewed.")]
+    ExpenseAlreadyReviewed,
+    #[msg("A reason is required to delete an expense.")]
+    DeleteReasonEmpty,
+    #[msg("The deletion reason is too long.")]
+    DeleteReasonTooLong,
+    #[msg("The batch is empty.")]
+    BatchEmpty,
+    #[msg("The batch is too large.")]
+    BatchTooLarge,
+    #[msg("The number of accounts does not match the number of batch items.")]
+    BatchAccountsMismatch,
+    #[msg("The expense account does not match the expected PDA.")]
+    InvalidExpenseAccount,
+    #[msg("The tag is too long.")]
+    TagTooLong,
+    #[msg("Too many tags.")]
+    TooManyTags,
+    #[msg("The expense already has this tag.")]
+    DuplicateTag,
+    #[msg("The expense does not have this tag.")]
+    TagNotFound,
+    #[msg("The due date must not be in the past.")]
+    DueDateInPast,
+    #[msg("The expense has already been paid.")]
+    ExpenseAlreadyPaid,
+    #[msg("The program is paused.")]
+    ProgramPaused,
+    #[msg("The expense exceeds the daily limit.")]
+    DailyLimitExceeded,
+    #[msg("The user has reached the maximum number of expenses.")]
+    TooManyExpenses,
+    #[msg("The refund exceeds the net amount of the expense.")]
+    RefundExceedsAmount,
+    #[msg("Too many group members.")]
+    TooManyMembers,
+    #[msg("The user is already a member of the group.")]
+    AlreadyGroupMember,
+    #[msg("The signer is not a member of the group.")]
+    NotGroupMember,
+    #[msg("Every expense of the group must be passed exactly once.")]
+    GroupExpensesMismatch,
+    #[msg("The expense is locked.")]
+    ExpenseLocked,
+    #[msg("The amount is too large.")]
+    AmountTooLarge,
+    #[msg("The receipt CID is too long.")]
+    ReceiptCidTooLong,
+    #[msg("The receipt CID must be ASCII.")]
+    InvalidReceiptCid,
+    #[msg("The number of required approvals is out of range.")]
+    InvalidRequiredApprovals,
+    #[msg("The approver has already approved the expense.")]
+    DuplicateApproval,
+    #[msg("The merchant name must not contain control characters.")]
+    MerchantNameInvalid,
+    #[msg("The expense is not archived.")]
+    ExpenseNotArchived,
+    #[msg("The exchange rate must be greater than zero.")]
+    InvalidFxRate,
+    #[msg("The reimbursement has already been requested.")]
+    ReimbursementAlreadyRequested,
+    #[msg("The reimbursement has not been requested.")]
+    ReimbursementNotRequested,
+    #[msg("The line item description is too long.")]
+    LineItemDescriptionTooLong,
+    #[msg("Too many line items.")]
+    TooManyLineItems,
+    #[msg("The line items exceed the amount of the expense.")]
+    LineItemsExceedAmount,
+    #[msg("The expense does not have this line item.")]
+    LineItemNotFound,
+    #[msg("The expense has been approved and can no longer be changed.")]
+    ExpenseApproved,
+    #[msg("The timestamp is too far in the future.")]
+    TimestampOutOfRange,
+    #[msg("An identical expense was already created today.")]
+    DuplicateExpense,
+    #[msg("The duplicate guard account is required while the guard is enabled.")]
+    DuplicateGuardMissing,
+    #[msg("The user still has expenses, delete them first.")]
+    ExpensesOutstanding,
+    #[msg("The line items add up to more than the amount of the expense.")]
+    LineItemMismatch,
+    #[msg("The metadata key is too long.")]
+    MetadataKeyTooLong,
+    #[msg("The metadata value is too long.")]
+    MetadataValueTooLong,
+    #[msg("Too many metadata entries.")]
+    TooManyMetadataEntries,
+    #[msg("The expense does not have this metadata key.")]
+    MetadataKeyNotFound,
+    #[msg("The authority can't cover the rent of the resized account.")]
+    InsufficientRent,
+    #[msg("The end of the date range is before its start.")]
+    InvalidDateRange,
+    #[msg("Too many viewers.")]
+    TooManyViewers,
+    #[msg("The expense is already shared with this viewer.")]
+    DuplicateViewer,
+    #[msg("The expense is not shared with this viewer.")]
+    ViewerNotFound,
+    #[msg("The expense was changed too recently to be modified again.")]
+    ModifyTooSoon,
+    #[msg("No transfer of the expense is pending.")]
+    NoPendingTransfer,
+    #[msg("The payment is not in the token of the budget.")]
+    MintMismatch,
+    #[msg("The owner of the expense has no recovery key.")]
+    RecoveryKeyNotSet,
+    #[msg("Only expenses with a receipt can be deleted.")]
+    ReceiptRequired,
+    #[msg("The expense has line items or approvals tied to its address.")]
+    ExpenseHasDependents,
+    #[msg("The encrypted note is too long.")]
+    EncryptedNoteTooLong,
+    #[msg("An encrypted note needs its nonce.")]
+    NonceMissing,
+    #[msg("The tax rate is above 100%.")]
+    InvalidTaxRate,
+    #[msg("No oracle program is configured.")]
+    OracleNotConfigured,
+    #[msg("The oracle account isn't owned by the oracle program.")]
+    InvalidOracleAccount,
+    #[msg("The oracle account doesn't hold a positive price.")]
+    InvalidOraclePrice,
+    #[msg("The oracle price is too old.")]
+    OracleStale,
+    #[msg("The account would grow beyond the maximum account size.")]
+    AccountTooLarge,
+    #[msg("The amount is below the minimum amount of the user.")]
+    AmountBelowMinimum,
+    #[msg("An expense can't be merged into itself.")]
+    MergeIntoItself,
+    #[msg("The expenses are in different currencies.")]
+    CurrencyMismatch,
+    #[msg("The period start day must be between 1 and 28.")]
+    InvalidPeriodStartDay,
+    #[msg("The expense ID is already in use.")]
+    IdInUse,
+    #[msg("The group expense was settled already.")]
+    ExpenseSettled,
+    #[msg("An expense with this client reference was submitted already.")]
+    DuplicateSubmission,
+    #[msg("The submission guard account is required with a client reference.")]
+    SubmissionGuardMissing,
+    #[msg("The config can't designate that many approvers.")]
+    TooManyApprovers,
+    #[msg("The tenant must not be all zeros.")]
+    InvalidTenant,
+    #[msg("Every expense account of the user must be passed.")]
+    IncompleteExpenseSet,
+    #[msg("The oracle account isn't the feed of the currency of the expense.")]
+    OracleFeedMismatch,
+    #[msg("The category index the expense is counted in wasn't passed.")]
+    CategoryIndexMissing,
+    #[msg("The account isn't a category index of the authority, or is passed twice.")]
+    InvalidCategoryIndex,
+    #[msg("The bump isn't the canonical bump of the expense.")]
+    InvalidBump,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_evenly_gives_the_remainder_to_the_first_participants() {
+        assert_eq!(split_evenly(10, 3), vec![4, 3, 3]);
+        assert_eq!(split_evenly(11, 4), vec![3, 3, 3, 2]);
+        assert_eq!(split_evenly(12, 4), vec![3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn split_evenly_gives_a_single_participant_everything() {
+        assert_eq!(split_evenly(10, 1), vec![10]);
+        assert_eq!(split_evenly(0, 1), vec![0]);
+    }
+
+    #[test]
+    fn split_evenly_handles_fewer_units_than_participants() {
+        assert_eq!(split_evenly(2, 5), vec![1, 1, 0, 0, 0]);
+        assert_eq!(split_evenly(0, 3), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn split_evenly_always_sums_up_to_the_amount() {
+        for amount in [0, 1, 7, 100, u64::MAX] {
+            for participants in 1..=MAX_SPLIT_PARTICIPANTS {
+                let shares = split_evenly(amount, participants);
+                assert_eq!(shares.len(), participants);
+                assert_eq!(
+                    shares.iter().map(|&share| u128::from(share)).sum::<u128>(),
+                    u128::from(amount)
+                );
+            }
+        }
+        assert!(split_evenly(10, 0).is_empty());
+    }
+
+    #[test]
+    fn category_index_rejects_removing_more_than_it_counts() {
+        let mut category_index = CategoryIndex::default();
+        category_index.record_expense(10).unwrap();
+
+        assert!(category_index.replace_amount(11, 5).is_err());
+        assert!(category_index.remove_expense(11).is_err());
+        category_index.remove_expense(10).unwrap();
+        assert!(category_index.remove_expense(0).is_err());
+    }
+
+    #[test]
+    fn move_category_index_requires_the_index_of_an_indexed_expense() {
+        let mut expense_account = ExpenseAccount {
+            amount_in_base: 10,
+            ..Default::default()
+        };
+        let mut category_index = CategoryIndex::default();
+        let mut new_category_index = CategoryIndex::default();
+
+        // An expense in no index joins the new one
+        move_category_index(
+            &mut expense_account,
+            None,
+            &mut new_category_index,
+            false,
+            10,
+        )
+        .unwrap();
+        assert!(expense_account.in_category_index);
+        assert_eq!(
+            (new_category_index.count, new_category_index.total),
+            (1, 10)
+        );
+
+        assert!(
+            move_category_index(&mut expense_account, None, &mut category_index, false, 10)
+                .is_err()
+        );
+        move_category_index(
+            &mut expense_account,
+            Some(&mut new_category_index),
+            &mut category_index,
+            false,
+            10,
+        )
+        .unwrap();
+        assert_eq!((new_category_index.count, new_category_index.total), (0, 0));
+        assert_eq!((category_index.count, category_index.total), (1, 10));
+    }
 }
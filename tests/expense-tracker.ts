@@ -6,6 +6,9 @@ import {ExpenseTracker} from "../target/types/expense_tracker";
 import BN from "bn.js";
 import {assert} from "chai";
 import {LAMPORTS_PER_SOL} from "@solana/web3.js";
+import * as fs from "fs";
+import {createHash} from "crypto";
+import {createMint, getAccount, getOrCreateAssociatedTokenAccount, mintTo, TOKEN_PROGRAM_ID} from "@solana/spl-token";
 
 describe("expense-tracker", () => {
     // Creates a new AnchorProvider instance, which is the client that connects to the Solana network.
@@ -25,6 +28,15 @@ describe("expense-tracker", () => {
     const expenseId = new BN(1);
     const merchantName = "Cafe Shop";
     const amount = new BN(10);
+    const category = {food: {}};
+    const currency = Array.from(Buffer.from("USD"));
+    // An all-zero receipt hash means "no receipt"
+    const noReceipt = new Array(32).fill(0);
+    const paymentMethod = {card: {}};
+    // A zero due date means "not a bill"
+    const noDueDate = new BN(0);
+    // Exchange rates are scaled by 1e6, this one converts 1:1 to the base currency
+    const identityFxRate = new BN(1_000_000);
 
     // Declares a variable to hold the public key of the PDA, which will be derived in the before block.
     let expenseAccountPDA: anchor.web3.PublicKey;
@@ -35,6 +47,256 @@ describe("expense-tracker", () => {
         return provider.connection.getBalance(pubkey);
     };
 
+    // Helper function to create a new keypair and fund it with 1 SOL
+    const createFundedUser = async () => {
+        const keypair = anchor.web3.Keypair.generate();
+        const airdropSignature = await provider.connection.requestAirdrop(keypair.publicKey, LAMPORTS_PER_SOL);
+        const latestBlockhash = await provider.connection.getLatestBlockhash();
+        await provider.connection.confirmTransaction({
+            signature: airdropSignature,
+            blockhash: latestBlockhash.blockhash,
+            lastValidBlockHeight: latestBlockhash.lastValidBlockHeight,
+        });
+        return keypair;
+    };
+
+    // Helper function to derive the expense PDA for the given owner and expense ID
+    const expensePda = (owner: anchor.web3.PublicKey, id: BN) => {
+        const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("expense"),
+                owner.toBuffer(),
+                id.toBuffer("le", 8),
+            ],
+            program.programId
+        );
+        return pda;
+    };
+
+    // Helper function to derive the stats PDA of the given user
+    const statsPda = (owner: anchor.web3.PublicKey) => {
+        const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("stats"), owner.toBuffer()],
+            program.programId
+        );
+        return pda;
+    };
+
+    // Helper function to derive the monthly rollup PDA for the given owner and `yyyymm` period
+    const rollupPda = (owner: anchor.web3.PublicKey, period: number) => {
+        const periodBytes = Buffer.alloc(4);
+        periodBytes.writeUInt32LE(period);
+        const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("rollup"), owner.toBuffer(), periodBytes],
+            program.programId
+        );
+        return pda;
+    };
+
+    // Returns the current calendar month as `yyyymm`, by the cluster clock the program
+    // files expenses by rather than the local one
+    const currentPeriod = async () => {
+        const blockTime = await provider.connection.getBlockTime(await provider.connection.getSlot());
+        const date = new Date(blockTime * 1000);
+        return date.getUTCFullYear() * 100 + date.getUTCMonth() + 1;
+    };
+
+    // Helper function to derive the global config PDA
+    const configPda = () => {
+        const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("config")],
+            program.programId
+        );
+        return pda;
+    };
+
+    // Helper function to derive the budget PDA of the given user
+    const budgetPda = (owner: anchor.web3.PublicKey) => {
+        const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("budget"), owner.toBuffer()],
+            program.programId
+        );
+        return pda;
+    };
+
+    // Helper function to derive the category index PDA of the given user, the category
+    // is given as the variant object the client uses, e.g. `{food: {}}`
+    const expenseCategories = ["food", "transport", "utilities", "entertainment", "healthcare", "other"];
+    const categoryIndexPda = (owner: anchor.web3.PublicKey, category: object) => {
+        const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("cat_index"),
+                owner.toBuffer(),
+                Buffer.from([expenseCategories.indexOf(Object.keys(category)[0])]),
+            ],
+            program.programId
+        );
+        return pda;
+    };
+
+    // Returns the category index the expense at `pda` is counted in, null if there is none
+    const categoryIndexOf = async (pda: anchor.web3.PublicKey) => {
+        const expenseAccount = await program.account.expenseAccount.fetchNullable(pda);
+        return expenseAccount?.inCategoryIndex ? categoryIndexPda(expenseAccount.owner, expenseAccount.category) : null;
+    };
+
+    // Returns the category index the expense is currently counted in, null if there is none
+    const currentCategoryIndex = (owner: anchor.web3.PublicKey, id: BN) => categoryIndexOf(expensePda(owner, id));
+
+    // Returns the category indices a transfer of the expense at `pda` to `newOwner` moves it between
+    const ownerCategoryIndices = async (pda: anchor.web3.PublicKey, newOwner: anchor.web3.PublicKey) => {
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        return {
+            categoryIndex: await categoryIndexOf(pda),
+            newOwnerCategoryIndex: categoryIndexPda(newOwner, expenseAccount.category),
+        };
+    };
+
+    // Helper function to create an expense for the given user and return its PDA
+    const createExpense = async (
+        owner: anchor.web3.Keypair,
+        id: BN,
+        {
+            merchantName = "Cafe Shop",
+            amount = new BN(10),
+            category = {food: {}},
+            currency = Array.from(Buffer.from("USD")),
+            note = "",
+            receiptHash = new Array(32).fill(0),
+            paymentMethod = {card: {}},
+            dueDate = new BN(0),
+            fxRate = identityFxRate,
+            budget = null,
+            merchant = null,
+            duplicateGuard = null,
+            clientRef = null,
+            submissionGuard = null,
+        }: {
+            merchantName?: string;
+            amount?: BN;
+            category?: object;
+            currency?: number[];
+            note?: string;
+            receiptHash?: number[];
+            paymentMethod?: object;
+            dueDate?: BN;
+            fxRate?: BN;
+            budget?: anchor.web3.PublicKey | null;
+            merchant?: anchor.web3.PublicKey | null;
+            duplicateGuard?: anchor.web3.PublicKey | null;
+            clientRef?: number[] | null;
+            submissionGuard?: anchor.web3.PublicKey | null;
+        } = {}
+    ) => {
+        const pda = expensePda(owner.publicKey, id);
+        await program.methods
+            .initializeExpense(id, merchantName, amount, category, currency, note, receiptHash, paymentMethod, dueDate, fxRate, clientRef)
+            .accounts({
+                expenseAccount: pda,
+                userStats: statsPda(owner.publicKey),
+                rollup: rollupPda(owner.publicKey, await currentPeriod()),
+                categoryIndex: categoryIndexPda(owner.publicKey, category),
+                budget,
+                merchant,
+                duplicateGuard,
+                submissionGuard,
+                authority: owner.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+            })
+            .signers([owner])
+            .rpc();
+        return pda;
+    };
+
+    // Helper function to modify an expense of the given user
+    const modifyExpense = async (
+        owner: anchor.web3.Keypair,
+        id: BN,
+        {
+            merchantName = "Cafe Shop",
+            amount = new BN(10),
+            category = {food: {}},
+            paymentMethod = {card: {}},
+            authority = owner,
+            budget = null,
+        }: {
+            merchantName?: string;
+            amount?: BN;
+            category?: object;
+            paymentMethod?: object;
+            authority?: anchor.web3.Keypair;
+            budget?: anchor.web3.PublicKey | null;
+        } = {}
+    ) =>
+        program.methods
+            .modifyExpense(id, merchantName, amount, category, paymentMethod)
+            .accounts({
+                expenseAccount: expensePda(owner.publicKey, id),
+                userStats: statsPda(owner.publicKey),
+                categoryIndex: await currentCategoryIndex(owner.publicKey, id),
+                newCategoryIndex: categoryIndexPda(owner.publicKey, category),
+                budget,
+                authority: authority.publicKey,
+            })
+            .signers([authority])
+            .rpc();
+
+    // Helper function to delete an expense of the given user
+    const deleteExpense = async (
+        owner: anchor.web3.Keypair,
+        id: BN,
+        reason = "No longer needed",
+        rentDestination = owner.publicKey
+    ) =>
+        program.methods
+            .deleteExpense(id, reason)
+            .accounts({
+                expenseAccount: expensePda(owner.publicKey, id),
+                userStats: statsPda(owner.publicKey),
+                categoryIndex: await currentCategoryIndex(owner.publicKey, id),
+                rentDestination,
+                authority: owner.publicKey,
+            })
+            .signers([owner])
+            .rpc();
+
+    // Helper function to replace the approvers of the config, signed by the admin
+    const setApprovers = (approvers: anchor.web3.PublicKey[]) =>
+        program.methods
+            .setApprovers(approvers)
+            .accounts({admin: provider.wallet.publicKey})
+            .rpc();
+
+    // Helper function to decode the events emitted by the program in the given transaction
+    const getEvents = async (txSignature: string) => {
+        const latestBlockhash = await provider.connection.getLatestBlockhash();
+        await provider.connection.confirmTransaction({
+            signature: txSignature,
+            blockhash: latestBlockhash.blockhash,
+            lastValidBlockHeight: latestBlockhash.lastValidBlockHeight,
+        }, "confirmed");
+        const tx = await provider.connection.getTransaction(txSignature, {
+            commitment: "confirmed",
+            maxSupportedTransactionVersion: 0,
+        });
+        const eventParser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+        return Array.from(eventParser.parseLogs(tx.meta.logMessages));
+    };
+
+    const sleep = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
+
+    // Helper function to assert that a transaction fails with the given Anchor error code
+    const expectError = async (tx: Promise<unknown>, code: string) => {
+        try {
+            await tx;
+        } catch (error) {
+            assert.isDefined(error.error, "The error object should have an 'error' property");
+            assert.equal(error.error.errorCode.code, code, `The error code should be ${code}.`);
+            return;
+        }
+        assert.fail(`The transaction should have failed with ${code}.`);
+    };
+
     before(async () => {
         // This is where the PDA address is derived.
         // It uses the same seeds as your Rust program: a hardcoded string ("expense"),
@@ -50,7 +312,8 @@ describe("expense-tracker", () => {
         );
         // Calculate the rent required for the PDA
         expenseAccountRent = await provider.connection.getMinimumBalanceForRentExemption(
-            8 + 8 + 32 + (4 + 12) + 8 + 1 // The space of your ExpenseAccount struct
+            // The space of your ExpenseAccount struct, sized to the merchant name and (empty) note
+            8 + 8 + 32 + (4 + merchantName.length) + 8 + 8 + 8 + 1 + 3 + (4 + 0) + (1 + 32) + 1 + (1 + 32) + 32 + 1 + (1 + 32) + 4 + 1 + 8 + 1 + 8 + 1 + 1 + 8 + (1 + 32) + 1 + 4 + 4 + 1 + 8 + 8 + 1 + 4 + 4 + (1 + 32) + 4 + 24 + 2 + 8 + 1 + 8 + 16 + 32 + (1 + 1) + 1 + 8
         );
 
         // Fund the user's account with 1SOL
@@ -65,6 +328,11 @@ describe("expense-tracker", () => {
             blockhash: latestBlockhash.blockhash,
             lastValidBlockHeight: latestBlockhash.lastValidBlockHeight,
         });
+
+        // The program config is global, the provider wallet acts as its admin
+        if (!(await program.account.config.fetchNullable(configPda()))) {
+            await program.methods.initializeConfig(provider.wallet.publicKey, Array.from(Buffer.from("USD"))).rpc();
+        }
     });
 
     it("Is initialized!", async () => {
@@ -81,11 +349,18 @@ describe("expense-tracker", () => {
         // Call the initialize_expense instruction
         const txSignature = await program.methods
             // Invokes the initializeExpense method on the program.
-            .initializeExpense(expenseId, merchantName, amount)
+            .initializeExpense(expenseId, merchantName, amount, category, currency, "", noReceipt, paymentMethod, noDueDate, identityFxRate, null)
             // Explicitly lists the on-chain accounts needed for this instruction.
             // This is a crucial step that tells the program which accounts to access.
             .accounts({
                 expenseAccount: expenseAccountPDA,
+                userStats: statsPda(user.publicKey),
+                rollup: rollupPda(user.publicKey, await currentPeriod()),
+                categoryIndex: categoryIndexPda(user.publicKey, category),
+                budget: null,
+                merchant: null,
+                duplicateGuard: null,
+                submissionGuard: null,
                 authority: user.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
             })
@@ -149,11 +424,15 @@ describe("expense-tracker", () => {
         // Call the modifyExpense instruction
         const txSignature = await program.methods
             // Invokes the modifyExpense method on the program.
-            .modifyExpense(expenseId, modifiedMerchantName, modifiedAmount)
+            .modifyExpense(expenseId, modifiedMerchantName, modifiedAmount, category, paymentMethod)
             // Explicitly lists the on-chain accounts needed for this instruction.
             // This is a crucial step that tells the program which accounts to access.
             .accounts({
                 expenseAccount: expenseAccountPDA,
+                userStats: statsPda(user.publicKey),
+                categoryIndex: categoryIndexPda(user.publicKey, category),
+                newCategoryIndex: categoryIndexPda(user.publicKey, category),
+                budget: null,
                 authority: user.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
             })
@@ -214,9 +493,12 @@ describe("expense-tracker", () => {
             // but the `expenseAccountPDA` was derived using the original `expenseId` (1).
             // The on-chain program should detect this mismatch and throw our custom error.
             await program.methods
-                .modifyExpense(wrongExpenseId, newMerchantName, newAmount)
+                .modifyExpense(wrongExpenseId, newMerchantName, newAmount, category, paymentMethod)
                 .accounts({
                     expenseAccount: expenseAccountPDA,
+                    categoryIndex: categoryIndexPda(user.publicKey, category),
+                    newCategoryIndex: categoryIndexPda(user.publicKey, category),
+                    budget: null,
                     authority: user.publicKey,
                 })
                 .signers([user])
@@ -235,6 +517,40 @@ describe("expense-tracker", () => {
         }
     });
 
+    it("Fails to modify an expense owned by another user", async () => {
+        // User B tries to modify the expense that belongs to `user` (user A)
+        // by passing A's expense account and signing with B's keys.
+        const otherUser = await createFundedUser();
+
+        try {
+            await program.methods
+                .modifyExpense(expenseId, "Hijacked", new BN(1), category, paymentMethod)
+                .accounts({
+                    expenseAccount: expenseAccountPDA,
+                    // The stats of the owner (A) go along with A's expense.
+                    userStats: statsPda(user.publicKey),
+                    categoryIndex: categoryIndexPda(user.publicKey, category),
+                    newCategoryIndex: categoryIndexPda(user.publicKey, category),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+            assert.fail("The transaction should have failed with a constraint violation.");
+        } catch (error) {
+            assert.isDefined(error.error, "The error object should have an 'error' property");
+            // The seeds are derived from the stored owner, so A's PDA passes the seeds check
+            // and it's the owner constraint that rejects B.
+            assert.equal(error.error.errorCode.code, "Unauthorized", "The error code should be Unauthorized.");
+        }
+
+        // The expense must be left untouched.
+        const expenseAccount = await program.account.expenseAccount.fetch(expenseAccountPDA);
+        assert.isTrue(expenseAccount.owner.equals(user.publicKey), "The owner should still be the original user");
+        assert.equal(expenseAccount.merchantName, "Petrol", "The merchant name should not change");
+    });
+
     it("Fetch expenses by owner", async () => {
         // At this point in the test suite, one expense account (ID 1) already exists for `user`.
         // Let's create a second one to demonstrate fetching multiple accounts with the filter.
@@ -254,9 +570,16 @@ describe("expense-tracker", () => {
 
         // Create the second expense account
         await program.methods
-            .initializeExpense(secondExpenseId, secondMerchantName, secondAmount)
+            .initializeExpense(secondExpenseId, secondMerchantName, secondAmount, category, currency, "", noReceipt, paymentMethod, noDueDate, identityFxRate, null)
             .accounts({
                 expenseAccount: secondExpensePDA,
+                userStats: statsPda(user.publicKey),
+                rollup: rollupPda(user.publicKey, await currentPeriod()),
+                categoryIndex: categoryIndexPda(user.publicKey, category),
+                budget: null,
+                merchant: null,
+                duplicateGuard: null,
+                submissionGuard: null,
                 authority: user.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
             })
@@ -302,9 +625,12 @@ describe("expense-tracker", () => {
         // Clean up the second expense so it doesn't interfere with the `Delete Expense` test.
         // The `Delete Expense` test is hardcoded to delete the expense with `expenseId` (ID 1).
         await program.methods
-            .deleteExpense(secondExpenseId)
+            .deleteExpense(secondExpenseId, "Test cleanup")
             .accounts({
                 expenseAccount: secondExpensePDA,
+                userStats: statsPda(user.publicKey),
+                categoryIndex: categoryIndexPda(user.publicKey, category),
+                rentDestination: user.publicKey,
                 authority: user.publicKey,
             })
             .signers([user])
@@ -331,11 +657,15 @@ describe("expense-tracker", () => {
         // Call the deleteExpense instruction
         const txSignature = await program.methods
             // Invokes the deleteExpense method on the program.
-            .deleteExpense(expenseId)
+            .deleteExpense(expenseId, "Entered by mistake")
             // Explicitly lists the on-chain accounts needed for this instruction.
             // This is a crucial step that tells the program which accounts to access.
             .accounts({
                 expenseAccount: expenseAccountPDA,
+                userStats: statsPda(user.publicKey),
+                categoryIndex: categoryIndexPda(user.publicKey, category),
+                // The reclaimed rent goes back to the user
+                rentDestination: user.publicKey,
                 authority: user.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
             })
@@ -382,5 +712,4090 @@ describe("expense-tracker", () => {
 
             console.log("Account successfully deleted as expected.");
         }
-    })
+    });
+
+    it("Rejects invalid expense input on initialize", async () => {
+        const otherUser = await createFundedUser();
+
+        await expectError(
+            createExpense(otherUser, new BN(1), {merchantName: "x".repeat(200)}),
+            "MerchantNameTooLong"
+        );
+        await expectError(createExpense(otherUser, new BN(1), {merchantName: ""}), "MerchantNameEmpty");
+        await expectError(createExpense(otherUser, new BN(1), {amount: new BN(0)}), "AmountZero");
+    });
+
+    it("Rejects invalid expense input on modify", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+
+        await expectError(modifyExpense(otherUser, id, {merchantName: "x".repeat(200)}), "MerchantNameTooLong");
+        await expectError(modifyExpense(otherUser, id, {merchantName: ""}), "MerchantNameEmpty");
+        await expectError(modifyExpense(otherUser, id, {amount: new BN(0)}), "AmountZero");
+
+        // None of the rejected modifications should have touched the account.
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.merchantName, "Cafe Shop", "The merchant name should not change");
+        assert.isTrue(expenseAccount.amount.eq(new BN(10)), "The amount should not change");
+    });
+
+    it("Validates the merchant name length in bytes", async () => {
+        const otherUser = await createFundedUser();
+
+        // Exactly MAX_MERCHANT_NAME_LEN (64) bytes is accepted.
+        const atLimit = "M".repeat(64);
+        const pda = await createExpense(otherUser, new BN(1), {merchantName: atLimit});
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.merchantName, atLimit, "The merchant name should match");
+
+        // One byte over the limit is rejected.
+        await expectError(
+            createExpense(otherUser, new BN(2), {merchantName: "M".repeat(65)}),
+            "MerchantNameTooLong"
+        );
+
+        // 17 chars but 68 bytes in UTF-8: under the char limit, over the byte limit.
+        const emojiName = "😀".repeat(17);
+        assert.equal(Buffer.byteLength(emojiName, "utf8"), 68);
+        await expectError(createExpense(otherUser, new BN(3), {merchantName: emojiName}), "MerchantNameTooLong");
+    });
+
+    it("Rejects a zero amount and accepts an amount of 1", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+
+        await expectError(createExpense(otherUser, id, {amount: new BN(0)}), "AmountZero");
+
+        const pda = await createExpense(otherUser, id, {amount: new BN(1)});
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amount.eq(new BN(1)), "The amount should be 1");
+
+        // The same rules apply when modifying the expense.
+        await expectError(modifyExpense(otherUser, id, {amount: new BN(0)}), "AmountZero");
+        await modifyExpense(otherUser, id, {amount: new BN(1)});
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amount.eq(new BN(1)), "The amount should still be 1");
+    });
+
+    it("Stores the creation timestamp", async () => {
+        const otherUser = await createFundedUser();
+        const pda = await createExpense(otherUser, new BN(1));
+
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        const slot = await provider.connection.getSlot();
+        const validatorTime = await provider.connection.getBlockTime(slot);
+
+        assert.isTrue(expenseAccount.createdAt.gtn(0), "created_at should be set");
+        assert.isAtMost(
+            Math.abs(expenseAccount.createdAt.toNumber() - validatorTime),
+            60,
+            "created_at should be close to the validator clock"
+        );
+    });
+
+    it("Refreshes updated_at on modify", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.updatedAt.eq(expenseAccount.createdAt), "updated_at should start at created_at");
+
+        // Let the validator clock advance past the creation second.
+        await sleep(2000);
+        await modifyExpense(otherUser, id, {amount: new BN(20)});
+
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.updatedAt.gt(expenseAccount.createdAt), "updated_at should be after created_at");
+    });
+
+    it("Emits ExpenseCreated on initialization", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = expensePda(otherUser.publicKey, id);
+
+        const txSignature = await program.methods
+            .initializeExpense(id, merchantName, amount, category, currency, "", noReceipt, paymentMethod, noDueDate, identityFxRate, null)
+            .accounts({
+                expenseAccount: pda,
+                userStats: statsPda(otherUser.publicKey),
+                rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                categoryIndex: categoryIndexPda(otherUser.publicKey, category),
+                budget: null,
+                merchant: null,
+                duplicateGuard: null,
+                submissionGuard: null,
+                authority: otherUser.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+            })
+            .signers([otherUser])
+            .rpc();
+
+        const events = await getEvents(txSignature);
+        const created = events.find(event => event.name === "expenseCreated");
+        assert.isDefined(created, "An ExpenseCreated event should be emitted");
+
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(created.data.id.eq(expenseAccount.id), "The event ID should match");
+        assert.isTrue(created.data.owner.equals(expenseAccount.owner), "The event owner should match");
+        assert.isTrue(created.data.amount.eq(expenseAccount.amount), "The event amount should match");
+        assert.equal(created.data.merchantName, expenseAccount.merchantName, "The event merchant name should match");
+    });
+
+    it("Emits ExpenseModified and ExpenseDeleted", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        await createExpense(otherUser, id);
+
+        const modifyEvents = await getEvents(
+            await modifyExpense(otherUser, id, {merchantName: "Bakery", amount: new BN(42)})
+        );
+        const modified = modifyEvents.find(event => event.name === "expenseModified");
+        assert.isDefined(modified, "An ExpenseModified event should be emitted");
+        assert.isTrue(modified.data.id.eq(id), "The event ID should match");
+        assert.isTrue(modified.data.amount.eq(new BN(42)), "The event should carry the new amount");
+        assert.equal(modified.data.merchantName, "Bakery", "The event should carry the new merchant name");
+
+        const deleteEvents = await getEvents(await deleteExpense(otherUser, id));
+        const deleted = deleteEvents.find(event => event.name === "expenseDeleted");
+        assert.isDefined(deleted, "An ExpenseDeleted event should be emitted");
+        assert.isTrue(deleted.data.id.eq(id), "The event ID should match");
+        assert.isTrue(deleted.data.owner.equals(otherUser.publicKey), "The event owner should match");
+    });
+
+    it("Stores the expense category", async () => {
+        const otherUser = await createFundedUser();
+        const categories = ["food", "transport", "utilities", "entertainment", "healthcare", "other"];
+
+        for (const [index, variant] of categories.entries()) {
+            const pda = await createExpense(otherUser, new BN(index + 1), {category: {[variant]: {}}});
+            const expenseAccount = await program.account.expenseAccount.fetch(pda);
+            assert.deepEqual(expenseAccount.category, {[variant]: {}}, `The category should be ${variant}`);
+        }
+    });
+
+    it("Validates the currency code", async () => {
+        const otherUser = await createFundedUser();
+
+        await expectError(
+            createExpense(otherUser, new BN(1), {currency: Array.from(Buffer.from("us1"))}),
+            "InvalidCurrency"
+        );
+
+        const pda = await createExpense(otherUser, new BN(1), {currency: Array.from(Buffer.from("EUR"))});
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(Buffer.from(expenseAccount.currency).toString(), "EUR", "The currency should be EUR");
+    });
+
+    it("Sizes the account to the note", async () => {
+        const otherUser = await createFundedUser();
+
+        const emptyNotePda = await createExpense(otherUser, new BN(1));
+        const emptyNoteAccount = await program.account.expenseAccount.fetch(emptyNotePda);
+        assert.equal(emptyNoteAccount.note, "", "The note should be empty");
+
+        const note = "Team lunch with the design crew";
+        const notePda = await createExpense(otherUser, new BN(2), {note});
+        const noteAccount = await program.account.expenseAccount.fetch(notePda);
+        assert.equal(noteAccount.note, note, "The note should match");
+
+        // The account with a note is exactly the note's length larger.
+        const emptyNoteInfo = await provider.connection.getAccountInfo(emptyNotePda);
+        const noteInfo = await provider.connection.getAccountInfo(notePda);
+        assert.equal(noteInfo.data.length - emptyNoteInfo.data.length, note.length);
+
+        // MAX_NOTE_LEN is 128 bytes.
+        await expectError(createExpense(otherUser, new BN(3), {note: "x".repeat(129)}), "NoteTooLong");
+    });
+
+    it("Transfers ownership without moving the account", async () => {
+        const originalOwner = await createFundedUser();
+        const newOwner = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(originalOwner, id);
+
+        const transfer = async (authority: anchor.web3.Keypair, to: anchor.web3.PublicKey) =>
+            program.methods
+                .transferOwnership(id, to)
+                .accounts({
+                    expenseAccount: pda,
+                    authority: authority.publicKey,
+                    ...(await ownerCategoryIndices(pda, to)),
+                })
+                .signers([authority])
+                .rpc();
+
+        await transfer(originalOwner, newOwner.publicKey);
+
+        // The account is still found at the address derived from the original owner's key.
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.owner.equals(newOwner.publicKey), "The owner should be the new owner");
+        assert.isTrue(expensePda(originalOwner.publicKey, id).equals(pda), "The PDA address should not change");
+        assert.isNull(
+            await provider.connection.getAccountInfo(expensePda(newOwner.publicKey, id)),
+            "No account should exist at the address derived from the new owner"
+        );
+
+        // The previous owner lost the right to transfer the expense.
+        await expectError(transfer(originalOwner, originalOwner.publicKey), "Unauthorized");
+    });
+
+    it("Tracks the running totals in the stats account", async () => {
+        const otherUser = await createFundedUser();
+        const amounts = [new BN(10), new BN(25), new BN(65)];
+
+        for (const [index, value] of amounts.entries()) {
+            await createExpense(otherUser, new BN(index + 1), {amount: value});
+
+            const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+            const expectedTotal = amounts.slice(0, index + 1).reduce((sum, next) => sum.add(next), new BN(0));
+            assert.isTrue(stats.expenseCount.eqn(index + 1), "The expense count should match");
+            assert.isTrue(stats.totalSpent.eq(expectedTotal), "The total spent should match");
+        }
+    });
+
+    it("Keeps the stats in sync on modify and delete", async () => {
+        const otherUser = await createFundedUser();
+        const stats = statsPda(otherUser.publicKey);
+        const assertStats = async (count: number, total: number) => {
+            const userStats = await program.account.userStats.fetch(stats);
+            assert.isTrue(userStats.expenseCount.eqn(count), `The expense count should be ${count}`);
+            assert.isTrue(userStats.totalSpent.eqn(total), `The total spent should be ${total}`);
+        };
+
+        await createExpense(otherUser, new BN(1), {amount: new BN(100)});
+        await createExpense(otherUser, new BN(2), {amount: new BN(50)});
+        await assertStats(2, 150);
+
+        // Modify up
+        await modifyExpense(otherUser, new BN(1), {amount: new BN(180)});
+        await assertStats(2, 230);
+
+        // Modify down
+        await modifyExpense(otherUser, new BN(1), {amount: new BN(20)});
+        await assertStats(2, 70);
+
+        await deleteExpense(otherUser, new BN(2));
+        await assertStats(1, 20);
+
+        await deleteExpense(otherUser, new BN(1));
+        await assertStats(0, 0);
+    });
+
+    it("Enforces the monthly budget when provided", async () => {
+        const otherUser = await createFundedUser();
+        const budget = budgetPda(otherUser.publicKey);
+
+        await program.methods
+            .setBudget(new BN(100), new BN(0), new BN(0), new BN(0), anchor.web3.PublicKey.default, 1, 0)
+            .accounts({
+                budget,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+
+        // Within the budget
+        await createExpense(otherUser, new BN(1), {amount: new BN(40), budget});
+        // Exactly at the limit
+        await createExpense(otherUser, new BN(2), {amount: new BN(60), budget});
+
+        const budgetAccount = await program.account.budget.fetch(budget);
+        assert.isTrue(budgetAccount.spentThisPeriod.eqn(100), "The spending should be at the limit");
+
+        // Over the limit
+        await expectError(createExpense(otherUser, new BN(3), {amount: new BN(1), budget}), "BudgetExceeded");
+
+        // The budget is opt-in, expenses created without it are not checked.
+        await createExpense(otherUser, new BN(3), {amount: new BN(1)});
+    });
+
+    it("Pays an expense in SPL tokens", async () => {
+        const otherUser = await createFundedUser();
+        const merchant = anchor.web3.Keypair.generate();
+
+        // A test mint with 6 decimals, the user holds 1 token.
+        const mint = await createMint(provider.connection, otherUser, otherUser.publicKey, null, 6);
+        const source = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, mint, otherUser.publicKey);
+        const destination = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, mint, merchant.publicKey);
+        await mintTo(provider.connection, otherUser, mint, source.address, otherUser, 1_000_000);
+
+        const pay = async (id: BN, value: BN) =>
+            program.methods
+                .payExpense(id, "Book Store", value)
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, id),
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: budgetPda(otherUser.publicKey),
+                    mint,
+                    source: source.address,
+                    destination: destination.address,
+                    authority: otherUser.publicKey,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        await pay(new BN(1), new BN(250_000));
+
+        assert.equal((await getAccount(provider.connection, source.address)).amount, BigInt(750_000));
+        assert.equal((await getAccount(provider.connection, destination.address)).amount, BigInt(250_000));
+
+        const expenseAccount = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, new BN(1)));
+        assert.isTrue(expenseAccount.amount.eqn(250_000), "The amount should match the payment");
+        assert.isTrue(expenseAccount.mint.equals(mint), "The mint should be recorded");
+        assert.isTrue(expenseAccount.isPaid, "A token payment should mark the expense paid");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, {other: {}}));
+        assert.isTrue(categoryIndex.total.eqn(250_000), "The payment should be counted in the category index");
+
+        // A payment that can't be covered fails as a whole, no expense is recorded.
+        try {
+            await pay(new BN(2), new BN(10_000_000));
+            assert.fail("The payment should have failed due to insufficient funds.");
+        } catch (error) {
+            assert.notInclude(error.toString(), "The payment should have failed");
+        }
+        assert.isNull(
+            await provider.connection.getAccountInfo(expensePda(otherUser.publicKey, new BN(2))),
+            "No expense should be recorded for a failed payment"
+        );
+        assert.equal((await getAccount(provider.connection, source.address)).amount, BigInt(750_000));
+    });
+
+    it("Creates split expenses whose shares sum up to the amount", async () => {
+        const otherUser = await createFundedUser();
+
+        const splitPda = (id: BN) => {
+            const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [anchor.utils.bytes.utf8.encode("split"), otherUser.publicKey.toBuffer(), id.toBuffer("le", 8)],
+                program.programId
+            );
+            return pda;
+        };
+        const createSplit = (id: BN, value: BN, shares: { participant: anchor.web3.PublicKey; amount: BN }[]) =>
+            program.methods
+                .createSplitExpense(id, value, shares)
+                .accounts({
+                    splitExpense: splitPda(id),
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+        const participants = (count: number, share: BN) =>
+            Array.from({length: count}, () => ({participant: anchor.web3.Keypair.generate().publicKey, amount: share}));
+
+        // A valid even split
+        const evenShares = participants(3, new BN(30));
+        await createSplit(new BN(1), new BN(90), evenShares);
+        const splitExpense = await program.account.splitExpense.fetch(splitPda(new BN(1)));
+        assert.isTrue(splitExpense.amount.eqn(90), "The amount should match");
+        assert.equal(splitExpense.shares.length, 3, "All participants should be stored");
+        splitExpense.shares.forEach((share, index) => {
+            assert.isTrue(share.participant.equals(evenShares[index].participant), "The participant should match");
+            assert.isTrue(share.amount.eqn(30), "The share should match");
+        });
+
+        // Shares that don't sum up to the total
+        await expectError(createSplit(new BN(2), new BN(100), participants(3, new BN(30))), "SplitMismatch");
+
+        // MAX_SPLIT_PARTICIPANTS is 16.
+        await expectError(createSplit(new BN(3), new BN(17), participants(17, new BN(1))), "TooManyParticipants");
+    });
+
+    it("Instantiates expenses from a recurring template", async () => {
+        const otherUser = await createFundedUser();
+        const templateId = new BN(1);
+        const [templatePda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("recurring"), otherUser.publicKey.toBuffer(), templateId.toBuffer("le", 8)],
+            program.programId
+        );
+
+        // A monthly subscription
+        await program.methods
+            .createRecurring(templateId, "Streaming", new BN(999), new BN(30 * 24 * 60 * 60))
+            .accounts({
+                template: templatePda,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+
+        const instantiate = async (newExpenseId: BN) =>
+            program.methods
+                .instantiateFromTemplate(templateId, newExpenseId)
+                .accounts({
+                    template: templatePda,
+                    expenseAccount: expensePda(otherUser.publicKey, newExpenseId),
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        await instantiate(new BN(1));
+
+        const expenseAccount = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, new BN(1)));
+        assert.equal(expenseAccount.merchantName, "Streaming", "The merchant name should come from the template");
+        assert.isTrue(expenseAccount.amount.eqn(999), "The amount should come from the template");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, {other: {}}));
+        assert.isTrue(categoryIndex.total.eqn(999), "The instance should be counted in the category index");
+
+        const template = await program.account.recurringTemplate.fetch(templatePda);
+        assert.isTrue(template.lastInstantiatedAt.eq(expenseAccount.createdAt), "The instantiation time should be recorded");
+
+        // Calling again before the interval elapsed is rejected.
+        await expectError(instantiate(new BN(2)), "RecurringTooSoon");
+    });
+
+    it("Approves and rejects expenses", async () => {
+        const otherUser = await createFundedUser();
+        const approver = await createFundedUser();
+        const approvedPda = await createExpense(otherUser, new BN(1));
+        const rejectedPda = await createExpense(otherUser, new BN(2));
+
+        const review = (signer: anchor.web3.Keypair, pda: anchor.web3.PublicKey, id: BN, approved: boolean) =>
+            program.methods
+                .approveExpense(id, approved)
+                .accounts({
+                    expenseAccount: pda,
+                    approver: signer.publicKey,
+                })
+                .signers([signer])
+                .rpc();
+
+        let expenseAccount = await program.account.expenseAccount.fetch(approvedPda);
+        assert.deepEqual(expenseAccount.status, {pending: {}}, "A new expense should be pending");
+        assert.isNull(expenseAccount.approver, "A new expense should have no approver");
+
+        // The owner is an approver too, but can't approve their own expense
+        await setApprovers([approver.publicKey, otherUser.publicKey]);
+        try {
+            // Only the approvers of the config can review expenses
+            const stranger = await createFundedUser();
+            await expectError(review(stranger, approvedPda, new BN(1), true), "Unauthorized");
+            await expectError(review(stranger, rejectedPda, new BN(2), false), "Unauthorized");
+
+            // No self-approval
+            await expectError(review(otherUser, approvedPda, new BN(1), true), "SelfApproval");
+
+            await review(approver, approvedPda, new BN(1), true);
+            expenseAccount = await program.account.expenseAccount.fetch(approvedPda);
+            assert.deepEqual(expenseAccount.status, {approved: {}}, "The expense should be approved");
+            assert.isTrue(expenseAccount.approver.equals(approver.publicKey), "The approver should be recorded");
+
+            await review(approver, rejectedPda, new BN(2), false);
+            expenseAccount = await program.account.expenseAccount.fetch(rejectedPda);
+            assert.deepEqual(expenseAccount.status, {rejected: {}}, "The expense should be rejected");
+
+            // A reviewed expense can't be reviewed again.
+            await expectError(review(approver, rejectedPda, new BN(2), true), "ExpenseAlreadyReviewed");
+        } finally {
+            // The config is shared by every test, remove the approvers again
+            await setApprovers([]);
+        }
+    });
+
+    it("Only lets the admin set the approvers", async () => {
+        const stranger = await createFundedUser();
+        await expectError(
+            program.methods
+                .setApprovers([stranger.publicKey])
+                .accounts({admin: stranger.publicKey})
+                .signers([stranger])
+                .rpc(),
+            "Unauthorized"
+        );
+        await expectError(
+            setApprovers(Array.from({length: 17}, () => anchor.web3.Keypair.generate().publicKey)),
+            "TooManyApprovers"
+        );
+
+        const config = await program.account.config.fetch(configPda());
+        assert.isEmpty(config.approvers, "The rejected approvers shouldn't be stored");
+    });
+
+    it("Stores and updates the receipt hash", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const receiptHash = Array.from(Buffer.alloc(32, 7));
+        const pda = await createExpense(otherUser, id, {receiptHash});
+
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.deepEqual(expenseAccount.receiptHash, receiptHash, "The receipt hash should match");
+
+        const updateReceiptHash = (hash: number[]) =>
+            program.methods
+                .updateReceiptHash(id, hash)
+                .accounts({
+                    expenseAccount: pda,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        const newReceiptHash = Array.from(Buffer.alloc(32, 9));
+        await updateReceiptHash(newReceiptHash);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.deepEqual(expenseAccount.receiptHash, newReceiptHash, "The receipt hash should be updated");
+
+        // Detaching the receipt
+        await updateReceiptHash(noReceipt);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.deepEqual(expenseAccount.receiptHash, noReceipt, "The receipt hash should be cleared");
+    });
+
+    it("Resizes the account when the merchant name changes", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const note = "Keep the note intact";
+        const pda = await createExpense(otherUser, id, {merchantName: "Cafe Shop", note});
+        const initialSize = (await provider.connection.getAccountInfo(pda)).data.length;
+
+        // Grow well past the original 12-byte reservation
+        const longName = "The Very Long Name Of A Neighbourhood Cafe";
+        await modifyExpense(otherUser, id, {merchantName: longName});
+
+        let accountInfo = await provider.connection.getAccountInfo(pda);
+        assert.equal(accountInfo.data.length, initialSize + longName.length - "Cafe Shop".length);
+        assert.isAtLeast(
+            accountInfo.lamports,
+            await provider.connection.getMinimumBalanceForRentExemption(accountInfo.data.length),
+            "The grown account should stay rent exempt"
+        );
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.merchantName, longName, "The merchant name should match");
+        assert.equal(expenseAccount.note, note, "The note should be intact");
+
+        // Shrink it again
+        await modifyExpense(otherUser, id, {merchantName: "Tea"});
+
+        accountInfo = await provider.connection.getAccountInfo(pda);
+        assert.equal(accountInfo.data.length, initialSize + "Tea".length - "Cafe Shop".length);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.merchantName, "Tea", "The merchant name should match");
+        assert.equal(expenseAccount.note, note, "The note should be intact");
+    });
+
+    it("Stores the payment method", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const methods = ["cash", "card", "crypto", "bankTransfer", "other"];
+
+        const pda = await createExpense(otherUser, id, {paymentMethod: {crypto: {}}});
+        const created = await program.account.expenseAccount.fetch(pda);
+        assert.deepEqual(created.paymentMethod, {crypto: {}}, "The payment method should be set on creation");
+
+        for (const method of methods) {
+            await modifyExpense(otherUser, id, {paymentMethod: {[method]: {}}});
+            const expenseAccount = await program.account.expenseAccount.fetch(pda);
+            assert.deepEqual(expenseAccount.paymentMethod, {[method]: {}}, `The payment method should be ${method}`);
+        }
+    });
+
+    it("Requires a reason to delete an expense", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+
+        await expectError(deleteExpense(otherUser, id, ""), "DeleteReasonEmpty");
+        // MAX_DELETE_REASON_LEN is 64 bytes.
+        await expectError(deleteExpense(otherUser, id, "x".repeat(65)), "DeleteReasonTooLong");
+        assert.isNotNull(await provider.connection.getAccountInfo(pda), "The expense should still exist");
+
+        const reason = "Duplicate of the card statement entry";
+        const events = await getEvents(await deleteExpense(otherUser, id, reason));
+        const deleted = events.find(event => event.name === "expenseDeleted");
+        assert.isDefined(deleted, "An ExpenseDeleted event should be emitted");
+        assert.equal(deleted.data.reason, reason, "The event should carry the reason");
+        assert.isNull(await provider.connection.getAccountInfo(pda), "The expense should be closed");
+    });
+
+    it("Lets a delegate modify but not delete an expense", async () => {
+        const otherUser = await createFundedUser();
+        const delegate = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+
+        const setDelegate = (newDelegate: anchor.web3.PublicKey | null) =>
+            program.methods
+                .setDelegate(id, newDelegate)
+                .accounts({
+                    expenseAccount: pda,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        await setDelegate(delegate.publicKey);
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.delegate.equals(delegate.publicKey), "The delegate should be stored");
+
+        // The delegate can modify
+        await modifyExpense(otherUser, id, {amount: new BN(77), authority: delegate});
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amount.eqn(77), "The delegate's modification should be applied");
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.totalSpent.eqn(77), "The owner's stats should reflect the delegate's modification");
+
+        // ...but not delete
+        try {
+            await program.methods
+                .deleteExpense(id, "Not the delegate's call")
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(otherUser.publicKey),
+                    categoryIndex: null,
+                    rentDestination: delegate.publicKey,
+                    authority: delegate.publicKey,
+                })
+                .signers([delegate])
+                .rpc();
+            assert.fail("The delegate should not be able to delete the expense.");
+        } catch (error) {
+            assert.notInclude(error.toString(), "should not be able to delete");
+        }
+        assert.isNotNull(await provider.connection.getAccountInfo(pda), "The expense should still exist");
+
+        // Revoking the delegate blocks further modifications
+        await setDelegate(null);
+        await expectError(modifyExpense(otherUser, id, {amount: new BN(88), authority: delegate}), "Unauthorized");
+    });
+
+    it("Fails cleanly instead of overflowing the total spent", async () => {
+        const otherUser = await createFundedUser();
+        const u64Max = new BN("18446744073709551615");
+
+        // Lift the limits and the maximum amount out of the way
+        const budget = budgetPda(otherUser.publicKey);
+        await program.methods
+            .setBudget(u64Max, new BN(0), u64Max, new BN(0), anchor.web3.PublicKey.default, 1, 0)
+            .accounts({budget, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+
+        // Push the total right up to u64::MAX
+        await createExpense(otherUser, new BN(1), {amount: u64Max.subn(1), budget});
+        await createExpense(otherUser, new BN(2), {amount: new BN(1), budget});
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.totalSpent.eq(u64Max), "The total spent should be u64::MAX");
+
+        // The next add errors instead of wrapping around
+        await expectError(createExpense(otherUser, new BN(3), {amount: new BN(1)}), "ArithmeticOverflow");
+        await expectError(modifyExpense(otherUser, new BN(2), {amount: new BN(2)}), "ArithmeticOverflow");
+    });
+
+    it("Creates several expenses in one batch", async () => {
+        const otherUser = await createFundedUser();
+        const expenses = [
+            {id: new BN(1), merchantName: "Bakery", amount: new BN(5)},
+            {id: new BN(2), merchantName: "Pharmacy", amount: new BN(15)},
+            {id: new BN(3), merchantName: "Cinema", amount: new BN(30)},
+        ];
+
+        const createBatch = async (inputs: typeof expenses, accounts: anchor.web3.PublicKey[]) =>
+            program.methods
+                .initializeExpensesBatch(inputs)
+                .accounts({
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .remainingAccounts(accounts.map(pubkey => ({pubkey, isWritable: true, isSigner: false})))
+                .signers([otherUser])
+                .rpc();
+
+        // An account that is not the PDA of the expense is rejected
+        await expectError(
+            createBatch(expenses, [1, 3, 2].map(id => expensePda(otherUser.publicKey, new BN(id)))),
+            "InvalidExpenseAccount"
+        );
+
+        await createBatch(expenses, expenses.map(expense => expensePda(otherUser.publicKey, expense.id)));
+
+        for (const expense of expenses) {
+            const expenseAccount = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, expense.id));
+            assert.isTrue(expenseAccount.id.eq(expense.id), "The ID should match");
+            assert.equal(expenseAccount.merchantName, expense.merchantName, "The merchant name should match");
+            assert.isTrue(expenseAccount.amount.eq(expense.amount), "The amount should match");
+            assert.isTrue(expenseAccount.owner.equals(otherUser.publicKey), "The owner should be the user");
+            assert.deepEqual(expenseAccount.currency, Array.from(Buffer.from("USD")), "The currency should be the base one");
+            assert.deepEqual(expenseAccount.category, {other: {}}, "The category should be the default one");
+        }
+
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.expenseCount.eqn(3), "The batch should be counted in the stats");
+        assert.isTrue(stats.totalSpent.eqn(50), "The batch should be added to the total spent");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, {other: {}}));
+        assert.isTrue(categoryIndex.count.eqn(3), "The batch should be counted in the category index");
+        assert.isTrue(categoryIndex.total.eqn(50), "The batch should be added to the category index");
+        const rollup = await program.account.monthlyRollup.fetch(rollupPda(otherUser.publicKey, await currentPeriod()));
+        assert.isTrue(rollup.count.eqn(3), "The batch should be counted in the rollup");
+        assert.isTrue(rollup.total.eqn(50), "The batch should be added to the rollup");
+
+        // The minimum amount of the user applies to batches too
+        await program.methods
+            .setMinAmount(new BN(10))
+            .accounts({authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        const small = [{id: new BN(4), merchantName: "Kiosk", amount: new BN(9)}];
+        await expectError(createBatch(small, [expensePda(otherUser.publicKey, new BN(4))]), "AmountBelowMinimum");
+    });
+
+    it("Adds and removes tags, resizing the account", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const initialSize = (await provider.connection.getAccountInfo(pda)).data.length;
+
+        const addTag = (tag: string) =>
+            program.methods
+                .addTag(id, tag)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const removeTag = (tag: string) =>
+            program.methods
+                .removeTag(id, tag)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        await addTag("travel");
+        await expectError(addTag("travel"), "DuplicateTag");
+        await expectError(addTag("x".repeat(17)), "TagTooLong");
+
+        // Fill up to the cap
+        for (let i = 1; i < 8; i++) {
+            await addTag(`tag${i}`);
+        }
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.tags.length, 8, "The expense should have 8 tags");
+        assert.equal(expenseAccount.tags[0], "travel", "The tags should keep their order");
+        await expectError(addTag("one-too-many"), "TooManyTags");
+
+        // Removing a tag shrinks the account back
+        const fullSize = (await provider.connection.getAccountInfo(pda)).data.length;
+        await removeTag("travel");
+        const shrunkSize = (await provider.connection.getAccountInfo(pda)).data.length;
+        assert.equal(shrunkSize, fullSize - (4 + "travel".length), "The account should shrink by the tag");
+        await expectError(removeTag("travel"), "TagNotFound");
+
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.notInclude(expenseAccount.tags, "travel", "The tag should be removed");
+        assert.equal(expenseAccount.tags.length, 7, "The other tags should be kept");
+        for (let i = 1; i < 8; i++) {
+            await removeTag(`tag${i}`);
+        }
+        const finalSize = (await provider.connection.getAccountInfo(pda)).data.length;
+        assert.equal(finalSize, initialSize, "The account should be back to its initial size");
+    });
+
+    it("Archives and unarchives an expense, keeping the stats in sync", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {amount: new BN(40)});
+        await createExpense(otherUser, new BN(2), {amount: new BN(2)});
+
+        const archive = (archived: boolean) =>
+            program.methods
+                .archiveExpense(id, archived)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const fetchStats = () => program.account.userStats.fetch(statsPda(otherUser.publicKey));
+
+        await archive(true);
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.isArchived, "The expense should be archived");
+        let stats = await fetchStats();
+        assert.isTrue(stats.totalSpent.eqn(2), "The archived amount should be excluded from the total");
+        assert.isTrue(stats.expenseCount.eqn(1), "The archived expense should not be counted");
+
+        // Archiving twice does not remove the amount twice
+        await archive(true);
+        stats = await fetchStats();
+        assert.isTrue(stats.totalSpent.eqn(2), "The total should be unchanged");
+
+        await archive(false);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isFalse(expenseAccount.isArchived, "The expense should be unarchived");
+        stats = await fetchStats();
+        assert.isTrue(stats.totalSpent.eqn(42), "The amount should be restored");
+        assert.isTrue(stats.expenseCount.eqn(2), "The expense should be counted again");
+    });
+
+    it("Marks a bill as paid", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const now = Math.floor(Date.now() / 1000);
+        const dueDate = new BN(now + 7 * 24 * 60 * 60);
+        const pda = await createExpense(otherUser, id, {dueDate});
+
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.dueDate.eq(dueDate), "The due date should be stored");
+        assert.isFalse(expenseAccount.isPaid, "A new bill should not be paid");
+        assert.isTrue(expenseAccount.paidAt.eqn(0), "The payment time should not be set");
+
+        const markPaid = () =>
+            program.methods
+                .markPaid(id)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        await markPaid();
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.isPaid, "The bill should be paid");
+        assert.isTrue(expenseAccount.paidAt.gtn(0), "The payment time should be recorded");
+        assert.isTrue(expenseAccount.updatedAt.eq(expenseAccount.paidAt), "Paying should update the timestamp");
+
+        await expectError(markPaid(), "ExpenseAlreadyPaid");
+    });
+
+    it("Rejects a due date in the past", async () => {
+        const otherUser = await createFundedUser();
+        const now = Math.floor(Date.now() / 1000);
+
+        await expectError(
+            createExpense(otherUser, new BN(1), {dueDate: new BN(now - 24 * 60 * 60)}),
+            "DueDateInPast"
+        );
+        // Expenses without a due date are not bills and are not validated
+        await createExpense(otherUser, new BN(1), {dueDate: noDueDate});
+    });
+
+    it("Rejects changes while the program is paused", async () => {
+        const otherUser = await createFundedUser();
+        const approver = await createFundedUser();
+        const pda = await createExpense(otherUser, new BN(1));
+        const templateId = new BN(1);
+        const [templatePda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("recurring"), otherUser.publicKey.toBuffer(), templateId.toBuffer("le", 8)],
+            program.programId
+        );
+        await program.methods
+            .createRecurring(templateId, "Streaming", new BN(999), new BN(30 * 24 * 60 * 60))
+            .accounts({template: templatePda, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        const mint = await createMint(provider.connection, otherUser, otherUser.publicKey, null, 6);
+        const source = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, mint, otherUser.publicKey);
+        const destination = await getOrCreateAssociatedTokenAccount(
+            provider.connection,
+            otherUser,
+            mint,
+            anchor.web3.Keypair.generate().publicKey
+        );
+        await mintTo(provider.connection, otherUser, mint, source.address, otherUser, 1_000);
+
+        const setPaused = (paused: boolean, admin?: anchor.web3.Keypair) =>
+            program.methods
+                .setPaused(paused)
+                .accounts({admin: admin ? admin.publicKey : provider.wallet.publicKey})
+                .signers(admin ? [admin] : [])
+                .rpc();
+
+        // Only the admin can toggle the switch
+        await expectError(setPaused(true, otherUser), "Unauthorized");
+
+        await setPaused(true);
+        try {
+            const config = await program.account.config.fetch(configPda());
+            assert.isTrue(config.paused, "The program should be paused");
+
+            await expectError(createExpense(otherUser, new BN(2)), "ProgramPaused");
+            await expectError(modifyExpense(otherUser, new BN(1), {amount: new BN(20)}), "ProgramPaused");
+            await expectError(deleteExpense(otherUser, new BN(1)), "ProgramPaused");
+
+            // Every other instruction changing state is stopped as well
+            await expectError(
+                program.methods
+                    .payExpense(new BN(2), "Book Store", new BN(100))
+                    .accounts({
+                        expenseAccount: expensePda(otherUser.publicKey, new BN(2)),
+                        userStats: statsPda(otherUser.publicKey),
+                        rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                        categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                        budget: budgetPda(otherUser.publicKey),
+                        mint,
+                        source: source.address,
+                        destination: destination.address,
+                        authority: otherUser.publicKey,
+                        tokenProgram: TOKEN_PROGRAM_ID,
+                    })
+                    .signers([otherUser])
+                    .rpc(),
+                "ProgramPaused"
+            );
+            await expectError(
+                program.methods
+                    .initializeExpensesBatch([{id: new BN(2), merchantName: "Bakery", amount: new BN(5)}])
+                    .accounts({
+                        userStats: statsPda(otherUser.publicKey),
+                        rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                        categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                        budget: null,
+                        authority: otherUser.publicKey,
+                    })
+                    .remainingAccounts([{pubkey: expensePda(otherUser.publicKey, new BN(2)), isWritable: true, isSigner: false}])
+                    .signers([otherUser])
+                    .rpc(),
+                "ProgramPaused"
+            );
+            await expectError(
+                program.methods
+                    .instantiateFromTemplate(templateId, new BN(2))
+                    .accounts({
+                        template: templatePda,
+                        expenseAccount: expensePda(otherUser.publicKey, new BN(2)),
+                        userStats: statsPda(otherUser.publicKey),
+                        rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                        categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                        budget: null,
+                        authority: otherUser.publicKey,
+                    })
+                    .signers([otherUser])
+                    .rpc(),
+                "ProgramPaused"
+            );
+            await expectError(
+                program.methods
+                    .transferOwnership(new BN(1), approver.publicKey)
+                    .accounts({
+                        expenseAccount: pda,
+                        authority: otherUser.publicKey,
+                        ...(await ownerCategoryIndices(pda, approver.publicKey)),
+                    })
+                    .signers([otherUser])
+                    .rpc(),
+                "ProgramPaused"
+            );
+            await setApprovers([approver.publicKey]);
+            await expectError(
+                program.methods
+                    .approveExpense(new BN(1), true)
+                    .accounts({expenseAccount: pda, approver: approver.publicKey})
+                    .signers([approver])
+                    .rpc(),
+                "ProgramPaused"
+            );
+            await expectError(
+                program.methods
+                    .applyRefund(new BN(1), new BN(5))
+                    .accounts({expenseAccount: pda, categoryIndex: await categoryIndexOf(pda), authority: otherUser.publicKey})
+                    .signers([otherUser])
+                    .rpc(),
+                "ProgramPaused"
+            );
+            await expectError(
+                program.methods
+                    .archiveExpense(new BN(1), true)
+                    .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                    .signers([otherUser])
+                    .rpc(),
+                "ProgramPaused"
+            );
+            await expectError(
+                program.methods
+                    .addTag(new BN(1), "travel")
+                    .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                    .signers([otherUser])
+                    .rpc(),
+                "ProgramPaused"
+            );
+            await expectError(
+                program.methods
+                    .setMinAmount(new BN(100))
+                    .accounts({authority: otherUser.publicKey})
+                    .signers([otherUser])
+                    .rpc(),
+                "ProgramPaused"
+            );
+        } finally {
+            // The config is shared by every test, never leave it paused
+            await setPaused(false);
+            await setApprovers([]);
+        }
+
+        await createExpense(otherUser, new BN(2));
+        await modifyExpense(otherUser, new BN(1), {amount: new BN(20)});
+        await deleteExpense(otherUser, new BN(2));
+    });
+
+    it("Migrates an expense created before the layout was versioned", async () => {
+        // Loaded into the validator from `tests/fixtures/legacy-expense.json`
+        const owner = anchor.web3.Keypair.fromSecretKey(
+            Uint8Array.from(JSON.parse(fs.readFileSync("tests/fixtures/legacy-owner.json", "utf8")))
+        );
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(owner.publicKey, LAMPORTS_PER_SOL)
+        );
+        const legacyOwner = owner.publicKey;
+        const id = new BN(1);
+        const pda = expensePda(legacyOwner, id);
+
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.version, 0, "The legacy account should read as version 0");
+
+        // Migrating is permissionless, any payer can bring the account up to date
+        const payer = await createFundedUser();
+        const migrateExpense = (id: BN, categoryIndex: anchor.web3.PublicKey) =>
+            program.methods
+                .migrateExpense(id)
+                .accounts({expenseAccount: expensePda(legacyOwner, id), owner: legacyOwner, categoryIndex, payer: payer.publicKey})
+                .signers([payer])
+                .rpc();
+        await migrateExpense(id, categoryIndexPda(legacyOwner, {food: {}}));
+
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.version, 22, "The account should be at the current version");
+        const [, legacyBump] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("expense"), legacyOwner.toBuffer(), id.toBuffer("le", 8)],
+            program.programId
+        );
+        assert.equal(expenseAccount.bump, legacyBump, "The migration should store the canonical bump");
+        assert.isTrue(expenseAccount.owner.equals(legacyOwner), "The owner should be preserved");
+        assert.isTrue(expenseAccount.creator.equals(legacyOwner), "The owner should be taken as the creator");
+        assert.equal(expenseAccount.merchantName, "Legacy Diner", "The merchant name should be preserved");
+        assert.isTrue(expenseAccount.amount.eqn(1234), "The amount should be preserved");
+        assert.equal(expenseAccount.note, "before versioning", "The note should be preserved");
+        assert.deepEqual(expenseAccount.tags, ["legacy"], "The tags should be preserved");
+        assert.equal(Buffer.from(expenseAccount.currency).toString(), "EUR", "The currency should be preserved");
+        assert.isTrue(expenseAccount.fxRate.eq(identityFxRate), "The legacy amount should convert 1:1");
+        assert.isTrue(expenseAccount.amountInBase.eqn(1234), "The amount in base should match the amount");
+        assert.isTrue(expenseAccount.netAmount.eqn(1234), "Without refunds the net amount should be the amount");
+        assert.deepEqual(expenseAccount.category, {food: {}}, "The stored category should be preserved");
+        assert.isTrue(expenseAccount.inCategoryIndex, "The legacy expense should be added to its category index");
+        let userStats = await program.account.userStats.fetch(statsPda(legacyOwner));
+        assert.isTrue(userStats.expenseCount.eqn(1), "The legacy expense should be added to the stats");
+        assert.isTrue(userStats.totalSpent.eqn(1234), "The legacy amount should be added to the stats");
+        let categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(legacyOwner, {food: {}}));
+        assert.isTrue(categoryIndex.count.eqn(1), "The index should count the legacy expense");
+
+        // `tests/fixtures/baseline-expense.json` is in the original layout, without the
+        // category and the payment method
+        const baselineId = new BN(2);
+        await migrateExpense(baselineId, categoryIndexPda(legacyOwner, {other: {}}));
+        const baselineExpense = await program.account.expenseAccount.fetch(expensePda(legacyOwner, baselineId));
+        assert.equal(baselineExpense.merchantName, "First Bistro", "The merchant name should be preserved");
+        assert.isTrue(baselineExpense.amount.eqn(500), "The amount should be preserved");
+        assert.deepEqual(baselineExpense.category, {other: {}}, "The default category of the owner should be taken");
+        assert.deepEqual(baselineExpense.paymentMethod, {other: {}}, "The default payment method should be taken");
+        categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(legacyOwner, {other: {}}));
+        assert.isTrue(categoryIndex.count.eqn(1), "The index of the default category should count it");
+
+        // The migrated expenses are modified and deleted like any other
+        await modifyExpense(owner, id, {amount: new BN(1000)});
+        userStats = await program.account.userStats.fetch(statsPda(legacyOwner));
+        assert.isTrue(userStats.totalSpent.eqn(1500), "The modification should reach the stats");
+        categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(legacyOwner, {food: {}}));
+        assert.isTrue(categoryIndex.total.eqn(1000), "The modification should reach the category index");
+        await deleteExpense(owner, id);
+        await deleteExpense(owner, baselineId);
+        userStats = await program.account.userStats.fetch(statsPda(legacyOwner));
+        assert.isTrue(userStats.expenseCount.eqn(0), "The deletions should reach the stats");
+        assert.isTrue(userStats.totalSpent.eqn(0), "The deletions should reach the stats");
+        categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(legacyOwner, {food: {}}));
+        assert.isTrue(categoryIndex.count.eqn(0), "The deletion should reach the category index");
+
+        // New expenses start at the current version
+        const otherUser = await createFundedUser();
+        const newExpense = await program.account.expenseAccount.fetch(await createExpense(otherUser, new BN(1)));
+        assert.equal(newExpense.version, 22, "New expenses should be at the current version");
+    });
+
+    it("Enforces the daily limit and resets it on a new day", async () => {
+        const otherUser = await createFundedUser();
+        const budget = budgetPda(otherUser.publicKey);
+        await program.methods
+            .setBudget(new BN(1000), new BN(50), new BN(0), new BN(0), anchor.web3.PublicKey.default, 1, 0)
+            .accounts({budget, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+
+        await createExpense(otherUser, new BN(1), {amount: new BN(30), budget});
+        await createExpense(otherUser, new BN(2), {amount: new BN(20), budget});
+        await expectError(createExpense(otherUser, new BN(3), {amount: new BN(1), budget}), "DailyLimitExceeded");
+
+        const budgetAccount = await program.account.budget.fetch(budget);
+        assert.isTrue(budgetAccount.spentToday.eqn(50), "The daily spending should be at the limit");
+        assert.equal(budgetAccount.dayStart.toNumber() % 86_400, 0, "The day should start at midnight UTC");
+
+        // The budget loaded from `tests/fixtures/stale-budget.json` is at its daily limit,
+        // but on 1970-01-02, so today's first expense starts a new day.
+        const staleOwner = anchor.web3.Keypair.fromSecretKey(
+            Uint8Array.from(JSON.parse(fs.readFileSync("tests/fixtures/budget-owner.json", "utf8")))
+        );
+        const airdropSignature = await provider.connection.requestAirdrop(staleOwner.publicKey, LAMPORTS_PER_SOL);
+        await provider.connection.confirmTransaction(airdropSignature);
+        const staleBudget = budgetPda(staleOwner.publicKey);
+        let staleBudgetAccount = await program.account.budget.fetch(staleBudget);
+        assert.isTrue(staleBudgetAccount.spentToday.eq(staleBudgetAccount.dailyLimit), "The fixture should be at its limit");
+
+        await createExpense(staleOwner, new BN(1), {amount: new BN(30), budget: staleBudget});
+        staleBudgetAccount = await program.account.budget.fetch(staleBudget);
+        assert.isTrue(staleBudgetAccount.spentToday.eqn(30), "The counter should reset on the new day");
+        assert.isTrue(staleBudgetAccount.dayStart.gtn(86_400), "The day should move forward");
+        await expectError(
+            createExpense(staleOwner, new BN(2), {amount: new BN(21), budget: staleBudget}),
+            "DailyLimitExceeded"
+        );
+    });
+
+    it("Stores the canonical bump of the expense PDA", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const [pda, bump] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("expense"), otherUser.publicKey.toBuffer(), id.toBuffer("le", 8)],
+            program.programId
+        );
+        assert.isTrue((await createExpense(otherUser, id)).equals(pda), "The helper should derive the same PDA");
+
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.bump, bump, "The stored bump should be the canonical one");
+
+        // Later instructions validate the address with the stored bump
+        await modifyExpense(otherUser, id, {amount: new BN(11)});
+    });
+
+    it("Caps the number of expenses per user", async () => {
+        const otherUser = await createFundedUser();
+        const maxExpenses = 1000;
+        const batchSize = 8;
+        // The rent of a thousand expense accounts is more than the 1 SOL of a funded user
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(otherUser.publicKey, 4 * LAMPORTS_PER_SOL)
+        );
+
+        // Fill up to the cap in batches to keep the number of transactions down
+        for (let first = 1; first <= maxExpenses; first += batchSize) {
+            const expenses = Array.from({length: batchSize}, (_, i) => ({
+                id: new BN(first + i),
+                merchantName: "Shop",
+                amount: new BN(1),
+            }));
+            await program.methods
+                .initializeExpensesBatch(expenses)
+                .accounts({
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .remainingAccounts(
+                    expenses.map(expense => ({
+                        pubkey: expensePda(otherUser.publicKey, expense.id),
+                        isWritable: true,
+                        isSigner: false,
+                    }))
+                )
+                .signers([otherUser])
+                .rpc();
+        }
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.equal(stats.expenseCount.toNumber(), maxExpenses, "The user should be at the cap");
+
+        await expectError(createExpense(otherUser, new BN(maxExpenses + 1)), "TooManyExpenses");
+
+        // Deleting an expense frees a slot
+        await deleteExpense(otherUser, new BN(1));
+        await createExpense(otherUser, new BN(maxExpenses + 1));
+    });
+
+    it("Applies refunds to the expense and the stats", async () => {
+        const otherUser = await createFundedUser();
+        const pda = await createExpense(otherUser, new BN(1), {amount: new BN(100)});
+        await createExpense(otherUser, new BN(2), {amount: new BN(5)});
+
+        const refund = async (id: BN, refundAmount: BN) =>
+            program.methods
+                .applyRefund(id, refundAmount)
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, id),
+                    categoryIndex: await currentCategoryIndex(otherUser.publicKey, id),
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+        const fetchStats = () => program.account.userStats.fetch(statsPda(otherUser.publicKey));
+
+        // Partial refund
+        await refund(new BN(1), new BN(30));
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amount.eqn(100), "The amount as entered should stay");
+        assert.isTrue(expenseAccount.netAmount.eqn(70), "The refund should be subtracted from the net amount");
+        assert.isTrue(expenseAccount.totalRefunded.eqn(30), "The refund should be recorded");
+        assert.isTrue((await fetchStats()).totalSpent.eqn(75), "The refund should be subtracted from the total");
+
+        // Over-refund
+        await expectError(refund(new BN(1), new BN(71)), "RefundExceedsAmount");
+
+        // Full refund
+        await refund(new BN(1), new BN(70));
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.netAmount.eqn(0), "A full refund should leave nothing");
+        assert.isTrue(expenseAccount.totalRefunded.eqn(100), "The refunds should add up");
+        const stats = await fetchStats();
+        assert.isTrue(stats.totalSpent.eqn(5), "Only the other expense should be left in the total");
+        assert.isTrue(stats.expenseCount.eqn(2), "A refunded expense is still counted");
+
+        // The refunds are kept when the amount is modified, the net amount clamps at zero
+        await modifyExpense(otherUser, new BN(1), {amount: new BN(80)});
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.totalRefunded.eqn(100), "The refunds should be kept");
+        assert.isTrue(expenseAccount.netAmount.eqn(0), "An amount below the refunds should leave nothing");
+        await expectError(refund(new BN(1), new BN(1)), "RefundExceedsAmount");
+
+        await modifyExpense(otherUser, new BN(1), {amount: new BN(150)});
+        await refund(new BN(1), new BN(20));
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amount.eqn(150), "The modified amount should stay");
+        assert.isTrue(expenseAccount.totalRefunded.eqn(120), "The refunds should keep adding up");
+        assert.isTrue(
+            expenseAccount.netAmount.eq(expenseAccount.amount.sub(expenseAccount.totalRefunded)),
+            "The net amount should be the amount less the refunds"
+        );
+        assert.isTrue((await fetchStats()).totalSpent.eqn(35), "The stats should add up the net amounts");
+    });
+
+    it("Registers a merchant and links an expense to it", async () => {
+        const otherUser = await createFundedUser();
+        // The PDA is seeded with the hash of the canonical name
+        const merchantPda = (canonicalName: string) => {
+            const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [anchor.utils.bytes.utf8.encode("merchant"), createHash("sha256").update(canonicalName).digest()],
+                program.programId
+            );
+            return pda;
+        };
+        const registerMerchant = (name: string) =>
+            program.methods
+                .registerMerchant(name)
+                .accounts({merchant: merchantPda("starbucks"), payer: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        await registerMerchant("  Starbucks ");
+        const merchant = await program.account.merchant.fetch(merchantPda("starbucks"));
+        assert.equal(merchant.name, "starbucks", "The name should be stored in its canonical form");
+        assert.isTrue(merchant.registeredBy.equals(otherUser.publicKey), "The registrant should be recorded");
+
+        // The same merchant typed differently is already registered
+        let duplicateError: Error | undefined;
+        try {
+            await registerMerchant("STARBUCKS");
+        } catch (error) {
+            duplicateError = error;
+        }
+        assert.isDefined(duplicateError, "Registering the same merchant twice should fail.");
+        assert.include(duplicateError.toString(), "already in use", "Expected the merchant account to exist already.");
+
+        const pda = await createExpense(otherUser, new BN(1), {
+            merchantName: "Starbucks Downtown",
+            merchant: merchantPda("starbucks"),
+        });
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.merchant.equals(merchantPda("starbucks")), "The expense should be linked");
+        assert.equal(expenseAccount.merchantName, "Starbucks Downtown", "The free-text name should be kept");
+
+        // Linking is optional
+        const unlinked = await program.account.expenseAccount.fetch(await createExpense(otherUser, new BN(2)));
+        assert.isNull(unlinked.merchant, "An expense without a merchant should not be linked");
+    });
+
+    it("Sends the reclaimed rent to the given destination on delete", async () => {
+        const otherUser = await createFundedUser();
+        const treasury = anchor.web3.Keypair.generate().publicKey;
+        const pda = await createExpense(otherUser, new BN(1));
+        const rent = await getBalance(pda);
+
+        const ownerBalance = await getBalance(otherUser.publicKey);
+        await deleteExpense(otherUser, new BN(1), "Sweeping to the treasury", treasury);
+
+        assert.equal(await getBalance(treasury), rent, "The rent should land in the destination");
+        // The owner only pays the transaction fee
+        assert.isBelow(await getBalance(otherUser.publicKey), ownerBalance, "The owner should not get the rent");
+        assert.isNull(await provider.connection.getAccountInfo(pda), "The expense account should be closed");
+    });
+
+    it("Shares expenses within a group", async () => {
+        const creator = await createFundedUser();
+        const roommate = await createFundedUser();
+        const outsider = await createFundedUser();
+        const groupId = new BN(1);
+        const [group] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("group"), creator.publicKey.toBuffer(), groupId.toBuffer("le", 8)],
+            program.programId
+        );
+        const groupExpensePda = (id: BN) => {
+            const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [anchor.utils.bytes.utf8.encode("group_expense"), group.toBuffer(), id.toBuffer("le", 8)],
+                program.programId
+            );
+            return pda;
+        };
+        const createGroupExpense = (member: anchor.web3.Keypair, id: BN, value: BN) =>
+            program.methods
+                .createGroupExpense(id, "Utilities", value)
+                .accounts({group, groupExpense: groupExpensePda(id), member: member.publicKey})
+                .signers([member])
+                .rpc();
+
+        await program.methods.createGroup(groupId).accounts({authority: creator.publicKey}).signers([creator]).rpc();
+        const addMember = (member: anchor.web3.PublicKey) =>
+            program.methods
+                .addMember(groupId, member)
+                .accounts({authority: creator.publicKey})
+                .signers([creator])
+                .rpc();
+        await addMember(roommate.publicKey);
+        await expectError(addMember(roommate.publicKey), "AlreadyGroupMember");
+
+        let groupAccount = await program.account.group.fetch(group);
+        assert.equal(groupAccount.members.length, 2, "The group should have two members");
+        assert.isTrue(groupAccount.members[0].equals(creator.publicKey), "The creator should be the first member");
+        assert.isTrue(groupAccount.members[1].equals(roommate.publicKey), "The roommate should be a member");
+
+        // Any member can record a group expense
+        await createGroupExpense(roommate, new BN(1), new BN(90));
+        const groupExpense = await program.account.groupExpense.fetch(groupExpensePda(new BN(1)));
+        assert.isTrue(groupExpense.group.equals(group), "The expense should belong to the group");
+        assert.isTrue(groupExpense.paidBy.equals(roommate.publicKey), "The paying member should be recorded");
+        assert.isTrue(groupExpense.amount.eqn(90), "The amount should match");
+        groupAccount = await program.account.group.fetch(group);
+        assert.isTrue(groupAccount.totalSpent.eqn(90), "The amount should be attributed to the group");
+        assert.isNull(
+            await program.account.userStats.fetchNullable(statsPda(roommate.publicKey)),
+            "The amount should not be attributed to the member"
+        );
+
+        // Non-members cannot record group expenses
+        await expectError(createGroupExpense(outsider, new BN(2), new BN(10)), "NotGroupMember");
+    });
+
+    it("Settles a group against an equal share", async () => {
+        const [creator, first, second] = await Promise.all([createFundedUser(), createFundedUser(), createFundedUser()]);
+        const groupId = new BN(1);
+        const [group] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("group"), creator.publicKey.toBuffer(), groupId.toBuffer("le", 8)],
+            program.programId
+        );
+        const groupExpensePda = (id: number) => {
+            const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [anchor.utils.bytes.utf8.encode("group_expense"), group.toBuffer(), new BN(id).toBuffer("le", 8)],
+                program.programId
+            );
+            return pda;
+        };
+
+        await program.methods.createGroup(groupId).accounts({authority: creator.publicKey}).signers([creator]).rpc();
+        for (const member of [first, second]) {
+            await program.methods
+                .addMember(groupId, member.publicKey)
+                .accounts({authority: creator.publicKey})
+                .signers([creator])
+                .rpc();
+        }
+
+        // 151 in total, so each share is 50 with 1 left over for the creator
+        const contributions: [anchor.web3.Keypair, number][] = [[creator, 60], [first, 51], [creator, 40]];
+        for (const [index, [member, value]] of contributions.entries()) {
+            await program.methods
+                .createGroupExpense(new BN(index + 1), "Rent", new BN(value))
+                .accounts({group, groupExpense: groupExpensePda(index + 1), member: member.publicKey})
+                .signers([member])
+                .rpc();
+        }
+
+        const settle = (ids: number[]) =>
+            program.methods
+                .settleGroup(groupId)
+                .accounts({group, member: second.publicKey})
+                .remainingAccounts(ids.map(id => ({pubkey: groupExpensePda(id), isWritable: true, isSigner: false})))
+                .signers([second])
+                .rpc();
+
+        // Every expense must be passed exactly once
+        await expectError(settle([1, 2]), "GroupExpensesMismatch");
+        await expectError(settle([1, 2, 2]), "GroupExpensesMismatch");
+
+        await settle([1, 2, 3]);
+        const groupAccount = await program.account.group.fetch(group);
+        const balances = groupAccount.balances.map((balance: BN) => balance.toNumber());
+        assert.deepEqual(balances, [100 - 51, 51 - 50, 0 - 50], "The balances should be relative to an equal share");
+        assert.equal(balances.reduce((sum: number, balance: number) => sum + balance, 0), 0, "The balances should sum to zero");
+
+        // Settled expenses can't be deleted, later ones can until the next settlement
+        const deleteGroupExpense = (member: anchor.web3.Keypair, id: number) =>
+            program.methods
+                .deleteGroupExpense(new BN(id))
+                .accounts({group, groupExpense: groupExpensePda(id), member: member.publicKey})
+                .signers([member])
+                .rpc();
+        assert.isTrue((await program.account.groupExpense.fetch(groupExpensePda(1))).settled);
+        await expectError(deleteGroupExpense(creator, 1), "ExpenseSettled");
+        await program.methods
+            .createGroupExpense(new BN(4), "Groceries", new BN(30))
+            .accounts({group, groupExpense: groupExpensePda(4), member: first.publicKey})
+            .signers([first])
+            .rpc();
+        await expectError(deleteGroupExpense(second, 4), "Unauthorized");
+        await deleteGroupExpense(first, 4);
+        assert.isNull(await provider.connection.getAccountInfo(groupExpensePda(4)), "The unsettled expense should be closed");
+        const afterDelete = await program.account.group.fetch(group);
+        assert.isTrue(afterDelete.totalSpent.eqn(151), "The deleted expense should leave the group total");
+        assert.isTrue(afterDelete.expenseCount.eqn(3), "The deleted expense should leave the group count");
+    });
+
+    it("Rejects modifying and deleting a locked expense", async () => {
+        const otherUser = await createFundedUser();
+        const locked = new BN(1);
+        const unlocked = new BN(2);
+        await createExpense(otherUser, locked);
+        await createExpense(otherUser, unlocked);
+
+        const lock = (id: BN) =>
+            program.methods
+                .lockExpense(id)
+                .accounts({expenseAccount: expensePda(otherUser.publicKey, id), authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        await lock(locked);
+        const expenseAccount = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, locked));
+        assert.isTrue(expenseAccount.isLocked, "The expense should be locked");
+
+        await expectError(modifyExpense(otherUser, locked, {amount: new BN(20)}), "ExpenseLocked");
+        await expectError(deleteExpense(otherUser, locked), "ExpenseLocked");
+        // Locking is one-way
+        await expectError(lock(locked), "ExpenseLocked");
+
+        await modifyExpense(otherUser, unlocked, {amount: new BN(20)});
+        await deleteExpense(otherUser, unlocked);
+    });
+
+    it("Counts the modifications of an expense", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.modificationCount, 0, "A new expense should not be modified yet");
+
+        for (const value of [11, 12, 13]) {
+            await modifyExpense(otherUser, id, {amount: new BN(value)});
+        }
+        // A failed modification is not counted
+        await expectError(modifyExpense(otherUser, id, {amount: new BN(0)}), "AmountZero");
+
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.modificationCount, 3, "Every modification should be counted");
+    });
+
+    it("Modifies an expense with the canonical bump passed by the client", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const [, canonicalBump] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("expense"), otherUser.publicKey.toBuffer(), id.toBuffer("le", 8)],
+            program.programId
+        );
+        const modifyWithBump = async (bump: number, amount: BN) =>
+            program.methods
+                .modifyExpenseWithBump(id, "Bump Bistro", amount, {food: {}}, {card: {}}, bump)
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(otherUser.publicKey),
+                    categoryIndex: await currentCategoryIndex(otherUser.publicKey, id),
+                    newCategoryIndex: categoryIndexPda(otherUser.publicKey, {food: {}}),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        const signature = await modifyWithBump(canonicalBump, new BN(42));
+        const events = await getEvents(signature);
+        assert.isDefined(events.find(event => event.name === "expenseModified"), "An ExpenseModified event should be emitted");
+        const tx = await provider.connection.getTransaction(signature, {
+            commitment: "confirmed",
+            maxSupportedTransactionVersion: 0,
+        });
+        assert.isAbove(tx.meta.computeUnitsConsumed, 0, "The modification should report its compute units");
+
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.bump, canonicalBump, "The stored bump should be the canonical one");
+        assert.equal(expenseAccount.amount.toNumber(), 42, "The amount should be updated");
+        assert.equal(expenseAccount.merchantName, "Bump Bistro", "The merchant name should be updated");
+
+        // Any other bump derives another address, or none at all
+        for (const wrongBump of [(canonicalBump + 255) % 256, (canonicalBump + 1) % 256]) {
+            await expectError(modifyWithBump(wrongBump, new BN(43)), "ConstraintSeeds");
+        }
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.amount.toNumber(), 42, "A rejected modification should change nothing");
+    });
+
+    it("Caps the amount of a single expense", async () => {
+        const otherUser = await createFundedUser();
+        const maxAmount = new BN("1000000000000000");
+
+        await createExpense(otherUser, new BN(1), {amount: maxAmount});
+        await createExpense(otherUser, new BN(2), {amount: new BN(25)});
+        await expectError(createExpense(otherUser, new BN(3), {amount: maxAmount.addn(1)}), "AmountTooLarge");
+        await expectError(modifyExpense(otherUser, new BN(2), {amount: maxAmount.addn(1)}), "AmountTooLarge");
+
+        // A budget can set a per-user cap
+        const budget = budgetPda(otherUser.publicKey);
+        await program.methods
+            .setBudget(maxAmount.muln(4), new BN(0), new BN(500), new BN(0), anchor.web3.PublicKey.default, 1, 0)
+            .accounts({budget, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        await createExpense(otherUser, new BN(3), {amount: new BN(500), budget});
+        await expectError(createExpense(otherUser, new BN(4), {amount: new BN(501), budget}), "AmountTooLarge");
+        await expectError(modifyExpense(otherUser, new BN(2), {amount: new BN(501), budget}), "AmountTooLarge");
+        await modifyExpense(otherUser, new BN(2), {amount: new BN(40), budget});
+    });
+
+    it("Updates only the amount or only the merchant name", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {merchantName: "Grocer", amount: new BN(10), note: "Weekly"});
+
+        await program.methods
+            .updateAmount(id, new BN(25))
+            .accounts({expenseAccount: pda, categoryIndex: await categoryIndexOf(pda), budget: null, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amount.eqn(25), "The amount should be updated");
+        assert.equal(expenseAccount.merchantName, "Grocer", "The merchant name should be untouched");
+        assert.equal(expenseAccount.note, "Weekly", "The note should be untouched");
+        assert.equal(expenseAccount.modificationCount, 1, "The update should be counted");
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.totalSpent.eqn(25), "The stats should follow the new amount");
+
+        const updatedAt = expenseAccount.updatedAt;
+        await sleep(1000);
+        await program.methods
+            .renameMerchant(id, "Greengrocer")
+            .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.merchantName, "Greengrocer", "The merchant name should be updated");
+        assert.isTrue(expenseAccount.amount.eqn(25), "The amount should be untouched");
+        assert.equal(expenseAccount.note, "Weekly", "The note should be untouched");
+        assert.equal(expenseAccount.modificationCount, 2, "The rename should be counted");
+        assert.isTrue(expenseAccount.updatedAt.gt(updatedAt), "The rename should refresh updated_at");
+    });
+
+    it("Aggregates the spending per month in a rollup", async () => {
+        const otherUser = await createFundedUser();
+        const period = await currentPeriod();
+        const rollup = rollupPda(otherUser.publicKey, period);
+
+        await createExpense(otherUser, new BN(1), {amount: new BN(10)});
+        await createExpense(otherUser, new BN(2), {amount: new BN(32)});
+        // Added in the base currency, 100 EUR at 1.5
+        await createExpense(otherUser, new BN(3), {amount: new BN(100), currency: Array.from(Buffer.from("EUR")), fxRate: new BN(1_500_000)});
+
+        const rollupAccount = await program.account.monthlyRollup.fetch(rollup);
+        assert.equal(rollupAccount.period, period, "The rollup should be for the current month");
+        assert.isTrue(rollupAccount.total.eqn(192), "The rollup should sum up the amounts in the base currency");
+        assert.isTrue(rollupAccount.count.eqn(3), "The rollup should count the expenses");
+
+        // The expenses are filed by their creation time
+        const expenseAccount = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, new BN(2)));
+        const createdAt = new Date(expenseAccount.createdAt.toNumber() * 1000);
+        assert.equal(createdAt.getUTCFullYear() * 100 + createdAt.getUTCMonth() + 1, period, "The periods should match");
+
+        // A rollup of another month is rejected
+        const otherPeriod = period % 100 === 12 ? period + 89 : period + 1;
+        try {
+            await program.methods
+                .initializeExpense(new BN(4), merchantName, amount, category, currency, "", noReceipt, paymentMethod, noDueDate, identityFxRate, null)
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, new BN(4)),
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, otherPeriod),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, category),
+                    budget: null,
+                    merchant: null,
+                    duplicateGuard: null,
+                    submissionGuard: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+            assert.fail("The rollup of another month should be rejected.");
+        } catch (error) {
+            assert.equal(error.error?.errorCode?.code, "ConstraintSeeds", "Expected a seeds error.");
+        }
+    });
+
+    it("Attaches an IPFS receipt CID, resizing the account", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const initialSize = (await provider.connection.getAccountInfo(pda)).data.length;
+
+        const attachReceipt = (cid: string) =>
+            program.methods
+                .attachReceipt(id, cid)
+                .accounts({
+                    expenseAccount: pda,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        const cid = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+        await attachReceipt(cid);
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.receiptCid, cid, "The receipt CID should match");
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, initialSize + cid.length);
+
+        await expectError(attachReceipt("b".repeat(65)), "ReceiptCidTooLong");
+        await expectError(attachReceipt("bafybéi"), "InvalidReceiptCid");
+
+        // Detaching the CID shrinks the account back
+        await attachReceipt("");
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.receiptCid, "", "The receipt CID should be cleared");
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, initialSize);
+    });
+
+    it("Requires several approvals for large expenses", async () => {
+        const otherUser = await createFundedUser();
+        const approvers = [await createFundedUser(), await createFundedUser()];
+        const largePda = await createExpense(otherUser, new BN(1), {amount: new BN(5000)});
+        const smallPda = await createExpense(otherUser, new BN(2), {amount: new BN(500)});
+        // 5000 yen are 35 in the base currency
+        const foreignPda = await createExpense(otherUser, new BN(3), {
+            amount: new BN(5000),
+            currency: Array.from(Buffer.from("JPY")),
+            fxRate: new BN(7_000),
+        });
+
+        const setApprovalPolicy = (requiredApprovals: number, approvalThreshold: BN) =>
+            program.methods
+                .setApprovalPolicy(requiredApprovals, approvalThreshold)
+                .accounts({admin: provider.wallet.publicKey})
+                .rpc();
+        const review = (signer: anchor.web3.Keypair, pda: anchor.web3.PublicKey, id: BN) =>
+            program.methods
+                .approveExpense(id, true)
+                .accounts({
+                    expenseAccount: pda,
+                    approver: signer.publicKey,
+                })
+                .signers([signer])
+                .rpc();
+
+        await expectError(setApprovalPolicy(0, new BN(1000)), "InvalidRequiredApprovals");
+        await setApprovalPolicy(2, new BN(1000));
+        await setApprovers(approvers.map((approver) => approver.publicKey));
+        try {
+            // One approval is not enough for an expense above the threshold
+            await review(approvers[0], largePda, new BN(1));
+            let expenseAccount = await program.account.expenseAccount.fetch(largePda);
+            assert.deepEqual(expenseAccount.status, {pending: {}}, "The expense should still be pending");
+
+            // The same approver can't approve twice
+            await expectError(review(approvers[0], largePda, new BN(1)), "DuplicateApproval");
+
+            await review(approvers[1], largePda, new BN(1));
+            expenseAccount = await program.account.expenseAccount.fetch(largePda);
+            assert.deepEqual(expenseAccount.status, {approved: {}}, "The expense should be approved");
+            assert.isTrue(expenseAccount.approver.equals(approvers[1].publicKey), "The last approver should be recorded");
+
+            const [approvalPda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [anchor.utils.bytes.utf8.encode("approval"), largePda.toBuffer()],
+                program.programId
+            );
+            const multiApproval = await program.account.multiApproval.fetch(approvalPda);
+            assert.deepEqual(
+                multiApproval.approvers.map((key) => key.toBase58()),
+                approvers.map((approver) => approver.publicKey.toBase58()),
+                "Every approver should be recorded"
+            );
+
+            // Smaller expenses need a single approval
+            await review(approvers[0], smallPda, new BN(2));
+            expenseAccount = await program.account.expenseAccount.fetch(smallPda);
+            assert.deepEqual(expenseAccount.status, {approved: {}}, "The small expense should be approved");
+
+            // The threshold is in the base currency, whatever the currency of the expense
+            await review(approvers[0], foreignPda, new BN(3));
+            expenseAccount = await program.account.expenseAccount.fetch(foreignPda);
+            assert.deepEqual(expenseAccount.status, {approved: {}}, "The converted amount should be below the threshold");
+        } finally {
+            // The config is shared by every test, restore the single approval
+            await setApprovalPolicy(1, new BN(0));
+            await setApprovers([]);
+        }
+    });
+
+    it("Rejects empty merchant names and names with control characters", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+
+        await expectError(createExpense(otherUser, id, {merchantName: ""}), "MerchantNameEmpty");
+        await expectError(createExpense(otherUser, id, {merchantName: "Cafe\nShop"}), "MerchantNameInvalid");
+        await expectError(createExpense(otherUser, id, {merchantName: "\tCafe"}), "MerchantNameInvalid");
+
+        const pda = await createExpense(otherUser, id, {merchantName: "Café Shop #1"});
+        const renameMerchant = (name: string) =>
+            program.methods
+                .renameMerchant(id, name)
+                .accounts({
+                    expenseAccount: pda,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+        await expectError(renameMerchant(""), "MerchantNameEmpty");
+        await expectError(renameMerchant("Cafe\nShop"), "MerchantNameInvalid");
+
+        await renameMerchant("Tea House");
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.merchantName, "Tea House", "The merchant name should be updated");
+    });
+
+    it("Restores an archived expense", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {amount: new BN(40)});
+
+        const restore = () =>
+            program.methods
+                .restoreExpense(id)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        // Only archived expenses can be restored
+        await expectError(restore(), "ExpenseNotArchived");
+
+        await program.methods
+            .archiveExpense(id, true)
+            .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        const archivedAt = (await program.account.expenseAccount.fetch(pda)).updatedAt;
+
+        await restore();
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isFalse(expenseAccount.isArchived, "The expense should be active again");
+        assert.isTrue(expenseAccount.updatedAt.gte(archivedAt), "updated_at should be refreshed");
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.totalSpent.eqn(40), "The amount should be counted again");
+
+        await expectError(restore(), "ExpenseNotArchived");
+    });
+
+    it("Flags an expense for review", async () => {
+        const otherUser = await createFundedUser();
+        const delegate = await createFundedUser();
+        const stranger = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        await program.methods
+            .setDelegate(id, delegate.publicKey)
+            .accounts({
+                expenseAccount: pda,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+
+        const setFlag = (signer: anchor.web3.Keypair, flagged: boolean) =>
+            program.methods
+                .setFlag(id, flagged)
+                .accounts({
+                    expenseAccount: pda,
+                    authority: signer.publicKey,
+                })
+                .signers([signer])
+                .rpc();
+
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isFalse(expenseAccount.flagged, "A new expense should not be flagged");
+
+        const events = await getEvents(await setFlag(otherUser, true));
+        const flagged = events.find(event => event.name === "expenseFlagged");
+        assert.isDefined(flagged, "An ExpenseFlagged event should be emitted");
+        assert.isTrue(flagged.data.id.eq(id), "The event ID should match");
+        assert.isTrue(flagged.data.owner.equals(otherUser.publicKey), "The event owner should match");
+        assert.isTrue(flagged.data.flagged, "The event should carry the flag");
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.flagged, "The expense should be flagged");
+
+        // The delegate may clear the flag, anyone else may not touch it
+        await expectError(setFlag(stranger, false), "Unauthorized");
+        await setFlag(delegate, false);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isFalse(expenseAccount.flagged, "The flag should be cleared");
+    });
+
+    it("Converts the amount to the base currency for the stats", async () => {
+        const otherUser = await createFundedUser();
+        const fetchStats = () => program.account.userStats.fetch(statsPda(otherUser.publicKey));
+
+        // A rate of 1.0 keeps the amount
+        let pda = await createExpense(otherUser, new BN(1), {amount: new BN(250)});
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amountInBase.eqn(250), "The identity rate should keep the amount");
+        assert.isTrue((await fetchStats()).totalSpent.eqn(250), "The stats should add the amount in base");
+
+        // A rate of 0.92, rounded down
+        pda = await createExpense(otherUser, new BN(2), {amount: new BN(1001), fxRate: new BN(920_000)});
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amount.eqn(1001), "The amount should be kept in its own currency");
+        assert.isTrue(expenseAccount.amountInBase.eqn(920), "The amount should be converted");
+        assert.isTrue((await fetchStats()).totalSpent.eqn(1170), "The stats should add the amount in base");
+
+        // Later changes of the amount are converted at the captured rate
+        await modifyExpense(otherUser, new BN(2), {amount: new BN(500)});
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.amountInBase.eqn(460), "The new amount should be converted");
+        assert.isTrue((await fetchStats()).totalSpent.eqn(710), "The stats should follow the amount in base");
+
+        await expectError(createExpense(otherUser, new BN(3), {fxRate: new BN(0)}), "InvalidFxRate");
+        // The converted amount must fit into a u64
+        await expectError(
+            createExpense(otherUser, new BN(3), {amount: new BN("1000000000000000"), fxRate: new BN("100000000000000")}),
+            "ArithmeticOverflow"
+        );
+    });
+
+    it("Only lets the owner delete an expense", async () => {
+        const owner = await createFundedUser();
+        const attacker = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(owner, id);
+
+        // Passing another user's expense with the attacker as the signer
+        await expectError(
+            program.methods
+                .deleteExpense(id, "Not mine")
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(owner.publicKey),
+                    categoryIndex: null,
+                    rentDestination: attacker.publicKey,
+                    authority: attacker.publicKey,
+                })
+                .signers([attacker])
+                .rpc(),
+            "Unauthorized"
+        );
+
+        // The creator can no longer delete the expense after transferring it away
+        await program.methods
+            .transferOwnership(id, attacker.publicKey)
+            .accounts({
+                expenseAccount: pda,
+                authority: owner.publicKey,
+                ...(await ownerCategoryIndices(pda, attacker.publicKey)),
+            })
+            .signers([owner])
+            .rpc();
+        await expectError(deleteExpense(owner, id), "Unauthorized");
+
+        assert.isNotNull(await provider.connection.getAccountInfo(pda), "The expense should still exist");
+    });
+
+    it("Assigns sequential IDs to auto-created expenses", async () => {
+        const otherUser = await createFundedUser();
+
+        const createAuto = async (name: string) => {
+            const stats = await program.account.userStats.fetchNullable(statsPda(otherUser.publicKey));
+            const id = stats ? stats.nextId : new BN(0);
+            const pda = expensePda(otherUser.publicKey, id);
+            await program.methods
+                .initializeExpenseAuto(name, amount, null)
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+            return pda;
+        };
+
+        const firstPda = await createAuto("Bakery");
+        const secondPda = await createAuto("Cafe Shop");
+        assert.isFalse(firstPda.equals(secondPda), "The expenses should live at distinct PDAs");
+
+        const first = await program.account.expenseAccount.fetch(firstPda);
+        const second = await program.account.expenseAccount.fetch(secondPda);
+        assert.isTrue(first.id.eqn(0), "The first ID should be 0");
+        assert.isTrue(second.id.eqn(1), "The second ID should follow the first");
+        assert.equal(second.merchantName, "Cafe Shop", "The merchant name should match");
+
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.nextId.eqn(2), "The counter should be advanced past both");
+        assert.isTrue(stats.expenseCount.eqn(2), "Both expenses should be counted");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, {other: {}}));
+        assert.isTrue(categoryIndex.count.eqn(2), "Both expenses should be in the index of their category");
+    });
+
+    it("Keeps per-category indices in sync", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const foodIndex = categoryIndexPda(otherUser.publicKey, {food: {}});
+        const transportIndex = categoryIndexPda(otherUser.publicKey, {transport: {}});
+
+        await createExpense(otherUser, id, {amount: new BN(30), category: {food: {}}});
+        await createExpense(otherUser, new BN(2), {amount: new BN(5), category: {food: {}}});
+        let food = await program.account.categoryIndex.fetch(foodIndex);
+        assert.deepEqual(food.category, {food: {}}, "The index should be of its category");
+        assert.isTrue(food.total.eqn(35), "The index should sum up the amounts");
+        assert.isTrue(food.count.eqn(2), "The index should count the expenses");
+
+        // Changing the amount within the category
+        await modifyExpense(otherUser, id, {amount: new BN(40), category: {food: {}}});
+        food = await program.account.categoryIndex.fetch(foodIndex);
+        assert.isTrue(food.total.eqn(45), "The index should follow the amount");
+        assert.isTrue(food.count.eqn(2), "The count should be unchanged");
+
+        // Moving the expense from Food to Transport
+        await modifyExpense(otherUser, id, {amount: new BN(40), category: {transport: {}}});
+        food = await program.account.categoryIndex.fetch(foodIndex);
+        assert.isTrue(food.total.eqn(5), "The amount should leave the old category");
+        assert.isTrue(food.count.eqn(1), "The expense should leave the old category");
+        let transport = await program.account.categoryIndex.fetch(transportIndex);
+        assert.isTrue(transport.total.eqn(40), "The amount should join the new category");
+        assert.isTrue(transport.count.eqn(1), "The expense should join the new category");
+
+        await deleteExpense(otherUser, id);
+        transport = await program.account.categoryIndex.fetch(transportIndex);
+        assert.isTrue(transport.total.eqn(0), "The deleted amount should be removed");
+        assert.isTrue(transport.count.eqn(0), "The deleted expense should be removed");
+    });
+
+    it("Reimburses a requested expense from the company payer", async () => {
+        const employee = await createFundedUser();
+        const companyPayer = await createFundedUser();
+        const stranger = await createFundedUser();
+        const id = new BN(1);
+        const reimbursedAmount = new BN(LAMPORTS_PER_SOL / 10);
+        const pda = await createExpense(employee, id, {amount: reimbursedAmount});
+
+        const setReimbursementPayer = (payer: anchor.web3.PublicKey) =>
+            program.methods
+                .setReimbursementPayer(payer)
+                .accounts({admin: provider.wallet.publicKey})
+                .rpc();
+        const markReimbursed = (payer: anchor.web3.Keypair) =>
+            program.methods
+                .markReimbursed(id)
+                .accounts({
+                    payer: payer.publicKey,
+                    expenseAccount: pda,
+                    owner: employee.publicKey,
+                })
+                .signers([payer])
+                .rpc();
+
+        await setReimbursementPayer(companyPayer.publicKey);
+        try {
+            // Only requested expenses are reimbursed
+            await expectError(markReimbursed(companyPayer), "ReimbursementNotRequested");
+
+            await program.methods
+                .requestReimbursement(id)
+                .accounts({expenseAccount: pda, authority: employee.publicKey})
+                .signers([employee])
+                .rpc();
+            let expenseAccount = await program.account.expenseAccount.fetch(pda);
+            assert.deepEqual(expenseAccount.reimbursementStatus, {requested: {}}, "The reimbursement should be requested");
+
+            await expectError(markReimbursed(stranger), "Unauthorized");
+
+            const balanceBefore = await getBalance(employee.publicKey);
+            await markReimbursed(companyPayer);
+            expenseAccount = await program.account.expenseAccount.fetch(pda);
+            assert.deepEqual(expenseAccount.reimbursementStatus, {reimbursed: {}}, "The expense should be reimbursed");
+            assert.equal(
+                await getBalance(employee.publicKey),
+                balanceBefore + reimbursedAmount.toNumber(),
+                "The owner should receive the amount"
+            );
+
+            // Reimbursing twice is rejected
+            await expectError(markReimbursed(companyPayer), "ReimbursementNotRequested");
+        } finally {
+            // The config is shared by every test
+            await setReimbursementPayer(anchor.web3.PublicKey.default);
+        }
+    });
+
+    it("Breaks an expense into line items", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {amount: new BN(100)});
+        const [lineItemsPda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("items"), pda.toBuffer()],
+            program.programId
+        );
+
+        const addLineItem = (description: string, itemAmount: number) =>
+            program.methods
+                .addLineItem(id, description, new BN(itemAmount))
+                .accounts({
+                    expenseAccount: pda,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+        const removeLineItem = (index: number) =>
+            program.methods
+                .removeLineItem(id, index)
+                .accounts({
+                    expenseAccount: pda,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        await addLineItem("Coffee", 30);
+        await addLineItem("Croissant", 45);
+        await addLineItem("Tip", 25);
+        let lineItems = await program.account.expenseLineItems.fetch(lineItemsPda);
+        assert.deepEqual(
+            lineItems.items.map((item) => [item.description, item.amount.toNumber()]),
+            [["Coffee", 30], ["Croissant", 45], ["Tip", 25]],
+            "The line items should be stored in order"
+        );
+        let accountInfo = await provider.connection.getAccountInfo(lineItemsPda);
+        assert.equal(accountInfo.data.length, 8 + 32 + 4 + (4 + 6 + 8) + (4 + 9 + 8) + (4 + 3 + 8));
+        assert.isAtLeast(
+            accountInfo.lamports,
+            await provider.connection.getMinimumBalanceForRentExemption(accountInfo.data.length),
+            "The grown account should stay rent exempt"
+        );
+
+        // The items already add up to the whole amount
+        await expectError(addLineItem("Water", 1), "LineItemsExceedAmount");
+
+        await removeLineItem(1);
+        lineItems = await program.account.expenseLineItems.fetch(lineItemsPda);
+        assert.deepEqual(
+            lineItems.items.map((item) => item.description),
+            ["Coffee", "Tip"],
+            "The line item should be removed"
+        );
+        accountInfo = await provider.connection.getAccountInfo(lineItemsPda);
+        assert.equal(accountInfo.data.length, 8 + 32 + 4 + (4 + 6 + 8) + (4 + 3 + 8));
+        await expectError(removeLineItem(2), "LineItemNotFound");
+
+        // The freed amount can be itemized again
+        await addLineItem("Water", 45);
+    });
+
+    it("Freezes an expense once approved", async () => {
+        const otherUser = await createFundedUser();
+        const approver = await createFundedUser();
+        const approvedPda = await createExpense(otherUser, new BN(1));
+        await createExpense(otherUser, new BN(2));
+
+        await setApprovers([approver.publicKey]);
+        try {
+            await program.methods
+                .approveExpense(new BN(1), true)
+                .accounts({
+                    expenseAccount: approvedPda,
+                    approver: approver.publicKey,
+                })
+                .signers([approver])
+                .rpc();
+        } finally {
+            // The config is shared by every test, remove the approver again
+            await setApprovers([]);
+        }
+
+        await expectError(modifyExpense(otherUser, new BN(1), {amount: new BN(20)}), "ExpenseApproved");
+        await expectError(
+            program.methods
+                .updateAmount(new BN(1), new BN(20))
+                .accounts({
+                    expenseAccount: approvedPda,
+                    categoryIndex: await categoryIndexOf(approvedPda),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc(),
+            "ExpenseApproved"
+        );
+        await expectError(
+            program.methods
+                .renameMerchant(new BN(1), "Renamed")
+                .accounts({expenseAccount: approvedPda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc(),
+            "ExpenseApproved"
+        );
+        const expenseAccount = await program.account.expenseAccount.fetch(approvedPda);
+        assert.isTrue(expenseAccount.amount.eq(amount), "The approved expense should be unchanged");
+
+        // A pending expense stays editable
+        await modifyExpense(otherUser, new BN(2), {amount: new BN(20)});
+    });
+
+    it("Deletes several expenses in one batch", async () => {
+        const otherUser = await createFundedUser();
+        const ids = [new BN(1), new BN(2), new BN(3)];
+        for (const [index, id] of ids.entries()) {
+            await createExpense(otherUser, id, {amount: new BN(10 * (index + 1))});
+        }
+        await createExpense(otherUser, new BN(4), {amount: new BN(7)});
+        const pdas = ids.map(id => expensePda(otherUser.publicKey, id));
+        const foodIndex = categoryIndexPda(otherUser.publicKey, {food: {}});
+
+        const deleteBatch = (batchIds: BN[], accounts: anchor.web3.PublicKey[]) =>
+            program.methods
+                .deleteExpensesBatch(batchIds)
+                .accounts({
+                    userStats: statsPda(otherUser.publicKey),
+                    authority: otherUser.publicKey,
+                })
+                // Followed by the index the expenses are counted in
+                .remainingAccounts([...accounts, foodIndex].map(pubkey => ({pubkey, isWritable: true, isSigner: false})))
+                .signers([otherUser])
+                .rpc();
+
+        // The accounts must be the PDAs of the IDs, in order
+        await expectError(deleteBatch(ids, [pdas[1], pdas[0], pdas[2]]), "InvalidExpenseAccount");
+
+        const balanceBefore = await getBalance(otherUser.publicKey);
+        let rent = 0;
+        for (const pda of pdas) {
+            rent += (await provider.connection.getAccountInfo(pda)).lamports;
+        }
+        await deleteBatch(ids, pdas);
+
+        for (const pda of pdas) {
+            assert.isNull(await provider.connection.getAccountInfo(pda), "The expense account should be closed");
+        }
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.expenseCount.eqn(1), "Only the remaining expense should be counted");
+        assert.isTrue(stats.totalSpent.eqn(7), "The deleted amounts should be removed from the total");
+        const categoryIndex = await program.account.categoryIndex.fetch(foodIndex);
+        assert.isTrue(categoryIndex.count.eqn(1), "The deleted expenses should leave the category index");
+        assert.isTrue(categoryIndex.total.eqn(7), "The deleted amounts should leave the category index");
+        // The authority pays the fee out of the reclaimed rent
+        assert.isAbove(await getBalance(otherUser.publicKey), balanceBefore + rent - LAMPORTS_PER_SOL / 1000);
+    });
+
+    it("Raises a budget alert once per period", async () => {
+        const otherUser = await createFundedUser();
+        const budget = budgetPda(otherUser.publicKey);
+        await program.methods
+            .setBudget(new BN(100), new BN(0), new BN(0), new BN(80), anchor.web3.PublicKey.default, 1, 0)
+            .accounts({authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+
+        const createAndGetAlert = async (id: number, expenseAmount: number) => {
+            const pda = expensePda(otherUser.publicKey, new BN(id));
+            const signature = await program.methods
+                .initializeExpense(new BN(id), merchantName, new BN(expenseAmount), category, currency, "", noReceipt, paymentMethod, noDueDate, identityFxRate, null)
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, category),
+                    budget,
+                    merchant: null,
+                    duplicateGuard: null,
+                    submissionGuard: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+            return (await getEvents(signature)).find(event => event.name === "budgetAlert");
+        };
+
+        // Below the threshold
+        assert.isUndefined(await createAndGetAlert(1, 50), "No alert should be raised below the threshold");
+
+        const alert = await createAndGetAlert(2, 35);
+        assert.isDefined(alert, "The alert should be raised when crossing the threshold");
+        assert.isTrue(alert.data.owner.equals(otherUser.publicKey), "The alert owner should match");
+        assert.isTrue(alert.data.spent.eqn(85), "The alert should carry the spending");
+        assert.isTrue(alert.data.limit.eqn(100), "The alert should carry the limit");
+
+        // Not raised again in the same period
+        assert.isUndefined(await createAndGetAlert(3, 5), "The alert should be raised once per period");
+        const budgetAccount = await program.account.budget.fetch(budget);
+        assert.isTrue(budgetAccount.alertFired, "The alert should be recorded as raised");
+    });
+
+    it("Rejects due dates too far in the future", async () => {
+        const otherUser = await createFundedUser();
+        const maxFutureSeconds = 10 * 365 * 24 * 60 * 60;
+        // The bound is relative to the cluster clock, which only moves forward from here
+        const now = await provider.connection.getBlockTime(await provider.connection.getSlot());
+
+        await expectError(
+            createExpense(otherUser, new BN(1), {dueDate: new BN(now + maxFutureSeconds + 24 * 60 * 60)}),
+            "TimestampOutOfRange"
+        );
+
+        const dueDate = new BN(now + maxFutureSeconds);
+        const pda = await createExpense(otherUser, new BN(1), {dueDate});
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.dueDate.eq(dueDate), "A due date at the bound should be accepted");
+    });
+
+    it("Rejects duplicate expenses of a day while the guard is enabled", async () => {
+        const otherUser = await createFundedUser();
+        const dayStart = async () => {
+            const now = await provider.connection.getBlockTime(await provider.connection.getSlot());
+            return now - (now % (24 * 60 * 60));
+        };
+        const guardPda = async (name: string, guardedAmount: BN) => {
+            const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [
+                    anchor.utils.bytes.utf8.encode("dup"),
+                    otherUser.publicKey.toBuffer(),
+                    createHash("sha256").update(name.trim().toLowerCase()).digest(),
+                    guardedAmount.toBuffer("le", 8),
+                    new BN(await dayStart()).toBuffer("le", 8),
+                ],
+                program.programId
+            );
+            return pda;
+        };
+        const setDuplicateGuard = (enabled: boolean) =>
+            program.methods
+                .setDuplicateGuard(enabled)
+                .accounts({admin: provider.wallet.publicKey})
+                .rpc();
+
+        await setDuplicateGuard(true);
+        try {
+            const guard = await guardPda("Cafe Shop", new BN(12));
+            await createExpense(otherUser, new BN(1), {amount: new BN(12), duplicateGuard: guard});
+
+            // The same merchant, however typed, and amount on the same day is a duplicate
+            await expectError(
+                createExpense(otherUser, new BN(2), {merchantName: " cafe shop", amount: new BN(12), duplicateGuard: guard}),
+                "DuplicateExpense"
+            );
+            // Skipping the guard is not an option
+            await expectError(createExpense(otherUser, new BN(2), {amount: new BN(12)}), "DuplicateGuardMissing");
+
+            // Another amount at the same merchant is fine
+            await createExpense(otherUser, new BN(2), {
+                amount: new BN(13),
+                duplicateGuard: await guardPda("Cafe Shop", new BN(13)),
+            });
+        } finally {
+            // The config is shared by every test, the guard is opt-in
+            await setDuplicateGuard(false);
+        }
+
+        // Without the guard repeated purchases are allowed
+        await createExpense(otherUser, new BN(3), {amount: new BN(12)});
+    });
+
+    it("Recategorizes an expense, moving it between the category indices", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const foodIndex = categoryIndexPda(otherUser.publicKey, {food: {}});
+        const transportIndex = categoryIndexPda(otherUser.publicKey, {transport: {}});
+        const pda = await createExpense(otherUser, id, {
+            merchantName: "Taxi",
+            amount: new BN(20),
+            note: "Airport",
+            category: {food: {}},
+        });
+
+        await program.methods
+            .recategorize(id, {transport: {}})
+            .accounts({
+                expenseAccount: pda,
+                categoryIndex: foodIndex,
+                newCategoryIndex: transportIndex,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.deepEqual(expenseAccount.category, {transport: {}}, "The category should be updated");
+        assert.equal(expenseAccount.merchantName, "Taxi", "The merchant name should be untouched");
+        assert.isTrue(expenseAccount.amount.eqn(20), "The amount should be untouched");
+        assert.equal(expenseAccount.note, "Airport", "The note should be untouched");
+        assert.equal(expenseAccount.modificationCount, 1, "The recategorization should be counted");
+
+        const food = await program.account.categoryIndex.fetch(foodIndex);
+        assert.isTrue(food.total.eqn(0), "The amount should leave the old category");
+        assert.isTrue(food.count.eqn(0), "The expense should leave the old category");
+        const transport = await program.account.categoryIndex.fetch(transportIndex);
+        assert.isTrue(transport.total.eqn(20), "The amount should join the new category");
+        assert.isTrue(transport.count.eqn(1), "The expense should join the new category");
+    });
+
+    it("Keeps the category indices in step with amount, merge and owner changes", async () => {
+        const otherUser = await createFundedUser();
+        const newOwner = await createFundedUser();
+        const foodIndex = categoryIndexPda(otherUser.publicKey, {food: {}});
+        const transportIndex = categoryIndexPda(otherUser.publicKey, {transport: {}});
+        const pda = await createExpense(otherUser, new BN(1), {amount: new BN(10)});
+        await createExpense(otherUser, new BN(2), {amount: new BN(20)});
+        await createExpense(otherUser, new BN(3), {amount: new BN(30), category: {transport: {}}});
+        const updateAmount = (categoryIndex: anchor.web3.PublicKey | null) =>
+            program.methods
+                .updateAmount(new BN(1), new BN(15))
+                .accounts({expenseAccount: pda, categoryIndex, budget: null, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const mergeExpenses = (removeId: number, removedCategoryIndex: anchor.web3.PublicKey) =>
+            program.methods
+                .mergeExpenses(new BN(1), new BN(removeId))
+                .accounts({
+                    keptExpense: pda,
+                    removedExpense: expensePda(otherUser.publicKey, new BN(removeId)),
+                    keptCategoryIndex: foodIndex,
+                    removedCategoryIndex,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+        const expectIndex = async (index: anchor.web3.PublicKey, count: number, total: number, message: string) => {
+            const categoryIndex = await program.account.categoryIndex.fetch(index);
+            assert.isTrue(categoryIndex.count.eqn(count), message);
+            assert.isTrue(categoryIndex.total.eqn(total), message);
+        };
+
+        // An indexed expense can't change without its index
+        await expectError(updateAmount(null), "CategoryIndexMissing");
+        await updateAmount(foodIndex);
+        await program.methods
+            .applyRefund(new BN(1), new BN(5))
+            .accounts({expenseAccount: pda, categoryIndex: foodIndex, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        await expectIndex(foodIndex, 2, 30, "The new amount and the refund should reach the index");
+
+        // The removed amount moves to the category of the kept expense
+        await mergeExpenses(3, transportIndex);
+        await expectIndex(foodIndex, 2, 60, "The merged amount should join the index of the kept expense");
+        await expectIndex(transportIndex, 0, 0, "The removed expense should leave its index");
+        // With the same category both indices are the same account
+        await mergeExpenses(2, foodIndex);
+        await expectIndex(foodIndex, 1, 60, "Merging within a category should keep its total");
+
+        await program.methods
+            .transferOwnership(new BN(1), newOwner.publicKey)
+            .accounts({
+                expenseAccount: pda,
+                authority: otherUser.publicKey,
+                ...(await ownerCategoryIndices(pda, newOwner.publicKey)),
+            })
+            .signers([otherUser])
+            .rpc();
+        await expectIndex(foodIndex, 0, 0, "The transferred expense should leave the index of the former owner");
+        await expectIndex(categoryIndexPda(newOwner.publicKey, {food: {}}), 1, 60, "The transferred expense should join the index of the new owner");
+    });
+
+    it("Closes the stats and budget of a user without expenses", async () => {
+        const otherUser = await createFundedUser();
+        const stats = statsPda(otherUser.publicKey);
+        const budget = budgetPda(otherUser.publicKey);
+        await program.methods
+            .setBudget(new BN(100), new BN(0), new BN(0), new BN(0), anchor.web3.PublicKey.default, 1, 0)
+            .accounts({budget, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        await createExpense(otherUser, new BN(1));
+        const closeUser = () =>
+            program.methods
+                .closeUser()
+                .accounts({userStats: stats, budget, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        await expectError(closeUser(), "ExpensesOutstanding");
+
+        await deleteExpense(otherUser, new BN(1));
+        const balanceBefore = await provider.connection.getBalance(otherUser.publicKey);
+        await closeUser();
+        assert.isNull(await provider.connection.getAccountInfo(stats), "The stats should be closed");
+        assert.isNull(await provider.connection.getAccountInfo(budget), "The budget should be closed");
+        const balanceAfter = await provider.connection.getBalance(otherUser.publicKey);
+        assert.isAbove(balanceAfter, balanceBefore, "The rent should be returned to the user");
+    });
+
+    it("Emits the shares of a large split in chunks", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const [splitPda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("split"), otherUser.publicKey.toBuffer(), id.toBuffer("le", 8)],
+            program.programId
+        );
+        const shares = Array.from({length: 16}, (_, index) => ({
+            participant: anchor.web3.Keypair.generate().publicKey,
+            amount: new BN(index + 1),
+        }));
+
+        const txSignature = await program.methods
+            .createSplitExpense(id, new BN(136), shares)
+            .accounts({splitExpense: splitPda, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        const chunks = (await getEvents(txSignature)).filter(event => event.name === "splitSharesRecorded");
+        // SPLIT_EVENT_CHUNK_SIZE shares per event
+        assert.equal(chunks.length, 4, "Every chunk should be emitted");
+        chunks.forEach((chunk, index) => {
+            assert.isTrue(chunk.data.id.eq(id), "The event ID should match");
+            assert.equal(chunk.data.sequence, index, "The chunks should be emitted in order");
+            assert.equal(chunk.data.chunkCount, 4, "The chunk count should match");
+        });
+
+        const reassembled = chunks.flatMap(chunk => chunk.data.shares);
+        assert.equal(reassembled.length, shares.length, "The chunks should carry every share");
+        reassembled.forEach((share, index) => {
+            assert.isTrue(share.participant.equals(shares[index].participant), "The participant should match");
+            assert.isTrue(share.amount.eq(shares[index].amount), "The share should match");
+        });
+    });
+
+    it("Verifies the line items against the amount of an expense", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {amount: new BN(50)});
+        const [lineItemsPda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("items"), pda.toBuffer()],
+            program.programId
+        );
+        const addLineItem = (description: string, itemAmount: number) =>
+            program.methods
+                .addLineItem(id, description, new BN(itemAmount))
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const updateAmount = async (newAmount: number) =>
+            program.methods
+                .updateAmount(id, new BN(newAmount))
+                .accounts({expenseAccount: pda, categoryIndex: await categoryIndexOf(pda), budget: null, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const verifyExpense = () =>
+            program.methods
+                .verifyExpense(id)
+                .accounts({expenseAccount: pda, lineItems: lineItemsPda})
+                .rpc();
+
+        // The items sum up to less than the amount, the rest is uncategorized
+        await addLineItem("Lunch", 30);
+        await verifyExpense();
+
+        // The items sum up to the amount
+        await addLineItem("Dessert", 20);
+        await verifyExpense();
+
+        // The amount was lowered below the items
+        await updateAmount(40);
+        await expectError(verifyExpense(), "LineItemMismatch");
+    });
+
+    it("Stores key-value metadata of an expense", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const baseSize = (await provider.connection.getAccountInfo(pda)).data.length;
+        const setMetadata = (key: string, value: string) =>
+            program.methods
+                .setMetadata(id, key, value)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const removeMetadata = (key: string) =>
+            program.methods
+                .removeMetadata(id, key)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const entries = async () =>
+            (await program.account.expenseAccount.fetch(pda)).metadata.map((entry) => [entry.key, entry.value]);
+
+        await setMetadata("project", "apollo");
+        await setMetadata("cost_center", "42");
+        assert.deepEqual(await entries(), [["project", "apollo"], ["cost_center", "42"]]);
+        let accountInfo = await provider.connection.getAccountInfo(pda);
+        assert.equal(accountInfo.data.length, baseSize + (4 + 7) + (4 + 6) + (4 + 11) + (4 + 2));
+
+        // Overwriting keeps a single entry per key
+        await setMetadata("project", "artemis-2");
+        assert.deepEqual(await entries(), [["project", "artemis-2"], ["cost_center", "42"]]);
+        accountInfo = await provider.connection.getAccountInfo(pda);
+        assert.equal(accountInfo.data.length, baseSize + (4 + 7) + (4 + 9) + (4 + 11) + (4 + 2));
+
+        await removeMetadata("project");
+        assert.deepEqual(await entries(), [["cost_center", "42"]]);
+        accountInfo = await provider.connection.getAccountInfo(pda);
+        assert.equal(accountInfo.data.length, baseSize + (4 + 11) + (4 + 2));
+        await expectError(removeMetadata("project"), "MetadataKeyNotFound");
+
+        await expectError(setMetadata("k".repeat(17), "v"), "MetadataKeyTooLong");
+        await expectError(setMetadata("key", "v".repeat(65)), "MetadataValueTooLong");
+
+        for (let i = 1; i < 8; i++) {
+            await setMetadata(`key${i}`, "value");
+        }
+        await expectError(setMetadata("key8", "value"), "TooManyMetadataEntries");
+        // Overwriting still works at the cap
+        await setMetadata("key1", "other");
+    });
+
+    it("Exports the full state of an expense as an event", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {note: "Team lunch"});
+        await program.methods
+            .addTag(id, "work")
+            .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+
+        const txSignature = await program.methods
+            .exportExpense(id)
+            .accounts({expenseAccount: pda})
+            .rpc();
+        const snapshot = (await getEvents(txSignature)).find(event => event.name === "expenseSnapshot");
+        assert.isDefined(snapshot, "An ExpenseSnapshot event should be emitted");
+        assert.isTrue(snapshot.data.expense.equals(pda), "The snapshot should name the account");
+
+        // BN and PublicKey serialize to JSON by value, so every field is compared
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.deepEqual(
+            JSON.parse(JSON.stringify(snapshot.data.account)),
+            JSON.parse(JSON.stringify(expenseAccount)),
+            "The snapshot should match the account"
+        );
+    });
+
+    it("Tops up the rent when a modification grows the expense", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {merchantName: "Cafe"});
+
+        await modifyExpense(otherUser, id, {merchantName: "Cafe ".repeat(12)});
+        const accountInfo = await provider.connection.getAccountInfo(pda);
+        assert.isAtLeast(
+            accountInfo.lamports,
+            await provider.connection.getMinimumBalanceForRentExemption(accountInfo.data.length),
+            "The grown account should stay rent exempt"
+        );
+
+        // A delegate without lamports can't pay for a longer name, the fees are paid by
+        // the provider wallet
+        const delegate = anchor.web3.Keypair.generate();
+        await program.methods
+            .setDelegate(id, delegate.publicKey)
+            .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        await expectError(
+            modifyExpense(otherUser, id, {merchantName: "Cafe ".repeat(13), authority: delegate}),
+            "InsufficientRent"
+        );
+        // Shrinking refunds the excess instead
+        const balanceBefore = await provider.connection.getBalance(otherUser.publicKey);
+        await modifyExpense(otherUser, id, {merchantName: "Cafe"});
+        assert.isAbove(
+            await provider.connection.getBalance(otherUser.publicKey),
+            balanceBefore,
+            "The excess rent should be refunded"
+        );
+    });
+
+    it("Sums up the expenses of a date range", async () => {
+        const otherUser = await createFundedUser();
+        const outOfRange = await createExpense(otherUser, new BN(1), {amount: new BN(10)});
+        await sleep(2000);
+        const inRange = [
+            await createExpense(otherUser, new BN(2), {amount: new BN(20)}),
+            await createExpense(otherUser, new BN(3), {amount: new BN(30)}),
+        ];
+        const createdAt = async (pda: anchor.web3.PublicKey) =>
+            (await program.account.expenseAccount.fetch(pda)).createdAt;
+        const start = await createdAt(inRange[0]);
+        assert.isTrue((await createdAt(outOfRange)).lt(start), "The first expense should be created earlier");
+
+        const sumRange = (rangeStart: BN, rangeEnd: BN, expenses: anchor.web3.PublicKey[], authority = otherUser) =>
+            program.methods
+                .sumRange(rangeStart, rangeEnd)
+                .accounts({authority: authority.publicKey})
+                .remainingAccounts(expenses.map((pubkey) => ({pubkey, isSigner: false, isWritable: false})))
+                .signers([authority])
+                .rpc();
+
+        const events = await getEvents(await sumRange(start, start.addn(3600), [outOfRange, ...inRange]));
+        const rangeTotal = events.find(event => event.name === "rangeTotal");
+        assert.isDefined(rangeTotal, "A RangeTotal event should be emitted");
+        assert.isTrue(rangeTotal.data.start.eq(start), "The event start should match");
+        assert.isTrue(rangeTotal.data.end.eq(start.addn(3600)), "The event end should match");
+        assert.isTrue(rangeTotal.data.total.eqn(50), "Only the expenses in range should be summed up");
+        assert.isTrue(rangeTotal.data.count.eqn(2), "Only the expenses in range should be counted");
+
+        await expectError(sumRange(start, start.subn(1), inRange), "InvalidDateRange");
+        await expectError(sumRange(start, start.addn(3600), [inRange[0], inRange[0]]), "InvalidExpenseAccount");
+        const stranger = await createFundedUser();
+        await expectError(sumRange(start, start.addn(3600), inRange, stranger), "Unauthorized");
+    });
+
+    it("Shares an expense read-only with viewers", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const baseSize = (await provider.connection.getAccountInfo(pda)).data.length;
+        const addViewer = (viewer: anchor.web3.PublicKey) =>
+            program.methods
+                .addViewer(id, viewer)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const removeViewer = (viewer: anchor.web3.PublicKey) =>
+            program.methods
+                .removeViewer(id, viewer)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        const viewers = Array.from({length: 8}, () => anchor.web3.Keypair.generate().publicKey);
+        await addViewer(viewers[0]);
+        await expectError(addViewer(viewers[0]), "DuplicateViewer");
+        for (const viewer of viewers.slice(1)) {
+            await addViewer(viewer);
+        }
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.deepEqual(
+            expenseAccount.viewers.map((viewer) => viewer.toBase58()),
+            viewers.map((viewer) => viewer.toBase58()),
+            "The viewers should be stored in order"
+        );
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, baseSize + 8 * 32);
+        await expectError(addViewer(anchor.web3.Keypair.generate().publicKey), "TooManyViewers");
+
+        await removeViewer(viewers[3]);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.viewers.length, 7, "The viewer should be removed");
+        assert.isFalse(
+            expenseAccount.viewers.some((viewer) => viewer.equals(viewers[3])),
+            "The removed viewer should be gone"
+        );
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, baseSize + 7 * 32);
+        await expectError(removeViewer(viewers[3]), "ViewerNotFound");
+    });
+
+    it("Rejects modifications too soon after the last change", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        await createExpense(otherUser, id);
+        const setMinModifyInterval = (seconds: number) =>
+            program.methods
+                .setMinModifyInterval(seconds)
+                .accounts({admin: provider.wallet.publicKey})
+                .rpc();
+
+        await setMinModifyInterval(2);
+        try {
+            await expectError(modifyExpense(otherUser, id, {amount: new BN(20)}), "ModifyTooSoon");
+
+            await sleep(3000);
+            await modifyExpense(otherUser, id, {amount: new BN(20)});
+            await expectError(modifyExpense(otherUser, id, {amount: new BN(30)}), "ModifyTooSoon");
+        } finally {
+            // The config is shared by every test, the interval is off by default
+            await setMinModifyInterval(0);
+        }
+
+        await modifyExpense(otherUser, id, {amount: new BN(30)});
+        const expenseAccount = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, id));
+        assert.isTrue(expenseAccount.amount.eqn(30), "Without the interval modifications should pass");
+    });
+
+    it("Transfers an expense in two steps", async () => {
+        const otherUser = await createFundedUser();
+        const newOwner = await createFundedUser();
+        const stranger = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const proposeTransfer = (to: anchor.web3.PublicKey) =>
+            program.methods
+                .proposeTransfer(id, to)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const acceptTransfer = async (signer: anchor.web3.Keypair) =>
+            program.methods
+                .acceptTransfer(id)
+                .accounts({expenseAccount: pda, authority: signer.publicKey, ...(await ownerCategoryIndices(pda, signer.publicKey))})
+                .signers([signer])
+                .rpc();
+        const cancelTransfer = () =>
+            program.methods
+                .cancelTransfer(id)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        // Propose, then cancel
+        await proposeTransfer(newOwner.publicKey);
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.pendingOwner.equals(newOwner.publicKey), "The transfer should be pending");
+        assert.isTrue(expenseAccount.owner.equals(otherUser.publicKey), "The owner should be unchanged until accepted");
+        await cancelTransfer();
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isNull(expenseAccount.pendingOwner, "The transfer should be cancelled");
+        await expectError(acceptTransfer(newOwner), "Unauthorized");
+        await expectError(cancelTransfer(), "NoPendingTransfer");
+
+        // Propose, then accept, only the proposed owner can accept
+        await proposeTransfer(newOwner.publicKey);
+        await expectError(acceptTransfer(stranger), "Unauthorized");
+        await acceptTransfer(newOwner);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.owner.equals(newOwner.publicKey), "The ownership should be transferred");
+        assert.isNull(expenseAccount.pendingOwner, "The pending transfer should be cleared");
+        await expectError(proposeTransfer(stranger.publicKey), "Unauthorized");
+    });
+
+    it("Only counts payments in the token of the budget", async () => {
+        const otherUser = await createFundedUser();
+        const merchant = anchor.web3.Keypair.generate();
+        const budget = budgetPda(otherUser.publicKey);
+        const payIn = async (mint: anchor.web3.PublicKey, id: BN) => {
+            const source = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, mint, otherUser.publicKey);
+            const destination = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, mint, merchant.publicKey);
+            await mintTo(provider.connection, otherUser, mint, source.address, otherUser, 1_000);
+            return program.methods
+                .payExpense(id, "Book Store", new BN(100))
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, id),
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget,
+                    mint,
+                    source: source.address,
+                    destination: destination.address,
+                    authority: otherUser.publicKey,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .signers([otherUser])
+                .rpc();
+        };
+        const usdc = await createMint(provider.connection, otherUser, otherUser.publicKey, null, 6);
+        const otherToken = await createMint(provider.connection, otherUser, otherUser.publicKey, null, 6);
+        await program.methods
+            .setBudget(new BN(1000), new BN(0), new BN(0), new BN(0), usdc, 1, 0)
+            .accounts({budget, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+
+        await payIn(usdc, new BN(1));
+        const budgetAccount = await program.account.budget.fetch(budget);
+        assert.isTrue(budgetAccount.mint.equals(usdc), "The budget should be in its token");
+        assert.isTrue(budgetAccount.spentThisPeriod.eqn(100), "The payment should count against the budget");
+
+        await expectError(payIn(otherToken, new BN(2)), "MintMismatch");
+
+        // The budget can't be left out, nor replaced by the one of another user
+        const stranger = await createFundedUser();
+        await program.methods
+            .setBudget(new BN(1000), new BN(0), new BN(0), new BN(0), otherToken, 1, 0)
+            .accounts({budget: budgetPda(stranger.publicKey), authority: stranger.publicKey})
+            .signers([stranger])
+            .rpc();
+        const source = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, otherToken, otherUser.publicKey);
+        const destination = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, otherToken, merchant.publicKey);
+        await expectError(
+            program.methods
+                .payExpense(new BN(3), "Book Store", new BN(100))
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, new BN(3)),
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: budgetPda(stranger.publicKey),
+                    mint: otherToken,
+                    source: source.address,
+                    destination: destination.address,
+                    authority: otherUser.publicKey,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .signers([otherUser])
+                .rpc(),
+            "ConstraintSeeds"
+        );
+    });
+
+    it("Recovers an expense with the recovery key of its owner", async () => {
+        const otherUser = await createFundedUser();
+        const recoveryKey = await createFundedUser();
+        const newWallet = anchor.web3.Keypair.generate();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const recoverExpense = async (signer: anchor.web3.Keypair) =>
+            program.methods
+                .recoverExpense(id, newWallet.publicKey)
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(otherUser.publicKey),
+                    recoveryKey: signer.publicKey,
+                    ...(await ownerCategoryIndices(pda, newWallet.publicKey)),
+                })
+                .signers([signer])
+                .rpc();
+
+        // Recovery is opt-in
+        await expectError(recoverExpense(recoveryKey), "RecoveryKeyNotSet");
+
+        await program.methods
+            .setRecoveryKey(recoveryKey.publicKey)
+            .accounts({authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        const stranger = await createFundedUser();
+        await expectError(recoverExpense(stranger), "Unauthorized");
+
+        await recoverExpense(recoveryKey);
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.owner.equals(newWallet.publicKey), "The expense should be reassigned");
+    });
+
+    it("Lets the new owner modify and delete a transferred or recovered expense", async () => {
+        const creator = await createFundedUser();
+        const newOwner = await createFundedUser();
+        const transferred = await createExpense(creator, new BN(1), {amount: new BN(20)});
+        const recovered = await createExpense(creator, new BN(2), {amount: new BN(30)});
+        await program.methods
+            .transferOwnership(new BN(1), newOwner.publicKey)
+            .accounts({
+                expenseAccount: transferred,
+                authority: creator.publicKey,
+                ...(await ownerCategoryIndices(transferred, newOwner.publicKey)),
+            })
+            .signers([creator])
+            .rpc();
+        await program.methods
+            .setRecoveryKey(newOwner.publicKey)
+            .accounts({authority: creator.publicKey})
+            .signers([creator])
+            .rpc();
+        await program.methods
+            .recoverExpense(new BN(2), newOwner.publicKey)
+            .accounts({
+                expenseAccount: recovered,
+                userStats: statsPda(creator.publicKey),
+                recoveryKey: newOwner.publicKey,
+                ...(await ownerCategoryIndices(recovered, newOwner.publicKey)),
+            })
+            .signers([newOwner])
+            .rpc();
+
+        let creatorStats = await program.account.userStats.fetch(statsPda(creator.publicKey));
+        assert.isTrue(creatorStats.expenseCount.eqn(0), "Both expenses should leave the stats of the creator");
+        assert.isTrue(creatorStats.totalSpent.eqn(0), "Their amounts should leave the total of the creator");
+        let newOwnerStats = await program.account.userStats.fetch(statsPda(newOwner.publicKey));
+        assert.isTrue(newOwnerStats.expenseCount.eqn(2), "Both expenses should be counted for the new owner");
+        assert.isTrue(newOwnerStats.totalSpent.eqn(50), "Their amounts should add up for the new owner");
+
+        // The accounts stay at the address derived from the creator
+        for (const [id, pda] of [[new BN(1), transferred], [new BN(2), recovered]] as [BN, anchor.web3.PublicKey][]) {
+            await program.methods
+                .modifyExpense(id, "Book Store", new BN(40), {food: {}}, {card: {}})
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(newOwner.publicKey),
+                    categoryIndex: null,
+                    newCategoryIndex: categoryIndexPda(newOwner.publicKey, {food: {}}),
+                    budget: null,
+                    authority: newOwner.publicKey,
+                })
+                .signers([newOwner])
+                .rpc();
+            const expenseAccount = await program.account.expenseAccount.fetch(pda);
+            assert.isTrue(expenseAccount.amount.eqn(40), "The new owner should be able to modify the expense");
+        }
+        newOwnerStats = await program.account.userStats.fetch(statsPda(newOwner.publicKey));
+        assert.isTrue(newOwnerStats.totalSpent.eqn(80), "The modifications should update the stats of the new owner");
+
+        // The creator gave the expenses away and can no longer delete them
+        await expectError(
+            program.methods
+                .deleteExpense(new BN(1), "Not mine anymore")
+                .accounts({
+                    expenseAccount: transferred,
+                    userStats: statsPda(creator.publicKey),
+                    categoryIndex: null,
+                    rentDestination: creator.publicKey,
+                    authority: creator.publicKey,
+                })
+                .signers([creator])
+                .rpc(),
+            "Unauthorized"
+        );
+        for (const [id, pda] of [[new BN(1), transferred], [new BN(2), recovered]] as [BN, anchor.web3.PublicKey][]) {
+            await program.methods
+                .deleteExpense(id, "No longer needed")
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(newOwner.publicKey),
+                    categoryIndex: categoryIndexPda(newOwner.publicKey, {food: {}}),
+                    rentDestination: newOwner.publicKey,
+                    authority: newOwner.publicKey,
+                })
+                .signers([newOwner])
+                .rpc();
+            assert.isNull(await provider.connection.getAccountInfo(pda), "The new owner should be able to delete the expense");
+        }
+        newOwnerStats = await program.account.userStats.fetch(statsPda(newOwner.publicKey));
+        assert.isTrue(newOwnerStats.expenseCount.eqn(0), "The deletions should leave no expenses");
+        assert.isTrue(newOwnerStats.totalSpent.eqn(0), "The deletions should leave no total");
+        creatorStats = await program.account.userStats.fetch(statsPda(creator.publicKey));
+        assert.isTrue(creatorStats.expenseCount.eqn(0), "The stats of the creator shouldn't be touched");
+    });
+
+    it("Splits an amount evenly, giving the remainder to the first participants", async () => {
+        const otherUser = await createFundedUser();
+        const evenSplit = async (id: BN, amount: number, count: number) => {
+            const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [anchor.utils.bytes.utf8.encode("split"), otherUser.publicKey.toBuffer(), id.toBuffer("le", 8)],
+                program.programId
+            );
+            const participants = Array.from({length: count}, () => anchor.web3.Keypair.generate().publicKey);
+            await program.methods
+                .createEvenSplitExpense(id, new BN(amount), participants)
+                .accounts({splitExpense: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+            const splitExpense = await program.account.splitExpense.fetch(pda);
+            splitExpense.shares.forEach((share, index) => {
+                assert.isTrue(share.participant.equals(participants[index]), "The participants should keep their order");
+            });
+            return splitExpense.shares.map((share) => share.amount.toNumber());
+        };
+
+        assert.deepEqual(await evenSplit(new BN(1), 100, 4), [25, 25, 25, 25], "An even amount splits evenly");
+        // A remainder of 1
+        assert.deepEqual(await evenSplit(new BN(2), 10, 3), [4, 3, 3], "The first participant takes the remainder");
+        // A remainder of n - 1
+        assert.deepEqual(await evenSplit(new BN(3), 11, 4), [3, 3, 3, 2], "All but the last take a unit more");
+    });
+
+    it("Requires a receipt to delete an expense in compliance mode", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const setRequireReceiptToDelete = (enabled: boolean) =>
+            program.methods
+                .setRequireReceiptToDelete(enabled)
+                .accounts({admin: provider.wallet.publicKey})
+                .rpc();
+
+        await setRequireReceiptToDelete(true);
+        try {
+            await expectError(deleteExpense(otherUser, id), "ReceiptRequired");
+
+            await program.methods
+                .updateReceiptHash(id, Array.from(createHash("sha256").update("receipt").digest()))
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+            await deleteExpense(otherUser, id);
+            assert.isNull(await provider.connection.getAccountInfo(pda), "The documented expense should be deleted");
+        } finally {
+            // The config is shared by every test, the mode is off by default
+            await setRequireReceiptToDelete(false);
+        }
+    });
+
+    it("Rekeys an expense to a new ID", async () => {
+        const otherUser = await createFundedUser();
+        const oldId = new BN(1);
+        const newId = new BN(7);
+        const oldPda = await createExpense(otherUser, oldId, {merchantName: "Bakery", amount: new BN(15), note: "Bread"});
+        await createExpense(otherUser, new BN(2));
+        const rekeyExpense = (from: BN, to: BN) =>
+            program.methods
+                .rekeyExpense(from, to)
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, from),
+                    newExpenseAccount: expensePda(otherUser.publicKey, to),
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+        const before = await program.account.expenseAccount.fetch(oldPda);
+
+        // The ID of another expense is taken
+        let collisionError: Error | undefined;
+        try {
+            await rekeyExpense(oldId, new BN(2));
+        } catch (error) {
+            collisionError = error;
+        }
+        assert.isDefined(collisionError, "Rekeying to a taken ID should fail.");
+        assert.include(collisionError.toString(), "already in use", "Expected the new expense account to exist already.");
+
+        await rekeyExpense(oldId, newId);
+        assert.isNull(await provider.connection.getAccountInfo(oldPda), "The old account should be closed");
+        const after = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, newId));
+        assert.isTrue(after.id.eq(newId), "The expense should have the new ID");
+        const {id: _oldId, bump: _oldBump, ...copied} = before;
+        const {id: _newId, bump: _newBump, ...migrated} = after;
+        assert.deepEqual(
+            JSON.parse(JSON.stringify(migrated)),
+            JSON.parse(JSON.stringify(copied)),
+            "Every other field should be copied"
+        );
+    });
+
+    it("Tracks the largest expense, flagging it stale once deleted", async () => {
+        const otherUser = await createFundedUser();
+        const stats = statsPda(otherUser.publicKey);
+        await createExpense(otherUser, new BN(1), {amount: new BN(10)});
+        await createExpense(otherUser, new BN(2), {amount: new BN(50)});
+        await createExpense(otherUser, new BN(3), {amount: new BN(30)});
+        let userStats = await program.account.userStats.fetch(stats);
+        assert.isTrue(userStats.largestAmount.eqn(50), "The largest amount should be tracked");
+        assert.isFalse(userStats.largestAmountStale, "The largest amount should be exact");
+        assert.isTrue(userStats.totalSpent.div(userStats.expenseCount).eqn(30), "The average should be computable");
+
+        // Deleting a smaller expense keeps the maximum exact
+        await deleteExpense(otherUser, new BN(3));
+        userStats = await program.account.userStats.fetch(stats);
+        assert.isTrue(userStats.largestAmount.eqn(50), "The largest amount should be unchanged");
+        assert.isFalse(userStats.largestAmountStale, "The largest amount should stay exact");
+
+        // Deleting the largest one would need a rescan
+        await deleteExpense(otherUser, new BN(2));
+        userStats = await program.account.userStats.fetch(stats);
+        assert.isTrue(userStats.largestAmount.eqn(50), "The largest amount should be kept as an upper bound");
+        assert.isTrue(userStats.largestAmountStale, "The largest amount should be flagged stale");
+
+        // A new largest expense makes it exact again
+        await createExpense(otherUser, new BN(4), {amount: new BN(60)});
+        userStats = await program.account.userStats.fetch(stats);
+        assert.isTrue(userStats.largestAmount.eqn(60), "The new largest amount should be tracked");
+        assert.isFalse(userStats.largestAmountStale, "The largest amount should be exact again");
+    });
+
+    it("Stores and clears an encrypted note", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id);
+        const baseSize = (await provider.connection.getAccountInfo(pda)).data.length;
+        const setEncryptedNote = (ciphertext: Buffer, nonce: number[]) =>
+            program.methods
+                .setEncryptedNote(id, ciphertext, nonce)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+        const ciphertext = Buffer.from(Array.from({length: 48}, (_, index) => index));
+        const nonce = Array.from({length: 24}, (_, index) => index + 1);
+        const noNonce = Array(24).fill(0);
+
+        await setEncryptedNote(ciphertext, nonce);
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(Buffer.from(expenseAccount.encryptedNote).equals(ciphertext), "The ciphertext should be stored");
+        assert.deepEqual(expenseAccount.nonce, nonce, "The nonce should be stored");
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, baseSize + 48);
+
+        await expectError(setEncryptedNote(Buffer.alloc(257, 1), nonce), "EncryptedNoteTooLong");
+        await expectError(setEncryptedNote(ciphertext, noNonce), "NonceMissing");
+
+        await setEncryptedNote(Buffer.alloc(0), noNonce);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.encryptedNote.length, 0, "The note should be cleared");
+        assert.deepEqual(expenseAccount.nonce, noNonce, "The nonce should be cleared");
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, baseSize);
+    });
+
+    it("Modifies several expenses in one batch, reporting every update", async () => {
+        const otherUser = await createFundedUser();
+        const stranger = await createFundedUser();
+        await createExpense(otherUser, new BN(1), {amount: new BN(10)});
+        await createExpense(otherUser, new BN(2), {amount: new BN(20)});
+        await createExpense(otherUser, new BN(3), {amount: new BN(30)});
+        await createExpense(stranger, new BN(4), {amount: new BN(40)});
+        await program.methods
+            .lockExpense(new BN(3))
+            .accounts({expenseAccount: expensePda(otherUser.publicKey, new BN(3)), authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        // Compared case-insensitively, the client may camelCase the names of the IDL
+        const errorCode = (name: string) =>
+            program.idl.errors.find((error) => error.name.toLowerCase() === name.toLowerCase()).code;
+
+        const updates = [
+            {id: new BN(1), merchantName: "Bistro", amount: new BN(15)},
+            {id: new BN(2), merchantName: "", amount: new BN(25)},
+            {id: new BN(3), merchantName: "Cafe Shop", amount: new BN(35)},
+            // Not an expense of the signer
+            {id: new BN(4), merchantName: "Cafe Shop", amount: new BN(45)},
+        ];
+        const txSignature = await program.methods
+            .modifyExpensesBatch(updates)
+            .accounts({budget: null, authority: otherUser.publicKey})
+            .remainingAccounts([
+                expensePda(otherUser.publicKey, new BN(1)),
+                expensePda(otherUser.publicKey, new BN(2)),
+                expensePda(otherUser.publicKey, new BN(3)),
+                expensePda(stranger.publicKey, new BN(4)),
+                categoryIndexPda(otherUser.publicKey, {food: {}}),
+            ].map(pubkey => ({pubkey, isWritable: true, isSigner: false})))
+            .signers([otherUser])
+            .rpc();
+
+        const results = (await getEvents(txSignature))
+            .filter(event => event.name === "modifyResult")
+            .map(event => [event.data.id.toNumber(), event.data.success, event.data.errorCode]);
+        assert.deepEqual(results, [
+            [1, true, 0],
+            [2, false, errorCode("MerchantNameEmpty")],
+            [3, false, errorCode("ExpenseLocked")],
+            [4, false, errorCode("Unauthorized")],
+        ], "Every update should be reported");
+
+        const modified = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, new BN(1)));
+        assert.equal(modified.merchantName, "Bistro", "The valid update should apply");
+        assert.isTrue(modified.amount.eqn(15), "The valid update should apply");
+        const skipped = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, new BN(2)));
+        assert.isTrue(skipped.amount.eqn(20), "The failed update should be skipped");
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.totalSpent.eqn(65), "Only the valid update should reach the stats");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, {food: {}}));
+        assert.isTrue(categoryIndex.total.eqn(65), "Only the valid update should reach the category index");
+    });
+
+    it("Skips a batch update the authority can't pay the rent for", async () => {
+        const otherUser = await createFundedUser();
+        // An owner without lamports, the fees are paid by the provider wallet
+        const owner = anchor.web3.Keypair.generate();
+        const ids = [new BN(1), new BN(2)];
+        for (const id of ids) {
+            await createExpense(otherUser, id, {amount: new BN(10)});
+            await program.methods
+                .transferOwnership(id, owner.publicKey)
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, id),
+                    authority: otherUser.publicKey,
+                    ...(await ownerCategoryIndices(expensePda(otherUser.publicKey, id), owner.publicKey)),
+                })
+                .signers([otherUser])
+                .rpc();
+        }
+        const errorCode = (name: string) =>
+            program.idl.errors.find((error) => error.name.toLowerCase() === name.toLowerCase()).code;
+        const sizeBefore = (await provider.connection.getAccountInfo(expensePda(otherUser.publicKey, ids[0]))).data.length;
+
+        const txSignature = await program.methods
+            .modifyExpensesBatch([
+                // A longer name grows the account
+                {id: ids[0], merchantName: "Cafe Shop ".repeat(6), amount: new BN(15)},
+                // The same length needs no rent
+                {id: ids[1], merchantName: "Book Shop", amount: new BN(25)},
+            ])
+            .accounts({budget: null, authority: owner.publicKey})
+            .remainingAccounts(
+                [...ids.map(id => expensePda(otherUser.publicKey, id)), categoryIndexPda(owner.publicKey, {food: {}})]
+                    .map(pubkey => ({pubkey, isWritable: true, isSigner: false}))
+            )
+            .signers([owner])
+            .rpc();
+
+        const results = (await getEvents(txSignature))
+            .filter(event => event.name === "modifyResult")
+            .map(event => [event.data.id.toNumber(), event.data.success, event.data.errorCode]);
+        assert.deepEqual(results, [
+            [1, false, errorCode("InsufficientRent")],
+            [2, true, 0],
+        ], "Only the unfunded update should fail");
+
+        const skipped = await provider.connection.getAccountInfo(expensePda(otherUser.publicKey, ids[0]));
+        assert.equal(skipped.data.length, sizeBefore, "The unfunded expense shouldn't be resized");
+        const skippedExpense = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, ids[0]));
+        assert.equal(skippedExpense.merchantName, "Cafe Shop", "The unfunded update should be skipped");
+        const modified = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, ids[1]));
+        assert.equal(modified.merchantName, "Book Shop", "The funded update should apply");
+        const stats = await program.account.userStats.fetch(statsPda(owner.publicKey));
+        assert.isTrue(stats.totalSpent.eqn(35), "Only the funded update should reach the stats");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(owner.publicKey, {food: {}}));
+        assert.isTrue(categoryIndex.total.eqn(35), "Only the funded update should reach the category index");
+    });
+
+    it("Leaves a shrunk expense as it was when the stats update of a batch fails", async () => {
+        // The stats loaded from `tests/fixtures/overflowing-stats.json` are 10 below the
+        // largest total, so the expense below fills them up
+        const owner = anchor.web3.Keypair.fromSecretKey(
+            Uint8Array.from(JSON.parse(fs.readFileSync("tests/fixtures/overflowing-stats-owner.json", "utf8")))
+        );
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(owner.publicKey, LAMPORTS_PER_SOL)
+        );
+        const id = new BN(1);
+        const pda = await createExpense(owner, id, {amount: new BN(10), merchantName: "Cafe Shop ".repeat(3)});
+        const sizeBefore = (await provider.connection.getAccountInfo(pda)).data.length;
+        const errorCode = (name: string) =>
+            program.idl.errors.find((error) => error.name.toLowerCase() === name.toLowerCase()).code;
+
+        const txSignature = await program.methods
+            // The shorter name shrinks the account, the larger amount overflows the total
+            .modifyExpensesBatch([{id, merchantName: "Cafe", amount: new BN(11)}])
+            .accounts({budget: null, authority: owner.publicKey})
+            .remainingAccounts(
+                [pda, categoryIndexPda(owner.publicKey, {food: {}})]
+                    .map(pubkey => ({pubkey, isWritable: true, isSigner: false}))
+            )
+            .signers([owner])
+            .rpc();
+
+        const results = (await getEvents(txSignature))
+            .filter(event => event.name === "modifyResult")
+            .map(event => [event.data.id.toNumber(), event.data.success, event.data.errorCode]);
+        assert.deepEqual(results, [[1, false, errorCode("ArithmeticOverflow")]], "The update should fail");
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, sizeBefore, "The expense shouldn't shrink");
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.merchantName, "Cafe Shop ".repeat(3), "The expense should still deserialize unchanged");
+        assert.isTrue(expenseAccount.amount.eqn(10), "The failed update should be skipped");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(owner.publicKey, {food: {}}));
+        assert.isTrue(categoryIndex.total.eqn(10), "The failed update shouldn't reach the category index");
+    });
+
+    it("Scopes expense IDs to their category", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const scopedPda = (category: object) => {
+            const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [
+                    anchor.utils.bytes.utf8.encode("expense"),
+                    otherUser.publicKey.toBuffer(),
+                    Buffer.from([expenseCategories.indexOf(Object.keys(category)[0])]),
+                    id.toBuffer("le", 8),
+                ],
+                program.programId
+            );
+            return pda;
+        };
+        const createScoped = async (category: object, merchantName: string) =>
+            program.methods
+                .initializeExpenseScoped(id, merchantName, new BN(10), category)
+                .accounts({
+                    expenseAccount: scopedPda(category),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, category),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        await createScoped({food: {}}, "Bakery");
+        await createScoped({transport: {}}, "Taxi");
+        // The flat ID space is separate as well
+        const flatPda = await createExpense(otherUser, id);
+
+        const food = scopedPda({food: {}});
+        const transport = scopedPda({transport: {}});
+        assert.isFalse(food.equals(transport), "The same ID should map to a PDA per category");
+        assert.isFalse(food.equals(flatPda), "The scoped PDA should differ from the flat one");
+        const foodExpense = await program.account.expenseAccount.fetch(food);
+        assert.equal(foodExpense.merchantName, "Bakery");
+        assert.deepEqual(foodExpense.category, {food: {}});
+        const transportExpense = await program.account.expenseAccount.fetch(transport);
+        assert.equal(transportExpense.merchantName, "Taxi");
+        assert.deepEqual(transportExpense.category, {transport: {}});
+        assert.isTrue(foodExpense.id.eq(transportExpense.id), "Both expenses should share the ID");
+        for (const category of [{food: {}}, {transport: {}}]) {
+            const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, category));
+            // The flat expense is filed under food as well
+            const expected = "food" in category ? 2 : 1;
+            assert.isTrue(categoryIndex.count.eqn(expected), "The scoped expenses should be in the index of their category");
+        }
+
+        // The scope stays in the seeds when the category changes
+        await program.methods
+            .modifyExpense(id, "Bakery", new BN(25), {entertainment: {}}, {card: {}})
+            .accounts({
+                expenseAccount: food,
+                userStats: statsPda(otherUser.publicKey),
+                categoryIndex: categoryIndexPda(otherUser.publicKey, {food: {}}),
+                newCategoryIndex: categoryIndexPda(otherUser.publicKey, {entertainment: {}}),
+                budget: null,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+        const modified = await program.account.expenseAccount.fetch(food);
+        assert.isTrue(modified.amount.eqn(25), "The scoped expense should be modified");
+        assert.deepEqual(modified.category, {entertainment: {}}, "The category should change");
+        assert.equal(modified.scope, expenseCategories.indexOf("food"), "The scope should be kept");
+
+        await program.methods
+            .deleteExpense(id, "No longer needed")
+            .accounts({
+                expenseAccount: food,
+                userStats: statsPda(otherUser.publicKey),
+                categoryIndex: categoryIndexPda(otherUser.publicKey, {entertainment: {}}),
+                rentDestination: otherUser.publicKey,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+        assert.isNull(await provider.connection.getAccountInfo(food), "The scoped expense should be deleted");
+        assert.isNotNull(await provider.connection.getAccountInfo(transport), "The other scope should be kept");
+        assert.isNotNull(await provider.connection.getAccountInfo(flatPda), "The flat expense should be kept");
+        const userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.expenseCount.eqn(2), "The deleted expense should leave the stats");
+        assert.isTrue(userStats.totalSpent.eqn(20), "The modified amount should leave the total");
+    });
+
+    it("Stores the tax rate and the derived tax amount", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {amount: new BN(2_000)});
+        const setTaxRate = (taxRateBps: number) =>
+            program.methods
+                .setTaxRate(id, taxRateBps)
+                .accounts({expenseAccount: pda, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        let expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.taxRateBps, 0, "New expenses should have no tax");
+        assert.isTrue(expenseAccount.taxAmount.eqn(0), "0% should mean no tax");
+
+        // 8.25%
+        await setTaxRate(825);
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.taxRateBps, 825, "The tax rate should be stored");
+        assert.isTrue(expenseAccount.taxAmount.eqn(165), "The tax amount should be derived");
+
+        // The tax amount follows the amount
+        await program.methods
+            .updateAmount(id, new BN(1_000))
+            .accounts({expenseAccount: pda, categoryIndex: await categoryIndexOf(pda), budget: null, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.taxAmount.eqn(82), "The tax amount should be rounded down");
+
+        await expectError(setTaxRate(10_001), "InvalidTaxRate");
+    });
+
+    it("Pins the exchange rate of an expense from an oracle account", async () => {
+        // Loaded into the validator from `tests/fixtures/mock-oracle.json`: a price of
+        // 108_250_000 * 10^-8 published at 1_700_000_000, owned by a mock oracle program
+        const oracle = new anchor.web3.PublicKey("p2Yicb86aZig616Eav2VWG9vuXR5mEqhtzshZYBxzsV");
+        const oracleProgram = new anchor.web3.PublicKey("k7FaK87WHGVXzkaoHb7CdVPgkKDQhZ29VLDeBVbDfYn");
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const eur = Array.from(Buffer.from("EUR"));
+        const gbp = Array.from(Buffer.from("GBP"));
+        const pda = await createExpense(otherUser, id, {amount: new BN(1000), currency: eur});
+        const gbpPda = await createExpense(otherUser, new BN(2), {amount: new BN(1000), currency: gbp});
+        const fxFeedPda = (currency: number[]) => {
+            const [feed] = anchor.web3.PublicKey.findProgramAddressSync(
+                [anchor.utils.bytes.utf8.encode("fx_feed"), Buffer.from(currency), Buffer.from("USD")],
+                program.programId
+            );
+            return feed;
+        };
+        const pinFxRate = async (oracleAccount: anchor.web3.PublicKey, expenseId = id, currency = eur) =>
+            program.methods
+                .pinFxRate(expenseId)
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, expenseId),
+                    userStats: statsPda(otherUser.publicKey),
+                    categoryIndex: await currentCategoryIndex(otherUser.publicKey, expenseId),
+                    oracle: oracleAccount,
+                    fxFeed: fxFeedPda(currency),
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+        const setFxFeed = (currency: number[], oracleAccount: anchor.web3.PublicKey) =>
+            program.methods
+                .setFxFeed(currency, oracleAccount)
+                .accounts({admin: provider.wallet.publicKey})
+                .rpc();
+
+        // The mock price is the one of EUR, another key feeds GBP
+        await expectError(
+            program.methods
+                .setFxFeed(eur, oracle)
+                .accounts({admin: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc(),
+            "Unauthorized"
+        );
+        await setFxFeed(eur, oracle);
+        await setFxFeed(gbp, anchor.web3.Keypair.generate().publicKey);
+        const setOracle = (oracleProgramId: anchor.web3.PublicKey, maxOracleAge: number) =>
+            program.methods
+                .setOracle(oracleProgramId, maxOracleAge)
+                .accounts({admin: provider.wallet.publicKey})
+                .rpc();
+
+        await expectError(pinFxRate(anchor.web3.Keypair.generate().publicKey), "OracleNotConfigured");
+        await expectError(pinFxRate(oracle), "InvalidOracleAccount");
+
+        await setOracle(oracleProgram, 60);
+        try {
+            await expectError(pinFxRate(oracle), "OracleStale");
+            await expectError(pinFxRate(pda), "InvalidOracleAccount");
+
+            await setOracle(oracleProgram, 4_294_967_295);
+            // The price of another currency pair isn't taken as the rate
+            await expectError(pinFxRate(oracle, new BN(2), gbp), "OracleFeedMismatch");
+            await pinFxRate(oracle);
+        } finally {
+            // The config is shared by every test, no oracle is configured by default
+            await setOracle(anchor.web3.PublicKey.default, 60);
+        }
+
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.isTrue(expenseAccount.fxRate.eqn(1_082_500), "The rate should come from the oracle");
+        assert.isTrue(expenseAccount.amountInBase.eqn(1082), "The amount should be converted again");
+        const gbpExpense = await program.account.expenseAccount.fetch(gbpPda);
+        assert.isTrue(gbpExpense.fxRate.eqn(1_000_000), "The mismatched rate shouldn't be pinned");
+        const userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.totalSpent.eqn(2082), "The stats should add up the converted amount");
+    });
+
+    it("Rejects growing an expense beyond the maximum account size", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const pda = await createExpense(otherUser, id, {merchantName: "M".repeat(64), note: "N".repeat(128)});
+        const accounts = {expenseAccount: pda, authority: otherUser.publicKey};
+        const addViewer = () =>
+            program.methods
+                .addViewer(id, anchor.web3.Keypair.generate().publicKey)
+                .accounts(accounts)
+                .signers([otherUser])
+                .rpc();
+
+        // 648 bytes, plus 88 per metadata entry and 20 per tag at their limits
+        for (let i = 0; i < 8; i++) {
+            await program.methods
+                .setMetadata(id, `key-${i}` + "k".repeat(11), "v".repeat(64))
+                .accounts(accounts)
+                .signers([otherUser])
+                .rpc();
+        }
+        for (let i = 0; i < 7; i++) {
+            await program.methods
+                .addTag(id, `tag-${i}` + "t".repeat(11))
+                .accounts(accounts)
+                .signers([otherUser])
+                .rpc();
+        }
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, 1492);
+
+        // One viewer still fits the 1536 bytes, a second doesn't
+        await addViewer();
+        await expectError(addViewer(), "AccountTooLarge");
+        await expectError(
+            program.methods
+                .setEncryptedNote(id, Buffer.alloc(32, 1), new Array(24).fill(1))
+                .accounts(accounts)
+                .signers([otherUser])
+                .rpc(),
+            "AccountTooLarge"
+        );
+
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.equal(expenseAccount.viewers.length, 1, "The rejected viewer shouldn't be stored");
+        assert.equal((await provider.connection.getAccountInfo(pda)).data.length, 1524);
+    });
+
+    it("Reconciles the user stats with the actual expenses", async () => {
+        const otherUser = await createFundedUser();
+        const formerOwner = await createFundedUser();
+        const own = await createExpense(otherUser, new BN(1), {amount: new BN(10)});
+        const archived = await createExpense(otherUser, new BN(2), {amount: new BN(50)});
+        await program.methods
+            .archiveExpense(new BN(2), true)
+            .accounts({expenseAccount: archived, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        const transferred = await createExpense(formerOwner, new BN(1), {amount: new BN(30)});
+        await program.methods
+            .transferOwnership(new BN(1), otherUser.publicKey)
+            .accounts({
+                expenseAccount: transferred,
+                authority: formerOwner.publicKey,
+                ...(await ownerCategoryIndices(transferred, otherUser.publicKey)),
+            })
+            .signers([formerOwner])
+            .rpc();
+        // Deleting the largest expense leaves the maximum stale
+        await createExpense(otherUser, new BN(3), {amount: new BN(80)});
+        await deleteExpense(otherUser, new BN(3));
+        const reconcileStats = (authority: anchor.web3.Keypair, expenses: anchor.web3.PublicKey[]) =>
+            program.methods
+                .reconcileStats()
+                .accounts({authority: authority.publicKey})
+                .remainingAccounts(expenses.map((pubkey) => ({pubkey, isSigner: false, isWritable: false})))
+                .signers([authority])
+                .rpc();
+
+        let userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.totalSpent.eqn(40), "The transfer should move the expense to the new owner");
+        assert.isTrue(userStats.largestAmountStale, "The maximum should be stale after the deletion");
+        const formerStats = await program.account.userStats.fetch(statsPda(formerOwner.publicKey));
+        assert.isTrue(formerStats.expenseCount.eqn(0), "The former owner should no longer count it");
+
+        await expectError(reconcileStats(otherUser, [own, own]), "InvalidExpenseAccount");
+        await expectError(reconcileStats(formerOwner, [transferred]), "Unauthorized");
+        // Leaving out an expense would lower the total
+        await expectError(reconcileStats(otherUser, [own, archived]), "IncompleteExpenseSet");
+        userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.totalSpent.eqn(40), "An incomplete set shouldn't change the total");
+
+        await reconcileStats(otherUser, [own, archived, transferred]);
+        userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.totalSpent.eqn(40), "The total should add up the owned expenses");
+        assert.isTrue(userStats.expenseCount.eqn(2), "The count should leave out the archived expense");
+        assert.isTrue(userStats.largestAmount.eqn(30), "The largest expense should be rescanned");
+        assert.isFalse(userStats.largestAmountStale, "The rescanned maximum shouldn't be stale");
+
+        // The stats loaded from `tests/fixtures/drifted-stats.json` count 5 expenses totalling
+        // 7, though the owner has none
+        const driftedOwner = anchor.web3.Keypair.fromSecretKey(
+            Uint8Array.from(JSON.parse(fs.readFileSync("tests/fixtures/drifted-stats-owner.json", "utf8")))
+        );
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(driftedOwner.publicKey, LAMPORTS_PER_SOL)
+        );
+        const first = await createExpense(driftedOwner, new BN(1), {amount: new BN(10)});
+        const second = await createExpense(driftedOwner, new BN(2), {amount: new BN(20)});
+        userStats = await program.account.userStats.fetch(statsPda(driftedOwner.publicKey));
+        assert.isTrue(userStats.expenseCount.eqn(7), "The fixture should have drifted");
+        assert.isTrue(userStats.accountCount.eqn(2), "Only the created accounts should be counted");
+
+        await expectError(reconcileStats(driftedOwner, [first]), "IncompleteExpenseSet");
+        await reconcileStats(driftedOwner, [first, second]);
+        userStats = await program.account.userStats.fetch(statsPda(driftedOwner.publicKey));
+        assert.isTrue(userStats.expenseCount.eqn(2), "The drifted count should be recomputed");
+        assert.isTrue(userStats.totalSpent.eqn(30), "The drifted total should be recomputed");
+        assert.isTrue(userStats.largestAmount.eqn(20), "The largest expense should be rescanned");
+    });
+
+    it("Rejects expenses below the minimum amount of the user", async () => {
+        const otherUser = await createFundedUser();
+        await createExpense(otherUser, new BN(1), {amount: new BN(1)});
+        await program.methods
+            .setMinAmount(new BN(100))
+            .accounts({authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+
+        await expectError(createExpense(otherUser, new BN(2), {amount: new BN(99)}), "AmountBelowMinimum");
+        await createExpense(otherUser, new BN(2), {amount: new BN(100)});
+        await createExpense(otherUser, new BN(3), {amount: new BN(250)});
+
+        // The other instructions creating expenses are held to the minimum as well
+        await expectError(
+            program.methods
+                .initializeTransfer(new BN(4), "Savings", new BN(99))
+                .accounts({
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc(),
+            "AmountBelowMinimum"
+        );
+        const templateId = new BN(1);
+        const [templatePda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("recurring"), otherUser.publicKey.toBuffer(), templateId.toBuffer("le", 8)],
+            program.programId
+        );
+        await program.methods
+            .createRecurring(templateId, "Streaming", new BN(99), new BN(30 * 24 * 60 * 60))
+            .accounts({template: templatePda, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        await expectError(
+            program.methods
+                .instantiateFromTemplate(templateId, new BN(4))
+                .accounts({
+                    template: templatePda,
+                    expenseAccount: expensePda(otherUser.publicKey, new BN(4)),
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc(),
+            "AmountBelowMinimum"
+        );
+        const mint = await createMint(provider.connection, otherUser, otherUser.publicKey, null, 0);
+        const source = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, mint, otherUser.publicKey);
+        const destination = await getOrCreateAssociatedTokenAccount(
+            provider.connection,
+            otherUser,
+            mint,
+            anchor.web3.Keypair.generate().publicKey
+        );
+        await mintTo(provider.connection, otherUser, mint, source.address, otherUser, 1_000);
+        await expectError(
+            program.methods
+                .payExpense(new BN(4), "Book Store", new BN(99))
+                .accounts({
+                    expenseAccount: expensePda(otherUser.publicKey, new BN(4)),
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: budgetPda(otherUser.publicKey),
+                    mint,
+                    source: source.address,
+                    destination: destination.address,
+                    authority: otherUser.publicKey,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .signers([otherUser])
+                .rpc(),
+            "AmountBelowMinimum"
+        );
+
+        const userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.minAmount.eqn(100), "The minimum should be stored");
+        assert.isTrue(userStats.expenseCount.eqn(3), "The rejected expense shouldn't be counted");
+    });
+
+    it("Merges two expenses into one", async () => {
+        const otherUser = await createFundedUser();
+        const newOwner = await createFundedUser();
+        const kept = await createExpense(otherUser, new BN(1), {amount: new BN(10), note: "Lunch"});
+        const removed = await createExpense(otherUser, new BN(2), {amount: new BN(15)});
+        const transferred = await createExpense(otherUser, new BN(3), {amount: new BN(20)});
+        await program.methods
+            .transferOwnership(new BN(3), newOwner.publicKey)
+            .accounts({
+                expenseAccount: transferred,
+                authority: otherUser.publicKey,
+                ...(await ownerCategoryIndices(transferred, newOwner.publicKey)),
+            })
+            .signers([otherUser])
+            .rpc();
+        const mergeExpenses = async (keepId: number, removeId: number) =>
+            program.methods
+                .mergeExpenses(new BN(keepId), new BN(removeId))
+                .accounts({
+                    keptExpense: expensePda(otherUser.publicKey, new BN(keepId)),
+                    removedExpense: expensePda(otherUser.publicKey, new BN(removeId)),
+                    keptCategoryIndex: await currentCategoryIndex(otherUser.publicKey, new BN(keepId)),
+                    removedCategoryIndex: await currentCategoryIndex(otherUser.publicKey, new BN(removeId)),
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+
+        await expectError(mergeExpenses(1, 1), "MergeIntoItself");
+        // The third expense lives at an address of the user but belongs to someone else now
+        await expectError(mergeExpenses(1, 3), "Unauthorized");
+
+        const balanceBefore = await provider.connection.getBalance(otherUser.publicKey);
+        const removedRent = await provider.connection.getBalance(removed);
+        const events = await getEvents(await mergeExpenses(1, 2));
+
+        const expenseAccount = await program.account.expenseAccount.fetch(kept);
+        assert.isTrue(expenseAccount.amount.eqn(25), "The kept expense should have the sum");
+        assert.equal(expenseAccount.note, "Lunch; Merged expense 2", "The merge should be noted");
+        assert.isNull(await provider.connection.getAccountInfo(removed), "The removed expense should be closed");
+        assert.isAbove(
+            await provider.connection.getBalance(otherUser.publicKey),
+            balanceBefore + removedRent - 100_000,
+            "The rent of the removed expense should go back to the owner"
+        );
+        const deleted = events.find((event) => event.name === "expenseDeleted");
+        assert.equal(deleted.data.reason, "Merged into expense 1");
+
+        const userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.expenseCount.eqn(2), "The merged expense should no longer be counted");
+        assert.isTrue(userStats.totalSpent.eqn(45), "The total shouldn't change by merging");
+    });
+
+    it("Resets the budget on the boundary of a custom period", async () => {
+        const otherUser = await createFundedUser();
+        const budget = budgetPda(otherUser.publicKey);
+        const setBudget = (periodStartDay: number, periodLengthDays: number) =>
+            program.methods
+                .setBudget(new BN(100), new BN(0), new BN(0), new BN(0), anchor.web3.PublicKey.default, periodStartDay, periodLengthDays)
+                .accounts({budget, authority: otherUser.publicKey})
+                .signers([otherUser])
+                .rpc();
+
+        await expectError(setBudget(0, 0), "InvalidPeriodStartDay");
+        await expectError(setBudget(29, 0), "InvalidPeriodStartDay");
+
+        await setBudget(1, 0);
+        await createExpense(otherUser, new BN(1), {amount: new BN(100), budget});
+        await expectError(createExpense(otherUser, new BN(2), {amount: new BN(1), budget}), "BudgetExceeded");
+
+        // Moving to weekly periods starting on the 15th puts today in a new period
+        await setBudget(15, 7);
+        const pda = await createExpense(otherUser, new BN(2), {amount: new BN(70), budget});
+        const createdAt = (await program.account.expenseAccount.fetch(pda)).createdAt.toNumber();
+        const budgetAccount = await program.account.budget.fetch(budget);
+        assert.isTrue(budgetAccount.spentThisPeriod.eqn(70), "The spending should restart in the new period");
+        assert.equal(
+            budgetAccount.period,
+            Math.floor(Math.floor((createdAt - 14 * 86_400) / 86_400) / 7),
+            "The period should be counted in weeks from the 15th"
+        );
+        assert.equal(budgetAccount.periodStartDay, 15);
+        assert.equal(budgetAccount.periodLengthDays, 7);
+
+        // Within the same period the limit still applies
+        await expectError(createExpense(otherUser, new BN(3), {amount: new BN(31), budget}), "BudgetExceeded");
+    });
+
+    it("Validates an expense without creating it", async () => {
+        const otherUser = await createFundedUser();
+        const budget = budgetPda(otherUser.publicKey);
+        await program.methods
+            .setBudget(new BN(100), new BN(0), new BN(0), new BN(0), anchor.web3.PublicKey.default, 1, 0)
+            .accounts({budget, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        // Compared case-insensitively, the client may camelCase the names of the IDL
+        const errorCode = (name: string) =>
+            program.idl.errors.find((error) => error.name.toLowerCase() === name.toLowerCase()).code;
+        const validateExpense = async (id: number, merchantName: string, amount: number) => {
+            const events = await getEvents(
+                await program.methods
+                    .validateExpense(new BN(id), merchantName, new BN(amount), {food: {}})
+                    .accounts({budget, authority: otherUser.publicKey})
+                    .signers([otherUser])
+                    .rpc()
+            );
+            return events.find((event) => event.name === "validationResult").data;
+        };
+
+        // A first expense, before the user stats exist
+        let result = await validateExpense(1, "Cafe Shop", 60);
+        assert.isTrue(result.ok, "A valid expense should pass");
+        assert.equal(result.errorCode, 0);
+        assert.isNull(
+            await provider.connection.getAccountInfo(expensePda(otherUser.publicKey, new BN(1))),
+            "The dry run shouldn't create the expense"
+        );
+        await createExpense(otherUser, new BN(1), {amount: new BN(60), budget});
+
+        const failures: [number, string, number, string][] = [
+            [2, "", 10, "MerchantNameEmpty"],
+            [2, "Cafe Shop", 41, "BudgetExceeded"],
+        ];
+        for (const [id, merchantName, amount, code] of failures) {
+            result = await validateExpense(id, merchantName, amount);
+            assert.isFalse(result.ok, `The dry run should report ${code}`);
+            assert.equal(result.errorCode, errorCode(code), `The dry run should report ${code}`);
+            await expectError(createExpense(otherUser, new BN(id), {merchantName, amount: new BN(amount), budget}), code);
+        }
+
+        result = await validateExpense(1, "Cafe Shop", 10);
+        assert.equal(result.errorCode, errorCode("IdInUse"), "The dry run should report the ID in use");
+
+        result = await validateExpense(2, "Cafe Shop", 40);
+        assert.isTrue(result.ok, "An expense within the budget should pass");
+        await createExpense(otherUser, new BN(2), {amount: new BN(40), budget});
+    });
+
+    it("Leaves transfers between own accounts out of the totals", async () => {
+        const otherUser = await createFundedUser();
+        const budget = budgetPda(otherUser.publicKey);
+        await program.methods
+            .setBudget(new BN(100), new BN(0), new BN(0), new BN(0), anchor.web3.PublicKey.default, 1, 0)
+            .accounts({budget, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        await createExpense(otherUser, new BN(1), {amount: new BN(60), budget});
+
+        // Above what is left of the budget, but not spending
+        await program.methods
+            .initializeTransfer(new BN(2), "Savings", new BN(500))
+            .accounts({
+                rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+        const transfer = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, new BN(2)));
+        assert.isTrue(transfer.isTransfer, "The expense should be flagged as a transfer");
+        assert.isTrue(transfer.amount.eqn(500), "The amount should be recorded");
+        assert.isTrue(transfer.amountInBase.eqn(0), "The transfer shouldn't count in the base currency");
+
+        const userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.expenseCount.eqn(2), "The transfer should be recorded");
+        assert.isTrue(userStats.totalSpent.eqn(60), "The transfer shouldn't count as spending");
+        const budgetAccount = await program.account.budget.fetch(budget);
+        assert.isTrue(budgetAccount.spentThisPeriod.eqn(60), "The transfer shouldn't count toward the budget");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, {other: {}}));
+        assert.isTrue(categoryIndex.count.eqn(1), "The transfer should be in the index of its category");
+        assert.isTrue(categoryIndex.total.eqn(0), "The transfer shouldn't count in the index total");
+
+        await createExpense(otherUser, new BN(3), {amount: new BN(40), budget});
+    });
+
+    it("Keeps the expenses of tenants apart", async () => {
+        const otherUser = await createFundedUser();
+        const id = new BN(1);
+        const tenantPda = (tenant: number[]) => {
+            const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+                [
+                    anchor.utils.bytes.utf8.encode("expense"),
+                    Buffer.from(tenant),
+                    otherUser.publicKey.toBuffer(),
+                    id.toBuffer("le", 8),
+                ],
+                program.programId
+            );
+            return pda;
+        };
+        const createForTenant = async (tenant: number[], merchantName: string) =>
+            program.methods
+                .initializeExpenseTenant(tenant, id, merchantName, new BN(10))
+                .accounts({
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+        const acme = Array.from(Buffer.from("acme\0\0\0\0"));
+        const globex = Array.from(Buffer.from("globex\0\0"));
+
+        await createForTenant(acme, "Bakery");
+        await createForTenant(globex, "Taxi");
+        const flatPda = await createExpense(otherUser, id);
+
+        assert.isFalse(tenantPda(acme).equals(tenantPda(globex)), "The same ID should map to a PDA per tenant");
+        assert.isFalse(tenantPda(acme).equals(flatPda), "The tenant PDA should differ from the flat one");
+        const acmeExpense = await program.account.expenseAccount.fetch(tenantPda(acme));
+        assert.equal(acmeExpense.merchantName, "Bakery");
+        assert.deepEqual(acmeExpense.tenant, acme, "The tenant should be stored");
+        const globexExpense = await program.account.expenseAccount.fetch(tenantPda(globex));
+        assert.equal(globexExpense.merchantName, "Taxi");
+        assert.deepEqual(globexExpense.tenant, globex, "The tenant should be stored");
+        const flatExpense = await program.account.expenseAccount.fetch(flatPda);
+        assert.deepEqual(flatExpense.tenant, new Array(8).fill(0), "Other expenses should have no tenant");
+        const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, {other: {}}));
+        assert.isTrue(categoryIndex.count.eqn(2), "Both tenant expenses should be in the index of their category");
+
+        // Zeros stand for no tenant
+        await expectError(createForTenant(new Array(8).fill(0), "Cafe"), "InvalidTenant");
+
+        await program.methods
+            .modifyExpense(id, "Bakery", new BN(25), {food: {}}, {card: {}})
+            .accounts({
+                expenseAccount: tenantPda(acme),
+                userStats: statsPda(otherUser.publicKey),
+                categoryIndex: categoryIndexPda(otherUser.publicKey, {other: {}}),
+                newCategoryIndex: categoryIndexPda(otherUser.publicKey, {food: {}}),
+                budget: null,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+        const modified = await program.account.expenseAccount.fetch(tenantPda(acme));
+        assert.isTrue(modified.amount.eqn(25), "The tenant expense should be modified");
+
+        await program.methods
+            .deleteExpense(id, "No longer needed")
+            .accounts({
+                expenseAccount: tenantPda(acme),
+                userStats: statsPda(otherUser.publicKey),
+                categoryIndex: categoryIndexPda(otherUser.publicKey, {food: {}}),
+                rentDestination: otherUser.publicKey,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+        assert.isNull(await provider.connection.getAccountInfo(tenantPda(acme)), "The tenant expense should be deleted");
+        assert.isNotNull(await provider.connection.getAccountInfo(tenantPda(globex)), "The other tenant should be kept");
+        const userStats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(userStats.expenseCount.eqn(2), "The deleted expense should leave the stats");
+        assert.isTrue(userStats.totalSpent.eqn(20), "The modified amount should leave the total");
+    });
+
+    it("Files auto-created expenses under the default category", async () => {
+        const otherUser = await createFundedUser();
+        const createAuto = async (category: object | null) => {
+            const stats = await program.account.userStats.fetchNullable(statsPda(otherUser.publicKey));
+            const pda = expensePda(otherUser.publicKey, stats ? stats.nextId : new BN(0));
+            const filedUnder = category ?? (stats ? stats.defaultCategory : {other: {}});
+            await program.methods
+                .initializeExpenseAuto("Cafe Shop", new BN(10), category)
+                .accounts({
+                    expenseAccount: pda,
+                    userStats: statsPda(otherUser.publicKey),
+                    rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                    categoryIndex: categoryIndexPda(otherUser.publicKey, filedUnder),
+                    budget: null,
+                    authority: otherUser.publicKey,
+                })
+                .signers([otherUser])
+                .rpc();
+            return (await program.account.expenseAccount.fetch(pda)).category;
+        };
+
+        assert.deepEqual(await createAuto(null), {other: {}}, "Without a default the category should be Other");
+
+        await program.methods
+            .setDefaultCategory({food: {}})
+            .accounts({authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        assert.deepEqual(await createAuto(null), {food: {}}, "The default category should be pre-filled");
+        assert.deepEqual(
+            await createAuto({transport: {}}),
+            {transport: {}},
+            "An explicit category should override the default"
+        );
+        for (const category of [{other: {}}, {food: {}}, {transport: {}}]) {
+            const categoryIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, category));
+            assert.isTrue(categoryIndex.count.eqn(1), "Every expense should be in the index of its category");
+        }
+
+        // Payments and template instances take the default category as well
+        const templateId = new BN(1);
+        const [templatePda] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("recurring"), otherUser.publicKey.toBuffer(), templateId.toBuffer("le", 8)],
+            program.programId
+        );
+        await program.methods
+            .createRecurring(templateId, "Streaming", new BN(10), new BN(30 * 24 * 60 * 60))
+            .accounts({template: templatePda, authority: otherUser.publicKey})
+            .signers([otherUser])
+            .rpc();
+        await program.methods
+            .instantiateFromTemplate(templateId, new BN(10))
+            .accounts({
+                template: templatePda,
+                expenseAccount: expensePda(otherUser.publicKey, new BN(10)),
+                userStats: statsPda(otherUser.publicKey),
+                rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                categoryIndex: categoryIndexPda(otherUser.publicKey, {food: {}}),
+                budget: null,
+                authority: otherUser.publicKey,
+            })
+            .signers([otherUser])
+            .rpc();
+        const mint = await createMint(provider.connection, otherUser, otherUser.publicKey, null, 0);
+        const source = await getOrCreateAssociatedTokenAccount(provider.connection, otherUser, mint, otherUser.publicKey);
+        const destination = await getOrCreateAssociatedTokenAccount(
+            provider.connection,
+            otherUser,
+            mint,
+            anchor.web3.Keypair.generate().publicKey
+        );
+        await mintTo(provider.connection, otherUser, mint, source.address, otherUser, 1_000);
+        await program.methods
+            .payExpense(new BN(11), "Book Store", new BN(10))
+            .accounts({
+                expenseAccount: expensePda(otherUser.publicKey, new BN(11)),
+                userStats: statsPda(otherUser.publicKey),
+                rollup: rollupPda(otherUser.publicKey, await currentPeriod()),
+                categoryIndex: categoryIndexPda(otherUser.publicKey, {food: {}}),
+                budget: budgetPda(otherUser.publicKey),
+                mint,
+                source: source.address,
+                destination: destination.address,
+                authority: otherUser.publicKey,
+                tokenProgram: TOKEN_PROGRAM_ID,
+            })
+            .signers([otherUser])
+            .rpc();
+        for (const id of [new BN(10), new BN(11)]) {
+            const expenseAccount = await program.account.expenseAccount.fetch(expensePda(otherUser.publicKey, id));
+            assert.deepEqual(expenseAccount.category, {food: {}}, "The default category should be taken");
+        }
+        const foodIndex = await program.account.categoryIndex.fetch(categoryIndexPda(otherUser.publicKey, {food: {}}));
+        assert.isTrue(foodIndex.count.eqn(3), "Both should be in the index of the default category");
+    });
+
+    it("Writes informational logs only at the info level", async () => {
+        const otherUser = await createFundedUser();
+        const setLogLevel = (logLevel: object) =>
+            program.methods
+                .setLogLevel(logLevel)
+                .accounts({admin: provider.wallet.publicKey})
+                .rpc();
+        const creationLogs = async (id: BN) => {
+            const pda = await createExpense(otherUser, id);
+            const [{signature}] = await provider.connection.getSignaturesForAddress(pda, {}, "confirmed");
+            const tx = await provider.connection.getTransaction(signature, {
+                commitment: "confirmed",
+                maxSupportedTransactionVersion: 0,
+            });
+            return tx.meta.logMessages.join("\n");
+        };
+
+        assert.notInclude(await creationLogs(new BN(1)), "Initialize Expense id", "The default level should be quiet");
+
+        await setLogLevel({info: {}});
+        try {
+            assert.include(await creationLogs(new BN(2)), "Initialize Expense id: 2", "The info level should log");
+        } finally {
+            // The config is shared by every test, quiet by default
+            await setLogLevel({error: {}});
+        }
+    });
+
+    it("Rejects a retried submission with the same client reference", async () => {
+        const otherUser = await createFundedUser();
+        const clientRef = Array.from(anchor.web3.Keypair.generate().publicKey.toBytes().slice(0, 16));
+        const [submissionGuard] = anchor.web3.PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("submission"), otherUser.publicKey.toBuffer(), Buffer.from(clientRef)],
+            program.programId
+        );
+
+        await expectError(createExpense(otherUser, new BN(1), {clientRef}), "SubmissionGuardMissing");
+
+        const pda = await createExpense(otherUser, new BN(1), {clientRef, submissionGuard});
+        const expenseAccount = await program.account.expenseAccount.fetch(pda);
+        assert.deepEqual(expenseAccount.clientRef, clientRef, "The client reference should be stored");
+        const guard = await program.account.submissionGuard.fetch(submissionGuard);
+        assert.isTrue(guard.expense.equals(pda), "The guard should point to the expense");
+
+        // A retry that picked another ID is still caught by the reference
+        await expectError(createExpense(otherUser, new BN(2), {clientRef, submissionGuard}), "DuplicateSubmission");
+        const stats = await program.account.userStats.fetch(statsPda(otherUser.publicKey));
+        assert.isTrue(stats.expenseCount.eqn(1), "The retry shouldn't create an expense");
+
+        // Expenses without a reference don't need a guard
+        await createExpense(otherUser, new BN(2));
+    });
 });